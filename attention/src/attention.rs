@@ -1,10 +1,7 @@
-use num_traits::Zero;
-
-use autodiff::node::Node;
 use config::Config;
-use interfaces::deep_learning::{DLModule, LinearLayer};
-use interfaces::tensors::{RealElement, RealTensor, Tensor};
-use neural_nets::lin_layer::LinLayer;
+use interfaces::deep_learning::{prefix_named_parameters, prefix_paths, DLModule, LinearLayer};
+use interfaces::tensors::{AsStdError, RealElement, RealTensor, Tensor};
+use neural_nets::{initializer::derive_seed, lin_layer::LinLayer};
 use tensors::TensorImpl;
 
 use std::marker::PhantomData;
@@ -39,12 +36,20 @@ where
     pub _marker_e: PhantomData<E>,
 }
 
-pub type El = Node<f64>;
+/// The element type used by the concrete modules. The `sync` feature swaps the single-threaded
+/// `Node` for the thread-safe `SyncNode`, the `tape` feature for the tape-based `Var`. With both
+/// features, `tape` takes precedence.
+#[cfg(not(any(feature = "sync", feature = "tape")))]
+pub type El = autodiff::node::Node<f64>;
+#[cfg(all(feature = "sync", not(feature = "tape")))]
+pub type El = autodiff::node::SyncNode<f64>;
+#[cfg(feature = "tape")]
+pub type El = autodiff::tape::Var;
 pub type Te = TensorImpl<El>;
 pub type La = LinLayer<Te, El>;
 pub type Mal = MultiHeadAttention<Te, El, La>;
 
-impl MultiHeadAttention<Te, El, La> {
+impl<E: RealElement> MultiHeadAttention<TensorImpl<E>, E, LinLayer<TensorImpl<E>, E>> {
     pub fn new(config: &Config, is_masked: bool) -> Self {
         // Generate weights tensors W_Q, W_K, W_V with shapes (embedding_dim, d_k),
         // where d_k is embedding_dim / num_heads. For now, we assume num_heads = 1.
@@ -54,20 +59,20 @@ impl MultiHeadAttention<Te, El, La> {
         let seq_len = config.seq_len;
         // let batch_size = config.batch_size;
         let d_k = config.embed_dim / config.num_head;
-        let mask: Option<Te> = if is_masked {
-            let mut mask: Vec<Node<f64>> = vec![Node::<f64>::zero(); seq_len * seq_len];
+        let mask: Option<TensorImpl<E>> = if is_masked {
+            let mut mask: Vec<E> = vec![E::zero(); seq_len * seq_len];
             let matrix_dim = seq_len;
             for j in 0..matrix_dim {
                 for k in 0..matrix_dim {
                     if k >= j {
-                        mask[j * matrix_dim + k] = Node::<f64>::zero();
+                        mask[j * matrix_dim + k] = E::zero();
                     } else {
-                        mask[j * matrix_dim + k] = Node::<f64>::neg_inf()
+                        mask[j * matrix_dim + k] = E::neg_inf()
                     }
                 }
             }
-            // Some(Te::from_vec(&vec![batch_size, seq_len, seq_len], &mask).unwrap())
-            Some(Te::from_vec(&vec![seq_len, seq_len], &mask).unwrap())
+            // Some(TensorImpl::from_vec(&vec![batch_size, seq_len, seq_len], &mask).unwrap())
+            Some(TensorImpl::from_vec(&vec![seq_len, seq_len], &mask).unwrap())
         } else {
             None
         };
@@ -77,15 +82,18 @@ impl MultiHeadAttention<Te, El, La> {
 
         // Q (B, T, C) * Q_W (B, C, T) = (B, T, T)
         // Q.matmaul(Q_W) : (B x T x C) x (C x T) -> (B x T x T)
-        let query_weights: Vec<La> = (0..num_heads)
-            .map(|_| La::new(embed_dim, d_k, config.seed))
-            .collect();
-        let value_weights: Vec<La> = (0..num_heads)
-            .map(|_| La::new(embed_dim, d_k, config.seed))
-            .collect();
-        let key_weights: Vec<La> = (0..num_heads)
-            .map(|_| La::new(embed_dim, d_k, config.seed))
-            .collect();
+        // Each projection of each head has its own seed, derived from its name (eg. `query.0`).
+        let projections = |name: &str| -> Vec<LinLayer<TensorImpl<E>, E>> {
+            (0..num_heads)
+                .map(|h| {
+                    let seed = derive_seed(config.seed, &format!("{}.{}", name, h));
+                    LinLayer::new(embed_dim, d_k, seed)
+                })
+                .collect()
+        };
+        let query_weights = projections("query");
+        let value_weights = projections("value");
+        let key_weights = projections("key");
 
         Self {
             query_weights,
@@ -99,6 +107,34 @@ impl MultiHeadAttention<Te, El, La> {
     }
 }
 
+impl<T, E, L> MultiHeadAttention<T, E, L>
+where
+    L: LinearLayer<T, E>,
+    T: Tensor<E>,
+    E: RealElement,
+{
+    /// Replace each linear layer `l` with `f(l)`, eg. to quantize the module. The layers are
+    /// visited in `params()` order.
+    pub fn try_map_linear_layers<L2, Er>(
+        self,
+        f: &mut impl FnMut(L) -> Result<L2, Er>,
+    ) -> Result<MultiHeadAttention<T, E, L2>, Er>
+    where
+        L2: LinearLayer<T, E>,
+    {
+        let mut map = |layers: Vec<L>| layers.into_iter().map(&mut *f).collect::<Result<_, _>>();
+        Ok(MultiHeadAttention {
+            query_weights: map(self.query_weights)?,
+            key_weights: map(self.key_weights)?,
+            value_weights: map(self.value_weights)?,
+            num_heads: self.num_heads,
+            mask: self.mask,
+            _marker_t: PhantomData,
+            _marker_e: PhantomData,
+        })
+    }
+}
+
 // TODO: consider renaming as `LearnableTransform`
 impl<T, E, L> DLModule<T, E> for MultiHeadAttention<T, E, L>
 where
@@ -150,8 +186,9 @@ where
                 let att: T = if let Some(mask) = &self.mask {
                     println!("Mask shape: {:?}", mask.shape());
                     // println!("Single element tensor: {:?}", single_batch_tensor.shape());
-                    // element-wise multiplication on attention: (T x T)  x (T x T)
-                    let masked_att: T = mask.clone() * att.clone();
+                    // element-wise addition on attention: (T x T) + (T x T), the masked scores
+                    // become -inf and so get a zero weight in the softmax
+                    let masked_att: T = att.clone() + mask.clone();
                     println!("Masked att shape: {:?}", masked_att.shape());
                     masked_att
                 } else {
@@ -197,6 +234,49 @@ where
             .chain(self.value_weights.iter().flat_map(|layer| layer.params()))
             .collect()
     }
+
+    fn param_paths(&self) -> Vec<String> {
+        [
+            ("query", &self.query_weights),
+            ("key", &self.key_weights),
+            ("value", &self.value_weights),
+        ]
+        .into_iter()
+        .flat_map(|(name, layers)| {
+            layers.iter().enumerate().flat_map(move |(head, layer)| {
+                prefix_paths(&format!("{}.{}", name, head), layer.param_paths())
+            })
+        })
+        .collect()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, T)> {
+        [
+            ("query", &self.query_weights),
+            ("key", &self.key_weights),
+            ("value", &self.value_weights),
+        ]
+        .into_iter()
+        .flat_map(|(name, layers)| {
+            layers.iter().enumerate().flat_map(move |(head, layer)| {
+                prefix_named_parameters(&format!("{}.{}", name, head), layer.named_parameters())
+            })
+        })
+        .collect()
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut T>, AsStdError> {
+        let mut params = Vec::new();
+        for layer in self
+            .query_weights
+            .iter_mut()
+            .chain(self.key_weights.iter_mut())
+            .chain(self.value_weights.iter_mut())
+        {
+            params.extend(layer.parameters_mut()?);
+        }
+        Ok(params)
+    }
 }
 
 impl<T, E, L> SelfAttention<T, E> for MultiHeadAttention<T, E, L>
@@ -209,6 +289,8 @@ where
 
 #[cfg(test)]
 mod tests {
+    use num_traits::Zero;
+
     use super::*;
 
     fn get_config() -> Config {
@@ -219,13 +301,18 @@ mod tests {
             embed_dim: 20,
             num_head: 4,
             seed: 0,
+            num_blocks: 1,
+            dropout: 0.0,
+            activation: "relu".to_string(),
+            intermediate_dim: 80,
+            gated_feed_forward: false,
         }
     }
 
     #[test]
     fn test_construct() {
         let config = get_config();
-        let attention = MultiHeadAttention::new(&config, true);
+        let attention: Mal = MultiHeadAttention::new(&config, true);
         assert_eq!(attention.num_heads, 4);
         assert!(attention.mask.is_some());
         // check that mask has the right shape
@@ -237,13 +324,35 @@ mod tests {
         println!("{:?}", attention.key_weights[0].w);
     }
 
+    #[test]
+    fn test_independent_weights() {
+        // Derived seeds: no two projections start out identical, but the same config gives the
+        // same weights.
+        let config = get_config();
+        let attention: MultiHeadAttention<TensorImpl<f64>, f64, LinLayer<TensorImpl<f64>, f64>> =
+            MultiHeadAttention::new(&config, true);
+        let weights: Vec<&TensorImpl<f64>> = attention
+            .query_weights
+            .iter()
+            .chain(attention.key_weights.iter())
+            .chain(attention.value_weights.iter())
+            .map(|layer| &layer.w)
+            .collect();
+        for (idx, w) in weights.iter().enumerate() {
+            assert!(!weights[idx + 1..].contains(w));
+        }
+        let again: MultiHeadAttention<TensorImpl<f64>, f64, LinLayer<TensorImpl<f64>, f64>> =
+            MultiHeadAttention::new(&config, true);
+        assert_eq!(again.key_weights[2].w, attention.key_weights[2].w);
+    }
+
     #[test]
     fn test_forward() {
         let config = get_config();
-        let attention = MultiHeadAttention::new(&config, true);
+        let attention: Mal = MultiHeadAttention::new(&config, true);
         let x = Te::from_vec(
             &vec![config.batch_size, config.seq_len, config.embed_dim],
-            &vec![Node::<f64>::zero(); config.batch_size * config.seq_len * config.embed_dim],
+            &vec![El::zero(); config.batch_size * config.seq_len * config.embed_dim],
         )
         .unwrap();
         let out = attention.forward(&x).unwrap();
@@ -251,4 +360,24 @@ mod tests {
         let actual_shape = out.shape();
         assert_eq!(actual_shape, expected_shape);
     }
+
+    #[test]
+    fn test_masked_forward_is_finite() {
+        // Inputs of both signs give negative attention scores, which the mask must not turn into
+        // NaN.
+        let config = get_config();
+        let attention: Mal = MultiHeadAttention::new(&config, true);
+        let num_elements = config.batch_size * config.seq_len * config.embed_dim;
+        let x = Te::from_vec(
+            &vec![config.batch_size, config.seq_len, config.embed_dim],
+            &(0..num_elements)
+                .map(|i| El::from((i as f64).sin() * 3.))
+                .collect::<Vec<El>>(),
+        )
+        .unwrap();
+        let out: Vec<El> = attention.forward(&x).unwrap().into();
+        assert!(out
+            .into_iter()
+            .all(|value| Into::<f64>::into(value).is_finite()));
+    }
 }
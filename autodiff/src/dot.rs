@@ -0,0 +1,308 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+use interfaces::tensors::{RealElement, Tensor};
+use tensors::TensorImpl;
+
+use crate::graph_ptr::GraphPtr;
+use crate::node::GenericNode;
+
+/// Options for rendering a computation graph in the Graphviz DOT language.
+#[derive(Debug, Default)]
+pub struct DotOptions {
+    /// Collapse every non-leaf node this many edges below the root(s) into a single box that
+    /// summarises its subgraph. `None` draws the whole graph.
+    pub max_depth: Option<usize>,
+    labels: HashMap<usize, String>,
+    collapsed: HashSet<usize>,
+}
+
+impl DotOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach a label to a node, eg. the name of a parameter.
+    pub fn label<T: RealElement, P: GraphPtr>(&mut self, node: &GenericNode<T, P>, label: &str) {
+        self.labels.insert(node.id(), label.to_string());
+    }
+
+    /// Label each node as `prefix[i]`, eg. with the flat vector returned by `DLModule::params()`.
+    pub fn label_all<T: RealElement, P: GraphPtr>(
+        &mut self,
+        nodes: &[GenericNode<T, P>],
+        prefix: &str,
+    ) {
+        for (i, node) in nodes.iter().enumerate() {
+            self.label(node, &format!("{}[{}]", prefix, i));
+        }
+    }
+
+    /// Draw the subgraph below `node` as a single box.
+    pub fn collapse<T: RealElement, P: GraphPtr>(&mut self, node: &GenericNode<T, P>) {
+        self.collapsed.insert(node.id());
+    }
+}
+
+/// Export of a computation graph to the Graphviz DOT language, eg. to inspect where a NaN comes
+/// from. Write the result to a file and render it with `dot -Tsvg graph.dot -o graph.svg`.
+///
+/// Each node shows its op, value and grad; edges point from operands to results. Nodes with a NaN
+/// value or grad are filled red and labelled nodes are filled blue.
+pub trait ToDot {
+    fn to_dot(&self) -> String {
+        self.to_dot_with(&DotOptions::default())
+    }
+
+    fn to_dot_with(&self, options: &DotOptions) -> String;
+}
+
+impl<T: RealElement + From<f64>, P: GraphPtr> ToDot for GenericNode<T, P> {
+    fn to_dot_with(&self, options: &DotOptions) -> String {
+        render(vec![(self.clone(), None)], options)
+    }
+}
+
+impl<T: RealElement + From<f64>, P: GraphPtr> ToDot for TensorImpl<GenericNode<T, P>> {
+    fn to_dot_with(&self, options: &DotOptions) -> String {
+        let shape = self.shape();
+        let roots = self
+            .get_data()
+            .iter()
+            .enumerate()
+            .map(|(flat_idx, node)| {
+                let idxs: Vec<String> = unravel_index(flat_idx, &shape)
+                    .iter()
+                    .map(|idx| idx.to_string())
+                    .collect();
+                (node.clone(), Some(format!("out[{}]", idxs.join(","))))
+            })
+            .collect();
+        render(roots, options)
+    }
+}
+
+fn unravel_index(mut flat_idx: usize, shape: &[usize]) -> Vec<usize> {
+    let mut idxs = vec![0; shape.len()];
+    for (idx, dim) in idxs.iter_mut().zip(shape.iter()).rev() {
+        *idx = flat_idx % dim;
+        flat_idx /= dim;
+    }
+    idxs
+}
+
+/// Role of each operand, shown on the edges of non-commutative ops.
+fn edge_label(op_name: &str, operand: usize) -> Option<&'static str> {
+    match (op_name, operand) {
+        ("Quot", 0) => Some("num"),
+        ("Quot", 1) => Some("denom"),
+        ("Pow", 0) => Some("base"),
+        ("Pow", 1) => Some("exp"),
+        _ => None,
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// NaN is the only value that is not equal to itself.
+#[allow(clippy::eq_op)]
+fn is_nan<T: PartialEq>(x: &T) -> bool {
+    x != x
+}
+
+/// Number of distinct nodes in the subgraph below `node`, excluding `node` itself.
+fn count_below<T: RealElement + From<f64>, P: GraphPtr>(node: &GenericNode<T, P>) -> usize {
+    let mut seen = HashSet::from([node.id()]);
+    let mut stack = node.children();
+    while let Some(node) = stack.pop() {
+        if seen.insert(node.id()) {
+            stack.extend(node.children());
+        }
+    }
+    seen.len() - 1
+}
+
+fn render<T: RealElement + From<f64>, P: GraphPtr>(
+    roots: Vec<(GenericNode<T, P>, Option<String>)>,
+    options: &DotOptions,
+) -> String {
+    let mut dot_ids: HashMap<usize, usize> = HashMap::new();
+    let mut root_labels: HashMap<usize, String> = HashMap::new();
+    let mut queue: VecDeque<(GenericNode<T, P>, usize)> = VecDeque::new();
+    for (node, label) in roots.into_iter() {
+        if let Some(label) = label {
+            root_labels.insert(node.id(), label);
+        }
+        queue.push_back((node, 0));
+    }
+
+    let mut node_lines: Vec<String> = Vec::new();
+    // (operand, result, label), stored as node ids until all dot ids are known.
+    let mut edges: Vec<(usize, usize, Option<&'static str>)> = Vec::new();
+
+    // Breadth-first, so that a node shared by several paths is drawn at its shallowest depth.
+    while let Some((node, depth)) = queue.pop_front() {
+        if dot_ids.contains_key(&node.id()) {
+            continue;
+        }
+        let dot_id = dot_ids.len();
+        dot_ids.insert(node.id(), dot_id);
+
+        let mut label_lines: Vec<String> = Vec::new();
+        if let Some(label) = root_labels.get(&node.id()) {
+            label_lines.push(label.clone());
+        }
+        if let Some(label) = options.labels.get(&node.id()) {
+            label_lines.push(label.clone());
+        }
+        label_lines.push(node.op_name().to_string());
+        let val = node.val();
+        let grad = node.grad();
+        label_lines.push(format!("val: {}", val));
+        match &grad {
+            Some(grad) => label_lines.push(format!("grad: {}", grad)),
+            None => label_lines.push("grad: None".to_string()),
+        }
+
+        let is_collapsed = !node.is_leaf()
+            && (options.collapsed.contains(&node.id())
+                || options
+                    .max_depth
+                    .is_some_and(|max_depth| depth >= max_depth));
+        if is_collapsed {
+            label_lines.push(format!("(+{} nodes)", count_below(&node)));
+        } else {
+            let op_name = node.op_name();
+            for (operand, child) in node.children().into_iter().enumerate() {
+                edges.push((child.id(), node.id(), edge_label(op_name, operand)));
+                queue.push_back((child, depth + 1));
+            }
+        }
+
+        let mut attrs = vec![format!("label=\"{}\"", escape(&label_lines.join("\n")))];
+        if is_nan(&val) || grad.as_ref().is_some_and(is_nan) {
+            attrs.push("style=filled, fillcolor=salmon".to_string());
+        } else if options.labels.contains_key(&node.id()) {
+            attrs.push("style=filled, fillcolor=lightblue".to_string());
+        } else if is_collapsed {
+            attrs.push("style=dashed".to_string());
+        }
+        node_lines.push(format!("    n{} [{}];", dot_id, attrs.join(", ")));
+    }
+
+    let mut dot = String::from("digraph {\n    rankdir=BT;\n    node [shape=box];\n");
+    for line in node_lines.iter() {
+        writeln!(dot, "{}", line).expect("Writing to a String cannot fail.");
+    }
+    for (operand, result, label) in edges.into_iter() {
+        let (operand, result) = (dot_ids[&operand], dot_ids[&result]);
+        match label {
+            Some(label) => writeln!(
+                dot,
+                "    n{} -> n{} [label=\"{}\"];",
+                operand, result, label
+            ),
+            None => writeln!(dot, "    n{} -> n{};", operand, result),
+        }
+        .expect("Writing to a String cannot fail.");
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use interfaces::utils::{Ln, Pow};
+
+    use super::*;
+    use crate::node::Node;
+
+    #[test]
+    fn test_node_to_dot() {
+        let x = Node::new(2.0, None);
+        let y = Node::new(3.0, None);
+        let mut z = x.clone() * y.clone();
+        z.retain_grad();
+        z.backward(1.0);
+
+        let dot = z.to_dot();
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("n0 [label=\"Prod\\nval: 6\\ngrad: 1\"];"));
+        assert!(dot.contains("n1 [label=\"Leaf\\nval: 2\\ngrad: 3\"];"));
+        assert!(dot.contains("n2 [label=\"Leaf\\nval: 3\\ngrad: 2\"];"));
+        assert!(dot.contains("n1 -> n0;"));
+        assert!(dot.contains("n2 -> n0;"));
+    }
+
+    #[test]
+    fn test_shared_node_drawn_once() {
+        let x = Node::new(2.0, None);
+        let z = x.clone() * x.clone();
+        let dot = z.to_dot();
+        assert_eq!(dot.matches("[label=").count(), 2);
+        assert_eq!(dot.matches("n1 -> n0;").count(), 2);
+    }
+
+    #[test]
+    fn test_edge_labels_and_nan() {
+        let x = Node::new(-1.0, None);
+        let z = x.clone().ln().pow(Node::new(2.0, None));
+        let dot = z.to_dot();
+        assert!(dot.contains("[label=\"base\"]"));
+        assert!(dot.contains("[label=\"exp\"]"));
+        // ln(-1) is NaN.
+        assert!(dot.contains("Ln\\nval: NaN\\ngrad: None\", style=filled, fillcolor=salmon"));
+    }
+
+    #[test]
+    fn test_labels() {
+        let params = vec![Node::new(1.0, None), Node::new(2.0, None)];
+        let z = params[0].clone() + params[1].clone();
+        let mut options = DotOptions::new();
+        options.label_all(&params, "w");
+        let dot = z.to_dot_with(&options);
+        assert!(dot.contains("w[0]\\nLeaf"));
+        assert!(dot.contains("w[1]\\nLeaf"));
+        assert_eq!(dot.matches("fillcolor=lightblue").count(), 2);
+    }
+
+    #[test]
+    fn test_collapse() {
+        let x = Node::new(1.0, None);
+        let y = Node::new(2.0, None);
+        let xy = x.clone() * y.clone();
+        let z = xy.clone() + Node::new(3.0, None);
+
+        let mut options = DotOptions::new();
+        options.collapse(&xy);
+        let dot = z.to_dot_with(&options);
+        assert!(dot.contains("Prod\\nval: 2\\ngrad: None\\n(+2 nodes)\", style=dashed"));
+        assert_eq!(dot.matches("[label=").count(), 3);
+
+        let options = DotOptions {
+            max_depth: Some(0),
+            ..Default::default()
+        };
+        let dot = z.to_dot_with(&options);
+        assert!(dot.contains("(+4 nodes)"));
+        assert_eq!(dot.matches("[label=").count(), 1);
+    }
+
+    #[test]
+    fn test_tensor_to_dot() {
+        let x = Node::new(2.0, None);
+        let data = vec![x.clone() + Node::new(1.0, None), x.clone() * x.clone()];
+        let tensor = TensorImpl::from_vec(&vec![1, 2], &data).unwrap();
+        let dot = tensor.to_dot();
+        assert!(dot.contains("out[0,0]\\nSum"));
+        assert!(dot.contains("out[0,1]\\nProd"));
+        // `x` is shared by both outputs.
+        assert_eq!(dot.matches("[label=").count(), 4);
+    }
+}
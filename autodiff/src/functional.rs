@@ -0,0 +1,324 @@
+//! Jacobians of functions of tensors, computed with reverse mode (`Node`) or forward mode
+//! (`DualNumber`) automatic differentiation.
+//!
+//! Each function takes a closure mapping a tensor of input nodes to a tensor of output nodes (eg.
+//! `|x| model.forward(x).unwrap()`) and the point `x` to differentiate at. The inputs are fresh
+//! leaves, so the `grad` of any node the closure captures (eg. the parameters of a model) is left
+//! untouched. Use `jacobian_wrt()` to differentiate with respect to such nodes instead.
+
+use std::collections::HashMap;
+
+use anyhow::Error;
+use elements::dual_number::DualNumber;
+use interfaces::tensors::{AsStdError, Tensor};
+use interfaces::utils::{Exp, Ln, Pow};
+use tensors::TensorImpl;
+
+use crate::node::{Node, NodeContent};
+
+/// Vector-Jacobian product `v^T J` of `f` at `x`, where `v` has the shape of `f(x)`. The result
+/// has the shape of `x`. Computed with a single reverse sweep.
+pub fn vjp<F>(f: F, x: &TensorImpl<f64>, v: &TensorImpl<f64>) -> Result<TensorImpl<f64>, AsStdError>
+where
+    F: Fn(&TensorImpl<Node<f64>>) -> TensorImpl<Node<f64>>,
+{
+    let inputs = leaves(x)?;
+    let outputs = f(&inputs);
+    if outputs.shape() != v.shape() {
+        return Err(Error::msg(format!(
+            "The shape of `v` {:?} does not match the shape of the output {:?}.",
+            v.shape(),
+            outputs.shape()
+        ))
+        .into());
+    }
+
+    // v^T J is the gradient of the scalar v . f(x).
+    let weighted_sum = outputs
+        .into_iter()
+        .zip(v.get_data().iter())
+        .fold(Node::new(0.0, None), |acc, (output, v_i)| {
+            acc + output * Node::new(*v_i, None)
+        });
+    let grads = weighted_sum.grad_wrt(inputs.get_data(), false);
+    TensorImpl::from_vec(&x.shape(), &grads.into_iter().map(f64::from).collect())
+}
+
+/// Jacobian-vector product `J v` of `f` at `x`, where `v` has the shape of `x`. The result has
+/// the shape of `f(x)`. Computed in forward mode, by propagating dual numbers through the graph
+/// built by `f`.
+pub fn jvp<F>(f: F, x: &TensorImpl<f64>, v: &TensorImpl<f64>) -> Result<TensorImpl<f64>, AsStdError>
+where
+    F: Fn(&TensorImpl<Node<f64>>) -> TensorImpl<Node<f64>>,
+{
+    if x.shape() != v.shape() {
+        return Err(Error::msg(format!(
+            "The shape of `v` {:?} does not match the shape of `x` {:?}.",
+            v.shape(),
+            x.shape()
+        ))
+        .into());
+    }
+    let inputs = leaves(x)?;
+    let outputs = f(&inputs);
+
+    let seeds: HashMap<usize, f64> = inputs
+        .get_data()
+        .iter()
+        .zip(v.get_data().iter())
+        .map(|(input, v_i)| (input.id(), *v_i))
+        .collect();
+    let tangents = tangents(outputs.get_data(), &seeds);
+    TensorImpl::from_vec(&outputs.shape(), &tangents)
+}
+
+/// Jacobian of `f` at `x`, with shape `f(x).shape() ++ x.shape()`. Computed in reverse mode, with
+/// one sweep per element of the output.
+pub fn jacobian<F>(f: F, x: &TensorImpl<f64>) -> Result<TensorImpl<f64>, AsStdError>
+where
+    F: Fn(&TensorImpl<Node<f64>>) -> TensorImpl<Node<f64>>,
+{
+    let inputs = leaves(x)?;
+    let outputs = f(&inputs);
+    jacobian_wrt(&outputs, inputs.get_data(), &x.shape())
+}
+
+/// Jacobian of the (already computed) `outputs` with respect to the nodes in `wrt`, eg. the
+/// parameters returned by `DLModule::params()`. The result has shape
+/// `outputs.shape() ++ wrt_shape`, where `wrt_shape` must contain `wrt.len()` elements (use
+/// `vec![wrt.len()]` for a flat list of nodes).
+pub fn jacobian_wrt(
+    outputs: &TensorImpl<Node<f64>>,
+    wrt: &[Node<f64>],
+    wrt_shape: &[usize],
+) -> Result<TensorImpl<f64>, AsStdError> {
+    let data: Vec<f64> = outputs
+        .get_data()
+        .iter()
+        .flat_map(|output| output.grad_wrt(wrt, false))
+        .map(f64::from)
+        .collect();
+    let mut shape = outputs.shape();
+    shape.extend_from_slice(wrt_shape);
+    TensorImpl::from_vec(&shape, &data)
+}
+
+/// Hessian of the scalar function `f` at `x`, with shape `x.shape() ++ x.shape()`. `f` must
+/// return a tensor with a single element. Computed by differentiating the gradient, built with
+/// `grad_wrt(.., create_graph = true)`, once per element of `x`.
+pub fn hessian<F>(f: F, x: &TensorImpl<f64>) -> Result<TensorImpl<f64>, AsStdError>
+where
+    F: Fn(&TensorImpl<Node<f64>>) -> TensorImpl<Node<f64>>,
+{
+    let inputs = leaves(x)?;
+    let output = f(&inputs);
+    let [output] = output.get_data().as_slice() else {
+        return Err(Error::msg(format!(
+            "The Hessian requires a scalar function, but the output has shape {:?}.",
+            output.shape()
+        ))
+        .into());
+    };
+
+    let grads = output.grad_wrt(inputs.get_data(), true);
+    let data: Vec<f64> = grads
+        .iter()
+        .flat_map(|grad| grad.grad_wrt(inputs.get_data(), false))
+        .map(f64::from)
+        .collect();
+    let mut shape = x.shape();
+    shape.extend(x.shape());
+    TensorImpl::from_vec(&shape, &data)
+}
+
+fn leaves(x: &TensorImpl<f64>) -> Result<TensorImpl<Node<f64>>, AsStdError> {
+    let data: Vec<Node<f64>> = x
+        .get_data()
+        .iter()
+        .map(|x_i| Node::new(*x_i, None))
+        .collect();
+    TensorImpl::from_vec(&x.shape(), &data)
+}
+
+/// Forward sweep through the graph below `outputs`, returning the tangent of each output given
+/// the tangents of the leaves in `seeds` (zero for the other leaves).
+fn tangents(outputs: &[Node<f64>], seeds: &HashMap<usize, f64>) -> Vec<f64> {
+    let mut duals: HashMap<usize, DualNumber> = HashMap::new();
+    for output in outputs.iter() {
+        // Every node comes after its children, so their dual numbers are known.
+        for node in output.topological_order() {
+            if duals.contains_key(&node.id()) {
+                continue;
+            }
+            let dual = node.with_content(|content| match content {
+                NodeContent::Leaf(val, _) => {
+                    let tangent = seeds.get(&node.id()).copied().unwrap_or(0.0);
+                    DualNumber::new(*val, tangent)
+                }
+                NodeContent::Sum(_, _, (a, b)) => duals[&a.id()] + duals[&b.id()],
+                NodeContent::Prod(_, _, (a, b)) => duals[&a.id()] * duals[&b.id()],
+                NodeContent::Quot(_, _, (num, denom)) => duals[&num.id()] / duals[&denom.id()],
+                NodeContent::Exp(_, _, a) => duals[&a.id()].exp(),
+                NodeContent::Ln(_, _, a) => duals[&a.id()].ln(),
+                NodeContent::Pow(_, _, (base, exp)) => duals[&base.id()].pow(duals[&exp.id()]),
+            });
+            duals.insert(node.id(), dual);
+        }
+    }
+    outputs
+        .iter()
+        .map(|output| duals[&output.id()].dual)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use interfaces::tensors::RealTensor;
+
+    use super::*;
+
+    /// f(x) = [x0 . x1, exp(x0) / x1, x1^3]
+    fn f(x: &TensorImpl<Node<f64>>) -> TensorImpl<Node<f64>> {
+        let x0 = x.at(vec![0]).unwrap().clone();
+        let x1 = x.at(vec![1]).unwrap().clone();
+        let data = vec![
+            x0.clone() * x1.clone(),
+            x0.exp() / x1.clone(),
+            x1.pow(Node::new(3.0, None)),
+        ];
+        TensorImpl::from_vec(&vec![3], &data).unwrap()
+    }
+
+    fn f_jacobian(x0: f64, x1: f64) -> Vec<f64> {
+        vec![
+            x1,
+            x0,
+            x0.exp() / x1,
+            -x0.exp() / (x1 * x1),
+            0.0,
+            3.0 * x1 * x1,
+        ]
+    }
+
+    fn assert_all_close(actual: &TensorImpl<f64>, expected: &[f64]) {
+        assert_eq!(actual.get_data().len(), expected.len());
+        for (a, e) in actual.get_data().iter().zip(expected.iter()) {
+            assert!(f64::abs(a - e) < 1e-10, "{} != {}", a, e);
+        }
+    }
+
+    #[test]
+    fn test_jacobian() {
+        let x = TensorImpl::from_vec(&vec![2], &vec![0.5, -2.0]).unwrap();
+        let jac = jacobian(f, &x).unwrap();
+        assert_eq!(jac.shape(), vec![3, 2]);
+        assert_all_close(&jac, &f_jacobian(0.5, -2.0));
+    }
+
+    #[test]
+    fn test_vjp() {
+        let x = TensorImpl::from_vec(&vec![2], &vec![0.5, -2.0]).unwrap();
+        let v = TensorImpl::from_vec(&vec![3], &vec![1.0, 2.0, 3.0]).unwrap();
+        let result = vjp(f, &x, &v).unwrap();
+        assert_eq!(result.shape(), vec![2]);
+
+        let jac = f_jacobian(0.5, -2.0);
+        let expected: Vec<f64> = (0..2)
+            .map(|j| (0..3).map(|i| v.get_data()[i] * jac[i * 2 + j]).sum())
+            .collect();
+        assert_all_close(&result, &expected);
+    }
+
+    #[test]
+    fn test_jvp() {
+        let x = TensorImpl::from_vec(&vec![2], &vec![0.5, -2.0]).unwrap();
+        let v = TensorImpl::from_vec(&vec![2], &vec![1.0, -1.0]).unwrap();
+        let result = jvp(f, &x, &v).unwrap();
+        assert_eq!(result.shape(), vec![3]);
+
+        let jac = f_jacobian(0.5, -2.0);
+        let expected: Vec<f64> = (0..3)
+            .map(|i| (0..2).map(|j| jac[i * 2 + j] * v.get_data()[j]).sum())
+            .collect();
+        assert_all_close(&result, &expected);
+    }
+
+    #[test]
+    fn test_jvp_at_zero() {
+        // d/dx x^2 = 0 at 0, not 0 * inf.
+        let square = |x: &TensorImpl<Node<f64>>| {
+            let x0 = x.at(vec![0]).unwrap().clone();
+            TensorImpl::from_vec(&vec![1], &vec![x0.pow(Node::new(2.0, None))]).unwrap()
+        };
+        let x = TensorImpl::from_vec(&vec![1], &vec![0.0]).unwrap();
+        let v = TensorImpl::from_vec(&vec![1], &vec![1.0]).unwrap();
+        assert_eq!(jvp(square, &x, &v).unwrap().get_data(), &vec![0.0]);
+    }
+
+    #[test]
+    fn test_jvp_matches_jacobian_columns() {
+        let data: Vec<f64> = (0..6).map(|x| x as f64 / 3.0).collect();
+        let x = TensorImpl::from_vec(&vec![2, 3], &data).unwrap();
+        let softmax = |x: &TensorImpl<Node<f64>>| x.softmax(1);
+        let jac = jacobian(softmax, &x).unwrap();
+        assert_eq!(jac.shape(), vec![2, 3, 2, 3]);
+
+        for j in 0..6 {
+            let mut basis = vec![0.0; 6];
+            basis[j] = 1.0;
+            let v = TensorImpl::from_vec(&vec![2, 3], &basis).unwrap();
+            let column = jvp(softmax, &x, &v).unwrap();
+            let expected: Vec<f64> = (0..6).map(|i| jac.get_data()[i * 6 + j]).collect();
+            assert_all_close(&column, &expected);
+        }
+    }
+
+    #[test]
+    fn test_hessian() {
+        // g(x) = x0^2 . x1 + exp(x1)
+        let g = |x: &TensorImpl<Node<f64>>| {
+            let x0 = x.at(vec![0]).unwrap().clone();
+            let x1 = x.at(vec![1]).unwrap().clone();
+            let y = x0.clone() * x0 * x1.clone() + x1.exp();
+            TensorImpl::from_vec(&vec![1], &vec![y]).unwrap()
+        };
+        let x = TensorImpl::from_vec(&vec![2], &vec![1.5, 0.5]).unwrap();
+        let hess = hessian(g, &x).unwrap();
+        assert_eq!(hess.shape(), vec![2, 2]);
+        assert_all_close(&hess, &[2.0 * 0.5, 2.0 * 1.5, 2.0 * 1.5, 0.5_f64.exp()]);
+    }
+
+    #[test]
+    fn test_hessian_requires_scalar_output() {
+        let x = TensorImpl::from_vec(&vec![2], &vec![0.5, -2.0]).unwrap();
+        assert!(hessian(f, &x).is_err());
+    }
+
+    #[test]
+    fn test_shape_mismatch() {
+        let x = TensorImpl::from_vec(&vec![2], &vec![0.5, -2.0]).unwrap();
+        let v = TensorImpl::from_vec(&vec![2], &vec![1.0, 2.0]).unwrap();
+        assert!(vjp(f, &x, &v).is_err());
+        let v = TensorImpl::from_vec(&vec![3], &vec![1.0, 2.0, 3.0]).unwrap();
+        assert!(jvp(f, &x, &v).is_err());
+    }
+
+    #[test]
+    fn test_jacobian_wrt_params() {
+        // y = w . x for a "model" with parameters w.
+        let w: Vec<Node<f64>> = vec![Node::new(2.0, None), Node::new(-1.0, None)];
+        let x = [3.0, 4.0];
+        let outputs: Vec<Node<f64>> = w
+            .iter()
+            .zip(x.iter())
+            .map(|(w_i, x_i)| w_i.clone() * Node::new(*x_i, None))
+            .collect();
+        let outputs = TensorImpl::from_vec(&vec![2], &outputs).unwrap();
+
+        let jac = jacobian_wrt(&outputs, &w, &[w.len()]).unwrap();
+        assert_eq!(jac.shape(), vec![2, 2]);
+        assert_all_close(&jac, &[3.0, 0.0, 0.0, 4.0]);
+        // The grads of the parameters are untouched.
+        assert!(w[0].grad().is_none());
+    }
+}
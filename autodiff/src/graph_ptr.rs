@@ -0,0 +1,130 @@
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    fmt::Debug,
+    mem::size_of,
+    ops::{Deref, DerefMut},
+    rc::Rc,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+/// A family of shared, interior-mutable pointers used to link the nodes of a computation graph.
+/// The pointer family decides whether a graph (and anything holding it, eg. a tensor of nodes) can
+/// be sent between threads.
+pub trait GraphPtr: Debug + Sized + 'static {
+    type Ptr<N>: Clone;
+    type Ref<'a, N: 'a>: Deref<Target = N>;
+    type RefMut<'a, N: 'a>: DerefMut<Target = N>;
+    /// A gradient hook stored in a node (see `GenericNode::register_hook()`).
+    type Hook<T>: Clone;
+
+    fn new_ptr<N>(content: N) -> Self::Ptr<N>;
+
+    fn borrow<N>(ptr: &Self::Ptr<N>) -> Self::Ref<'_, N>;
+
+    fn borrow_mut<N>(ptr: &Self::Ptr<N>) -> Self::RefMut<'_, N>;
+
+    /// Whether both pointers point to the same allocation.
+    fn ptr_eq<N>(a: &Self::Ptr<N>, b: &Self::Ptr<N>) -> bool;
+
+    /// Address of the allocation, unique among the pointers alive at the same time.
+    fn addr<N>(ptr: &Self::Ptr<N>) -> usize;
+
+    /// Size in bytes of the allocation behind a `Ptr<N>`, including the reference counts and the
+    /// lock.
+    fn alloc_size<N>() -> usize;
+
+    fn new_hook<T>(hook: impl Fn(T) -> T + Send + Sync + 'static) -> Self::Hook<T>;
+
+    fn call_hook<T>(hook: &Self::Hook<T>, grad: T) -> T;
+}
+
+/// Single-threaded pointers: `Rc<RefCell<_>>`.
+#[derive(Debug)]
+pub struct Local;
+
+impl GraphPtr for Local {
+    type Ptr<N> = Rc<RefCell<N>>;
+    type Ref<'a, N: 'a> = Ref<'a, N>;
+    type RefMut<'a, N: 'a> = RefMut<'a, N>;
+    type Hook<T> = Rc<dyn Fn(T) -> T>;
+
+    fn new_ptr<N>(content: N) -> Self::Ptr<N> {
+        Rc::new(RefCell::new(content))
+    }
+
+    fn borrow<N>(ptr: &Self::Ptr<N>) -> Self::Ref<'_, N> {
+        ptr.deref().borrow()
+    }
+
+    fn borrow_mut<N>(ptr: &Self::Ptr<N>) -> Self::RefMut<'_, N> {
+        ptr.deref().borrow_mut()
+    }
+
+    fn ptr_eq<N>(a: &Self::Ptr<N>, b: &Self::Ptr<N>) -> bool {
+        Rc::ptr_eq(a, b)
+    }
+
+    fn addr<N>(ptr: &Self::Ptr<N>) -> usize {
+        Rc::as_ptr(ptr) as *const () as usize
+    }
+
+    fn alloc_size<N>() -> usize {
+        // Strong and weak counts.
+        2 * size_of::<usize>() + size_of::<RefCell<N>>()
+    }
+
+    fn new_hook<T>(hook: impl Fn(T) -> T + Send + Sync + 'static) -> Self::Hook<T> {
+        Rc::new(hook)
+    }
+
+    fn call_hook<T>(hook: &Self::Hook<T>, grad: T) -> T {
+        hook(grad)
+    }
+}
+
+/// Thread-safe pointers: `Arc<RwLock<_>>`. Graphs built from these are `Send + Sync`, at the cost
+/// of atomic reference counting and locking on every access.
+#[derive(Debug)]
+pub struct Shared;
+
+impl GraphPtr for Shared {
+    type Ptr<N> = Arc<RwLock<N>>;
+    type Ref<'a, N: 'a> = RwLockReadGuard<'a, N>;
+    type RefMut<'a, N: 'a> = RwLockWriteGuard<'a, N>;
+    type Hook<T> = Arc<dyn Fn(T) -> T + Send + Sync>;
+
+    fn new_ptr<N>(content: N) -> Self::Ptr<N> {
+        Arc::new(RwLock::new(content))
+    }
+
+    fn borrow<N>(ptr: &Self::Ptr<N>) -> Self::Ref<'_, N> {
+        ptr.read()
+            .expect("Node lock poisoned by a panicking thread.")
+    }
+
+    fn borrow_mut<N>(ptr: &Self::Ptr<N>) -> Self::RefMut<'_, N> {
+        ptr.write()
+            .expect("Node lock poisoned by a panicking thread.")
+    }
+
+    fn ptr_eq<N>(a: &Self::Ptr<N>, b: &Self::Ptr<N>) -> bool {
+        Arc::ptr_eq(a, b)
+    }
+
+    fn addr<N>(ptr: &Self::Ptr<N>) -> usize {
+        Arc::as_ptr(ptr) as *const () as usize
+    }
+
+    fn alloc_size<N>() -> usize {
+        // Strong and weak counts.
+        2 * size_of::<usize>() + size_of::<RwLock<N>>()
+    }
+
+    fn new_hook<T>(hook: impl Fn(T) -> T + Send + Sync + 'static) -> Self::Hook<T> {
+        Arc::new(hook)
+    }
+
+    fn call_hook<T>(hook: &Self::Hook<T>, grad: T) -> T {
+        hook(grad)
+    }
+}
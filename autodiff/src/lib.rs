@@ -1,4 +1,10 @@
+pub mod dot;
+pub mod functional;
+pub mod graph_ptr;
+pub mod no_grad;
 pub mod node;
+pub mod profile;
+pub mod tape;
 
 // pub fn add(left: usize, right: usize) -> usize {
 //     left + right
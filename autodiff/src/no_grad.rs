@@ -0,0 +1,162 @@
+use std::cell::Cell;
+
+use interfaces::tensors::{RealElement, Tensor};
+use tensors::TensorImpl;
+
+use crate::graph_ptr::GraphPtr;
+use crate::node::GenericNode;
+
+thread_local! {
+    static GRAD_ENABLED: Cell<bool> = const { Cell::new(true) };
+}
+
+/// Returns `false` while a `NoGradGuard` is alive on the current thread. When grad is disabled,
+/// arithmetic on `Node`s computes values only and returns leaf nodes, so no computation graph is
+/// built.
+pub fn is_grad_enabled() -> bool {
+    GRAD_ENABLED.with(|enabled| enabled.get())
+}
+
+/// Disable graph construction on the current thread until the returned guard is dropped. Guards
+/// can be nested; dropping a guard restores the mode that was active when it was created.
+///
+/// ```ignore
+/// let _guard = no_grad();
+/// let pred = model.forward(&x)?; // `pred` holds leaf nodes only.
+/// ```
+pub fn no_grad() -> NoGradGuard {
+    let prev = GRAD_ENABLED.with(|enabled| enabled.replace(false));
+    NoGradGuard { prev }
+}
+
+/// Scope guard returned by `no_grad()`.
+#[must_use = "grad is only disabled while the guard is alive"]
+pub struct NoGradGuard {
+    prev: bool,
+}
+
+impl Drop for NoGradGuard {
+    fn drop(&mut self) {
+        GRAD_ENABLED.with(|enabled| enabled.set(self.prev));
+    }
+}
+
+/// Cut an object out of the computation graph, keeping its value(s) but none of the history.
+pub trait Detach {
+    fn detach(&self) -> Self;
+}
+
+impl<T: RealElement + From<f64>, P: GraphPtr> Detach for GenericNode<T, P> {
+    fn detach(&self) -> Self {
+        GenericNode::new(self.val(), None)
+    }
+}
+
+impl<T: RealElement + From<f64>, P: GraphPtr> Detach for TensorImpl<GenericNode<T, P>> {
+    fn detach(&self) -> Self {
+        let data: Vec<GenericNode<T, P>> =
+            self.get_data().iter().map(|node| node.detach()).collect();
+        TensorImpl::from_vec(&self.shape(), &data)
+            .expect("Data is taken from a tensor of the same shape.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use interfaces::tensors::RealTensor;
+    use interfaces::utils::{Exp, Ln, Pow};
+
+    use super::*;
+    use crate::node::Node;
+
+    #[test]
+    fn test_grad_enabled_by_default() {
+        assert!(is_grad_enabled());
+    }
+
+    #[test]
+    fn test_guard_restores_mode() {
+        {
+            let _guard = no_grad();
+            assert!(!is_grad_enabled());
+            {
+                let _inner = no_grad();
+                assert!(!is_grad_enabled());
+            }
+            // Still disabled, the outer guard is alive.
+            assert!(!is_grad_enabled());
+        }
+        assert!(is_grad_enabled());
+    }
+
+    #[test]
+    fn test_ops_build_graph_with_grad() {
+        let x = Node::new(2.0, None);
+        let y = Node::new(3.0, None);
+        let z = x * y;
+        assert!(!z.is_leaf());
+    }
+
+    #[test]
+    fn test_ops_return_leaves_without_grad() {
+        let _guard = no_grad();
+        let x = Node::new(2.0, None);
+        let y = Node::new(3.0, None);
+
+        let results = [
+            x.clone() + y.clone(),
+            x.clone() - y.clone(),
+            x.clone() * y.clone(),
+            x.clone() / y.clone(),
+            x.clone().pow(y.clone()),
+            x.clone().exp(),
+            x.clone().ln(),
+        ];
+        for node in results.iter() {
+            assert!(node.is_leaf());
+            assert!(node.grad().is_none());
+        }
+        assert_eq!(results[0].val(), 5.0);
+        assert_eq!(results[1].val(), -1.0);
+        assert_eq!(results[2].val(), 6.0);
+        assert_eq!(results[4].val(), 8.0);
+    }
+
+    #[test]
+    fn test_backward_does_not_reach_inputs_without_grad() {
+        let x = Node::new(2.0, None);
+        let mut y = {
+            let _guard = no_grad();
+            x.clone() * Node::new(3.0, None)
+        };
+        y.backward(1.0);
+        assert!(x.grad().is_none());
+    }
+
+    #[test]
+    fn test_detach_node() {
+        let x = Node::new(2.0, None);
+        let y = x.clone() * Node::new(3.0, None);
+        let mut detached = y.detach();
+        assert!(detached.is_leaf());
+        assert_eq!(detached.val(), 6.0);
+
+        detached.backward(1.0);
+        assert!(x.grad().is_none());
+        assert!(y.grad().is_none());
+    }
+
+    #[test]
+    fn test_detach_tensor() {
+        let data: Vec<Node<f64>> = (0..6).map(|x| Node::new(x as f64, None)).collect();
+        let tensor = TensorImpl::from_vec(&vec![2, 3], &data).unwrap();
+        let softmax = tensor.softmax(1);
+
+        let detached = softmax.detach();
+        assert_eq!(detached.shape(), vec![2, 3]);
+        for (node, original) in detached.clone().into_iter().zip(softmax) {
+            assert!(node.is_leaf());
+            assert_eq!(node.val(), original.val());
+        }
+    }
+}
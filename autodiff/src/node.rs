@@ -1,56 +1,122 @@
 use std::{
-    cell::RefCell,
-    fmt::Display,
-    ops::{Add, AddAssign, Deref, DerefMut, Div, Mul, Sub},
+    collections::{HashMap, HashSet},
+    fmt::{Debug, Display},
+    mem::size_of,
+    ops::{Add, AddAssign, Div, Mul, Sub},
     rc::Rc,
 };
 
 use interfaces::{
-    tensors::{Element, RealElement},
+    tensors::{Element, Piecewise, RealElement, SetValue},
     utils::{Exp, Ln, Pow},
 };
 use num_traits::Zero;
 
-type Ptr<N> = Rc<RefCell<N>>;
+use crate::graph_ptr::{GraphPtr, Local, Shared};
+use crate::no_grad::{is_grad_enabled, no_grad};
 
 #[derive(Debug)]
-pub enum NodeContent<T> {
-    Sum(T, Option<T>, (Node<T>, Node<T>)),
-    Prod(T, Option<T>, (Node<T>, Node<T>)),
-    Quot(T, Option<T>, (Node<T>, Node<T>)),
-    Exp(T, Option<T>, Node<T>),
-    Ln(T, Option<T>, Node<T>),
-    Pow(T, Option<T>, (Node<T>, Node<T>)),
+pub enum NodeContent<T, P: GraphPtr = Local> {
+    Sum(T, Option<T>, (GenericNode<T, P>, GenericNode<T, P>)),
+    Prod(T, Option<T>, (GenericNode<T, P>, GenericNode<T, P>)),
+    Quot(T, Option<T>, (GenericNode<T, P>, GenericNode<T, P>)),
+    Exp(T, Option<T>, GenericNode<T, P>),
+    Ln(T, Option<T>, GenericNode<T, P>),
+    Pow(T, Option<T>, (GenericNode<T, P>, GenericNode<T, P>)),
     Leaf(T, Option<T>),
 }
 
-/// A node in a computation graph.
-#[derive(Debug)]
-pub struct Node<T> {
-    ptr: Ptr<NodeContent<T>>,
+/// What a node points to: its content plus the settings that only matter during `backward()`.
+struct NodeCell<T, P: GraphPtr> {
+    content: NodeContent<T, P>,
+    retain_grad: bool,
+    hooks: Vec<P::Hook<T>>,
 }
 
-impl<T: RealElement + From<f64>> Node<T> {
+impl<T, P: GraphPtr> From<NodeContent<T, P>> for NodeCell<T, P> {
+    fn from(content: NodeContent<T, P>) -> Self {
+        NodeCell {
+            content,
+            retain_grad: false,
+            hooks: Vec::new(),
+        }
+    }
+}
+
+/// A node in a computation graph, generic over the family of pointers `P` that link the nodes.
+/// Use one of the `Node` or `SyncNode` aliases rather than naming this type directly.
+pub struct GenericNode<T, P: GraphPtr> {
+    ptr: P::Ptr<NodeCell<T, P>>,
+}
+
+/// A node in a single-threaded computation graph (`Rc<RefCell<_>>` links).
+pub type Node<T> = GenericNode<T, Local>;
+
+/// A node in a thread-safe computation graph (`Arc<RwLock<_>>` links). Tensors and modules built
+/// on `SyncNode` are `Send + Sync`.
+pub type SyncNode<T> = GenericNode<T, Shared>;
+
+impl<T: RealElement + From<f64>, P: GraphPtr> GenericNode<T, P> {
     pub fn new(val: T, grad: Option<T>) -> Self {
-        Node {
-            ptr: Rc::new(RefCell::new(NodeContent::new(val, grad))),
+        NodeContent::new(val, grad).into()
+    }
+
+    /// Wrap the result of an operation, dropping the references to the operands if grad is
+    /// disabled (see `no_grad()`).
+    fn from_op(content: NodeContent<T, P>) -> Self {
+        if is_grad_enabled() {
+            content.into()
+        } else {
+            GenericNode::new(content.val().clone(), None)
         }
     }
 
     pub fn val(&self) -> T {
-        self.ptr.deref().borrow().val().clone()
+        P::borrow(&self.ptr).content.val().clone()
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        matches!(P::borrow(&self.ptr).content, NodeContent::Leaf(_, _))
     }
 
     pub fn grad(&self) -> Option<T> {
-        self.ptr.deref().borrow().grad().clone()
+        P::borrow(&self.ptr).content.grad().clone()
+    }
+
+    /// Identifier of the node, shared by all of its clones and unique among the nodes alive at the
+    /// same time.
+    pub fn id(&self) -> usize {
+        P::addr(&self.ptr)
+    }
+
+    /// Name of the operation that produced this node (eg. `"Prod"`), `"Leaf"` for inputs.
+    pub fn op_name(&self) -> &'static str {
+        P::borrow(&self.ptr).content.op_name()
+    }
+
+    /// Apply `f` to the content of this node, eg. to match on its operation.
+    pub(crate) fn with_content<R>(&self, f: impl FnOnce(&NodeContent<T, P>) -> R) -> R {
+        f(&P::borrow(&self.ptr).content)
+    }
+
+    /// The nodes this node was computed from, in operand order (eg. base then exponent for `Pow`).
+    pub fn children(&self) -> Vec<GenericNode<T, P>> {
+        match &P::borrow(&self.ptr).content {
+            NodeContent::Sum(_, _, (np1, np2))
+            | NodeContent::Prod(_, _, (np1, np2))
+            | NodeContent::Quot(_, _, (np1, np2))
+            | NodeContent::Pow(_, _, (np1, np2)) => vec![np1.clone(), np2.clone()],
+            NodeContent::Exp(_, _, np) | NodeContent::Ln(_, _, np) => vec![np.clone()],
+            NodeContent::Leaf(_, _) => vec![],
+        }
     }
 
     pub fn set_grad(&mut self, new_grad: T) {
-        self.ptr.deref().borrow_mut().deref_mut().set_grad(new_grad)
+        P::borrow_mut(&self.ptr).content.set_grad(new_grad)
     }
 
     pub fn set_val(&mut self, new_val: T) {
-        self.ptr.deref().borrow_mut().deref_mut().set_val(new_val)
+        P::borrow_mut(&self.ptr).content.set_val(new_val)
     }
 
     pub fn add_assign_grad(&mut self, new_grad: T) {
@@ -60,70 +126,230 @@ impl<T: RealElement + From<f64>> Node<T> {
         }
     }
 
-    // Set the gradient and initiate backward propagation.
-    // Propagate a given gradient on the `grad` of each associated Node.
-    // Assumes the `grad` on self is not None.
+    /// Approximate number of heap bytes owned by this node alone (not its children): the node
+    /// allocation and its hooks. Heap data owned by `T` itself is not counted.
+    pub fn heap_bytes(&self) -> usize {
+        let num_hooks = P::borrow(&self.ptr).hooks.capacity();
+        P::alloc_size::<NodeCell<T, P>>() + num_hooks * size_of::<P::Hook<T>>()
+    }
+
+    /// Keep the gradient of this (intermediate) node during `backward()`. Only leaves keep their
+    /// gradient by default.
+    pub fn retain_grad(&self) {
+        P::borrow_mut(&self.ptr).retain_grad = true;
+    }
+
+    /// Register a hook that is called with the total gradient of this node during `backward()`,
+    /// before the gradient is stored or propagated further. The hook returns the gradient to use,
+    /// so it can eg. clip the gradient, log it or check it for NaNs. Hooks run in the order they
+    /// were registered.
+    pub fn register_hook(&self, hook: impl Fn(T) -> T + Send + Sync + 'static) {
+        P::borrow_mut(&self.ptr).hooks.push(P::new_hook(hook));
+    }
+
+    /// Initiate backward propagation from `self`, seeded with `grad`. Gradients are accumulated
+    /// (added) into the `grad` of the leaves, and of the nodes marked with `retain_grad()`.
+    ///
+    /// Calling `backward()` twice on the same graph accumulates twice; call `release_graph()`
+    /// afterwards to prevent that and to free the graph straight away.
     pub fn backward(&mut self, grad: T) {
-        let self_val = self.val();
+        // Reverse topological order, so that every node receives its total gradient once.
+        let mut grads: HashMap<usize, T> = HashMap::new();
+        grads.insert(self.id(), grad);
+        for mut node in self.topological_order().into_iter().rev() {
+            let Some(mut grad) = grads.remove(&node.id()) else {
+                continue;
+            };
+            // Don't hold the borrow while calling the hooks, they may access the node.
+            let (hooks, keep_grad) = {
+                let cell = P::borrow(&node.ptr);
+                let is_leaf = matches!(cell.content, NodeContent::Leaf(_, _));
+                (cell.hooks.clone(), is_leaf || cell.retain_grad)
+            };
+            for hook in hooks.iter() {
+                grad = P::call_hook(hook, grad);
+            }
+            if keep_grad {
+                node.add_assign_grad(grad.clone());
+            }
 
-        // let node_content_ref = self.ptr.as_ref().borrow().deref();
-        match self.ptr.as_ref().borrow_mut().deref_mut() {
-            NodeContent::Sum(_, _, (ref mut np1, ref mut np2)) => {
-                np1.backward(grad.clone());
-                np2.backward(grad.clone());
+            let child_grads: Vec<(Self, T)> = match &P::borrow(&node.ptr).content {
+                NodeContent::Sum(_, _, (np1, np2)) => {
+                    vec![(np1.clone(), grad.clone()), (np2.clone(), grad)]
+                }
+                NodeContent::Prod(_, _, (np1, np2)) => vec![
+                    (np1.clone(), np2.val() * grad.clone()),
+                    (np2.clone(), np1.val() * grad),
+                ],
+                NodeContent::Quot(_, _, (np_num, np_denom)) => {
+                    let minus_one = <f64 as Into<T>>::into(-1_f64);
+                    let two = <f64 as Into<T>>::into(2_f64);
+                    let np_num_grad = grad.clone() / np_denom.val();
+                    let np_denom_grad = minus_one * grad * np_num.val() / np_denom.val().pow(two);
+                    vec![
+                        (np_num.clone(), np_num_grad),
+                        (np_denom.clone(), np_denom_grad),
+                    ]
+                }
+                NodeContent::Exp(self_val, _, np) => vec![(np.clone(), grad * self_val.clone())],
+                NodeContent::Ln(_, _, np) => {
+                    let np_grad = grad * <f64 as Into<T>>::into(1_f64) / np.val();
+                    vec![(np.clone(), np_grad)]
+                }
+                NodeContent::Pow(_, _, (np_b, np_e)) => {
+                    let b_val = np_b.val();
+                    let e_val = np_e.val();
+                    let minus_one = <f64 as Into<T>>::into(-1_f64);
+
+                    // exponent . base^(exponent - 1)
+                    let np_b_grad =
+                        grad.clone() * e_val.clone() * b_val.clone().pow(e_val.clone() + minus_one);
+                    // base^exponent . ln(base)
+                    let np_e_grad = grad * b_val.clone().pow(e_val) * b_val.ln();
+                    vec![(np_b.clone(), np_b_grad), (np_e.clone(), np_e_grad)]
+                }
+                NodeContent::Leaf(_, _) => vec![],
+            };
+            for (child, child_grad) in child_grads.into_iter() {
+                let acc = match grads.remove(&child.id()) {
+                    Some(acc) => acc + child_grad,
+                    None => child_grad,
+                };
+                grads.insert(child.id(), acc);
             }
-            NodeContent::Prod(_, _, (ref mut np1, ref mut np2)) => {
-                let np1_grad = np2.val().to_owned() * grad.clone();
-                let np2_grad = np1.val().to_owned() * grad.clone();
-                np1.backward(np1_grad);
-                np2.backward(np2_grad);
+        }
+    }
+
+    /// Drop the links from every node below `self` to its operands, turning them into leaves that
+    /// keep their value and grad. Call after `backward()` once the graph is no longer needed: the
+    /// intermediate nodes are freed even while `self` is still alive, and a second `backward()`
+    /// can no longer reach (and accumulate into) the inputs.
+    ///
+    /// The hooks of the nodes turned into leaves are dropped too, as they may capture other nodes
+    /// of the graph (eg. those of a `Checkpoint`). The leaves below `self`, eg. the parameters of a
+    /// model, keep theirs.
+    pub fn release_graph(&mut self) {
+        let mut visited: HashSet<usize> = HashSet::new();
+        let mut stack: Vec<Self> = vec![self.clone()];
+        while let Some(node) = stack.pop() {
+            if !visited.insert(node.id()) {
+                continue;
             }
-            NodeContent::Quot(_, _, (ref mut np_num, ref mut np_denom)) => {
-                let minus_one = <f64 as Into<T>>::into(-1_f64);
-                let two = <f64 as Into<T>>::into(2_f64);
-                let np_num_grad = grad.clone() / np_denom.val().to_owned();
-                let np_denom_grad =
-                    minus_one * grad.clone() * np_num.val().to_owned() / np_denom.val().to_owned().pow(two);
-                np_num.backward(np_num_grad);
-                np_denom.backward(np_denom_grad);
+            let children = node.children();
+            if children.is_empty() {
+                continue;
             }
-            NodeContent::Exp(_, _, ref mut np) => {
-                let np_grad = grad.clone() * self_val;
-                np.backward(np_grad);
+            {
+                let mut cell = P::borrow_mut(&node.ptr);
+                let leaf =
+                    NodeContent::Leaf(cell.content.val().clone(), cell.content.grad().clone());
+                cell.content = leaf;
+                cell.hooks.clear();
+            }
+            stack.extend(children);
+        }
+    }
+
+    /// Differentiate `self` with respect to each of the nodes in `wrt`. Unlike `backward()`, this
+    /// leaves the `grad` of every node untouched and returns the gradients instead (a zero node for
+    /// an element of `wrt` that `self` does not depend on).
+    ///
+    /// With `create_graph` the gradients are built from `Node` arithmetic, so they are part of the
+    /// graph and can themselves be differentiated (eg. for Hessian-vector products or gradient
+    /// penalties). Otherwise they are returned as leaves.
+    pub fn grad_wrt(&self, wrt: &[Self], create_graph: bool) -> Vec<Self> {
+        let _guard = if create_graph { None } else { Some(no_grad()) };
+
+        // Reverse topological order, so that the gradient of a node is complete before it is
+        // propagated to its children.
+        let mut grads: HashMap<usize, Self> = HashMap::new();
+        grads.insert(self.id(), GenericNode::from(1.0));
+        for node in self.topological_order().into_iter().rev() {
+            let Some(grad) = grads.get(&node.id()).cloned() else {
+                continue;
+            };
+            let child_grads: Vec<(Self, Self)> = match &P::borrow(&node.ptr).content {
+                NodeContent::Sum(_, _, (np1, np2)) => {
+                    vec![(np1.clone(), grad.clone()), (np2.clone(), grad)]
+                }
+                NodeContent::Prod(_, _, (np1, np2)) => vec![
+                    (np1.clone(), grad.clone() * np2.clone()),
+                    (np2.clone(), grad * np1.clone()),
+                ],
+                NodeContent::Quot(_, _, (np_num, np_denom)) => vec![
+                    (np_num.clone(), grad.clone() / np_denom.clone()),
+                    (
+                        np_denom.clone(),
+                        GenericNode::from(-1.0) * grad * np_num.clone()
+                            / (np_denom.clone() * np_denom.clone()),
+                    ),
+                ],
+                NodeContent::Exp(_, _, np) => vec![(np.clone(), grad * node.clone())],
+                NodeContent::Ln(_, _, np) => vec![(np.clone(), grad / np.clone())],
+                NodeContent::Pow(_, _, (np_b, np_e)) => {
+                    // exponent . base^(exponent - 1)
+                    let np_b_grad = grad.clone()
+                        * np_e.clone()
+                        * np_b.clone().pow(np_e.clone() - GenericNode::from(1.0));
+                    // base^exponent . ln(base)
+                    let np_e_grad = grad * node.clone() * np_b.clone().ln();
+                    vec![(np_b.clone(), np_b_grad), (np_e.clone(), np_e_grad)]
+                }
+                NodeContent::Leaf(_, _) => vec![],
+            };
+            for (child, child_grad) in child_grads.into_iter() {
+                let acc = match grads.remove(&child.id()) {
+                    Some(acc) => acc + child_grad,
+                    None => child_grad,
+                };
+                grads.insert(child.id(), acc);
             }
-            NodeContent::Ln(_, _, ref mut np) => {
-                let np_grad = grad.clone() * <f64 as Into<T>>::into(1_f64) / np.val();
-                np.backward(np_grad);
+        }
+
+        wrt.iter()
+            .map(|node| grads.get(&node.id()).cloned().unwrap_or_else(Self::zero))
+            .collect()
+    }
+
+    /// The nodes of the graph below (and including) `self`, with every node after its children.
+    pub(crate) fn topological_order(&self) -> Vec<Self> {
+        let mut order: Vec<Self> = Vec::new();
+        let mut visited: HashSet<usize> = HashSet::new();
+        // The flag marks a node whose children have already been pushed.
+        let mut stack: Vec<(Self, bool)> = vec![(self.clone(), false)];
+        while let Some((node, children_done)) = stack.pop() {
+            if children_done {
+                order.push(node);
+                continue;
             }
-            NodeContent::Pow(_, _, (ref mut np_b, ref mut np_e)) => {
-                // exponent . base^(exponent - 1)
-                let b_val = np_b.val().clone();
-                let e_val = np_e.val().clone();
-                let minus_one = <f64 as Into<T>>::into(-1_f64);
-
-                let np_b_grad =
-                    grad.clone() * e_val.clone() * b_val.clone().pow(e_val.clone() + minus_one);
-                let np_e_grad = grad.clone() * b_val.clone().pow(e_val.to_owned()) * b_val.ln();
-
-                // base^exponent . ln(base)
-                np_b.backward(np_b_grad);
-                np_e.backward(np_e_grad);
+            if !visited.insert(node.id()) {
+                continue;
             }
-            NodeContent::Leaf(_, _) => {} // Do nothing.
+            let children = node.children();
+            stack.push((node, true));
+            stack.extend(children.into_iter().map(|child| (child, false)));
         }
-        self.add_assign_grad(grad.clone());
+        order
+    }
+}
+
+impl<T: RealElement + From<f64>> Node<T> {
+    /// Like `register_hook()`, for hooks that capture data which cannot be shared between threads
+    /// (eg. other `Node`s).
+    pub fn register_local_hook(&self, hook: impl Fn(T) -> T + 'static) {
+        Local::borrow_mut(&self.ptr).hooks.push(Rc::new(hook));
     }
 }
 
-impl<T> From<NodeContent<T>> for Node<T> {
-    fn from(value: NodeContent<T>) -> Self {
-        Node {
-            ptr: Rc::new(RefCell::new(value)),
+impl<T, P: GraphPtr> From<NodeContent<T, P>> for GenericNode<T, P> {
+    fn from(value: NodeContent<T, P>) -> Self {
+        GenericNode {
+            ptr: P::new_ptr(value.into()),
         }
     }
 }
 
-impl<T: RealElement + From<f64>> NodeContent<T> {
+impl<T: RealElement + From<f64>, P: GraphPtr> NodeContent<T, P> {
     pub fn new(val: T, grad: Option<T>) -> Self {
         NodeContent::Leaf(val, grad)
     }
@@ -140,6 +366,18 @@ impl<T: RealElement + From<f64>> NodeContent<T> {
         }
     }
 
+    pub fn op_name(&self) -> &'static str {
+        match self {
+            NodeContent::Sum(_, _, _) => "Sum",
+            NodeContent::Prod(_, _, _) => "Prod",
+            NodeContent::Quot(_, _, _) => "Quot",
+            NodeContent::Exp(_, _, _) => "Exp",
+            NodeContent::Ln(_, _, _) => "Ln",
+            NodeContent::Pow(_, _, _) => "Pow",
+            NodeContent::Leaf(_, _) => "Leaf",
+        }
+    }
+
     pub fn grad(&self) -> &Option<T> {
         match self {
             NodeContent::Sum(_, grad, _)
@@ -198,10 +436,10 @@ impl<T: RealElement + From<f64>> NodeContent<T> {
     }
 }
 
-impl<T: RealElement + From<f64>> Add<NodeContent<T>> for NodeContent<T> {
-    type Output = NodeContent<T>;
+impl<T: RealElement + From<f64>, P: GraphPtr> Add<NodeContent<T, P>> for NodeContent<T, P> {
+    type Output = NodeContent<T, P>;
 
-    fn add(self, rhs: NodeContent<T>) -> NodeContent<T> {
+    fn add(self, rhs: NodeContent<T, P>) -> NodeContent<T, P> {
         NodeContent::Sum(
             self.val().clone() + rhs.val().clone(),
             None,
@@ -210,27 +448,27 @@ impl<T: RealElement + From<f64>> Add<NodeContent<T>> for NodeContent<T> {
     }
 }
 
-impl<T: RealElement + From<f64>> Add<Node<T>> for Node<T> {
-    type Output = Node<T>;
+impl<T: RealElement + From<f64>, P: GraphPtr> Add<GenericNode<T, P>> for GenericNode<T, P> {
+    type Output = GenericNode<T, P>;
 
-    fn add(self, rhs: Node<T>) -> Self::Output {
-        NodeContent::Sum(self.val() + rhs.val(), None, (self, rhs)).into()
+    fn add(self, rhs: GenericNode<T, P>) -> Self::Output {
+        GenericNode::from_op(NodeContent::Sum(self.val() + rhs.val(), None, (self, rhs)))
     }
 }
 
-impl<T: RealElement + From<f64>> Sub<Node<T>> for Node<T> {
-    type Output = Node<T>;
+impl<T: RealElement + From<f64>, P: GraphPtr> Sub<GenericNode<T, P>> for GenericNode<T, P> {
+    type Output = GenericNode<T, P>;
 
-    fn sub(self, mut rhs: Node<T>) -> Self::Output {
-        rhs = rhs * Node::from(-1.0);
-        NodeContent::Sum(self.val() + rhs.val(), None, (self, rhs)).into()
+    fn sub(self, mut rhs: GenericNode<T, P>) -> Self::Output {
+        rhs = rhs * GenericNode::from(-1.0);
+        GenericNode::from_op(NodeContent::Sum(self.val() + rhs.val(), None, (self, rhs)))
     }
 }
 
-impl<T: RealElement + From<f64>> Sub<NodeContent<T>> for NodeContent<T> {
-    type Output = NodeContent<T>;
+impl<T: RealElement + From<f64>, P: GraphPtr> Sub<NodeContent<T, P>> for NodeContent<T, P> {
+    type Output = NodeContent<T, P>;
 
-    fn sub(self, mut rhs: NodeContent<T>) -> NodeContent<T> {
+    fn sub(self, mut rhs: NodeContent<T, P>) -> NodeContent<T, P> {
         rhs = rhs * NodeContent::from(-1.0);
         NodeContent::Sum(
             self.val().clone() + rhs.val().clone(),
@@ -240,10 +478,10 @@ impl<T: RealElement + From<f64>> Sub<NodeContent<T>> for NodeContent<T> {
     }
 }
 
-impl<T: RealElement + From<f64>> Mul<NodeContent<T>> for NodeContent<T> {
-    type Output = NodeContent<T>;
+impl<T: RealElement + From<f64>, P: GraphPtr> Mul<NodeContent<T, P>> for NodeContent<T, P> {
+    type Output = NodeContent<T, P>;
 
-    fn mul(self, rhs: NodeContent<T>) -> NodeContent<T> {
+    fn mul(self, rhs: NodeContent<T, P>) -> NodeContent<T, P> {
         NodeContent::Prod(
             self.val().clone() * rhs.val().clone(),
             None,
@@ -252,18 +490,18 @@ impl<T: RealElement + From<f64>> Mul<NodeContent<T>> for NodeContent<T> {
     }
 }
 
-impl<T: RealElement + From<f64>> Mul<Node<T>> for Node<T> {
-    type Output = Node<T>;
+impl<T: RealElement + From<f64>, P: GraphPtr> Mul<GenericNode<T, P>> for GenericNode<T, P> {
+    type Output = GenericNode<T, P>;
 
-    fn mul(self, rhs: Node<T>) -> Self::Output {
-        NodeContent::Prod(self.val() * rhs.val(), None, (self, rhs)).into()
+    fn mul(self, rhs: GenericNode<T, P>) -> Self::Output {
+        GenericNode::from_op(NodeContent::Prod(self.val() * rhs.val(), None, (self, rhs)))
     }
 }
 
-impl<T: RealElement + From<f64>> Div<NodeContent<T>> for NodeContent<T> {
-    type Output = NodeContent<T>;
+impl<T: RealElement + From<f64>, P: GraphPtr> Div<NodeContent<T, P>> for NodeContent<T, P> {
+    type Output = NodeContent<T, P>;
 
-    fn div(self, rhs: NodeContent<T>) -> NodeContent<T> {
+    fn div(self, rhs: NodeContent<T, P>) -> NodeContent<T, P> {
         // Same division by zero rules as standard division operator.
         NodeContent::Quot(
             self.val().clone() / rhs.val().clone(),
@@ -273,40 +511,40 @@ impl<T: RealElement + From<f64>> Div<NodeContent<T>> for NodeContent<T> {
     }
 }
 
-impl<T: RealElement + From<f64>> Div<Node<T>> for Node<T> {
-    type Output = Node<T>;
+impl<T: RealElement + From<f64>, P: GraphPtr> Div<GenericNode<T, P>> for GenericNode<T, P> {
+    type Output = GenericNode<T, P>;
 
-    fn div(self, rhs: Node<T>) -> Self::Output {
-        NodeContent::Quot(self.val() / rhs.val(), None, (self, rhs)).into()
+    fn div(self, rhs: GenericNode<T, P>) -> Self::Output {
+        GenericNode::from_op(NodeContent::Quot(self.val() / rhs.val(), None, (self, rhs)))
     }
 }
 
-impl<T: RealElement + From<f64>> Exp for NodeContent<T> {
+impl<T: RealElement + From<f64>, P: GraphPtr> Exp for NodeContent<T, P> {
     fn exp(self) -> Self {
         NodeContent::Exp(self.val().clone().exp(), None, self.into())
     }
 }
 
-impl<T: RealElement + From<f64>> Exp for Node<T> {
+impl<T: RealElement + From<f64>, P: GraphPtr> Exp for GenericNode<T, P> {
     fn exp(self) -> Self {
-        NodeContent::Exp(self.val().exp(), None, self).into()
+        GenericNode::from_op(NodeContent::Exp(self.val().exp(), None, self))
     }
 }
 
-impl<T: RealElement + From<f64>> Ln for NodeContent<T> {
+impl<T: RealElement + From<f64>, P: GraphPtr> Ln for NodeContent<T, P> {
     fn ln(self) -> Self {
         NodeContent::Exp(self.val().clone().ln(), None, self.into())
     }
 }
 
-impl<T: RealElement + From<f64>> Ln for Node<T> {
+impl<T: RealElement + From<f64>, P: GraphPtr> Ln for GenericNode<T, P> {
     fn ln(self) -> Self {
-        NodeContent::Ln(self.val().ln(), None, self).into()
+        GenericNode::from_op(NodeContent::Ln(self.val().ln(), None, self))
     }
 }
 
-impl<T: RealElement + From<f64>> Pow for NodeContent<T> {
-    fn pow(self, exponent: NodeContent<T>) -> NodeContent<T> {
+impl<T: RealElement + From<f64>, P: GraphPtr> Pow for NodeContent<T, P> {
+    fn pow(self, exponent: NodeContent<T, P>) -> NodeContent<T, P> {
         NodeContent::Pow(
             self.val().clone().pow(exponent.val().clone()), // Note: unnecessary clone of exp.val() here?
             None,
@@ -315,37 +553,49 @@ impl<T: RealElement + From<f64>> Pow for NodeContent<T> {
     }
 }
 
-impl<T: RealElement + From<f64>> Pow for Node<T> {
-    fn pow(self, exponent: Node<T>) -> Node<T> {
-        NodeContent::Pow(self.val().pow(exponent.val()), None, (self, exponent)).into()
+impl<T: RealElement + From<f64>, P: GraphPtr> Pow for GenericNode<T, P> {
+    fn pow(self, exponent: GenericNode<T, P>) -> GenericNode<T, P> {
+        GenericNode::from_op(NodeContent::Pow(
+            self.val().pow(exponent.val()),
+            None,
+            (self, exponent),
+        ))
     }
 }
 
-impl<T: RealElement> AddAssign for NodeContent<T> {
+impl<T: RealElement, P: GraphPtr> AddAssign for NodeContent<T, P> {
     fn add_assign(&mut self, _rhs: Self) {
         panic!("Unexpected call to AddAssign on a Node.")
     }
 }
 
-impl<T: RealElement> AddAssign for Node<T> {
+impl<T: RealElement, P: GraphPtr> AddAssign for GenericNode<T, P> {
     fn add_assign(&mut self, _rhs: Self) {
         *self = self.clone() + _rhs;
     }
 }
 
-impl<T: RealElement> Display for NodeContent<T> {
+impl<T: RealElement, P: GraphPtr> Display for NodeContent<T, P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Node: {:?}", self)
     }
 }
 
-impl<T: RealElement> Display for Node<T> {
+impl<T: RealElement, P: GraphPtr> Display for GenericNode<T, P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Node: {:?}", P::borrow(&self.ptr).content)
+    }
+}
+
+impl<T: Debug, P: GraphPtr> Debug for GenericNode<T, P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Node: {:?}", self.ptr.deref().borrow())
+        f.debug_struct("Node")
+            .field("ptr", &P::borrow(&self.ptr).content)
+            .finish()
     }
 }
 
-impl<T: RealElement> Clone for Node<T> {
+impl<T: RealElement, P: GraphPtr> Clone for GenericNode<T, P> {
     fn clone(&self) -> Self {
         Self {
             ptr: self.ptr.clone(),
@@ -353,7 +603,7 @@ impl<T: RealElement> Clone for Node<T> {
     }
 }
 
-impl<T: RealElement> PartialEq for NodeContent<T> {
+impl<T: RealElement, P: GraphPtr> PartialEq for NodeContent<T, P> {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Self::Sum(l0, l1, l2), Self::Sum(r0, r1, r2)) => l0 == r0 && l1 == r1 && l2 == r2,
@@ -368,9 +618,11 @@ impl<T: RealElement> PartialEq for NodeContent<T> {
     }
 }
 
-impl<T: RealElement> PartialEq for Node<T> {
+impl<T: RealElement, P: GraphPtr> PartialEq for GenericNode<T, P> {
     fn eq(&self, other: &Self) -> bool {
-        self.ptr == other.ptr
+        // Compare the node contents, as `Rc<RefCell<_>>` equality does.
+        P::ptr_eq(&self.ptr, &other.ptr)
+            || P::borrow(&self.ptr).content == P::borrow(&other.ptr).content
     }
 }
 
@@ -380,19 +632,19 @@ impl<T: RealElement> PartialEq for Node<T> {
 //     }
 // }
 
-impl<T: RealElement> From<f64> for NodeContent<T> {
+impl<T: RealElement, P: GraphPtr> From<f64> for NodeContent<T, P> {
     fn from(value: f64) -> Self {
         NodeContent::new(value.into(), None)
     }
 }
 
-impl<T: RealElement> From<f64> for Node<T> {
+impl<T: RealElement, P: GraphPtr> From<f64> for GenericNode<T, P> {
     fn from(value: f64) -> Self {
-        NodeContent::<T>::from(value).into()
+        NodeContent::<T, P>::from(value).into()
     }
 }
 
-impl<T: RealElement> Zero for NodeContent<T> {
+impl<T: RealElement, P: GraphPtr> Zero for NodeContent<T, P> {
     fn zero() -> Self {
         NodeContent::new(0_f64.into(), None)
     }
@@ -402,9 +654,9 @@ impl<T: RealElement> Zero for NodeContent<T> {
     }
 }
 
-impl<T: RealElement> Zero for Node<T> {
+impl<T: RealElement, P: GraphPtr> Zero for GenericNode<T, P> {
     fn zero() -> Self {
-        NodeContent::<T>::zero().into()
+        NodeContent::<T, P>::zero().into()
     }
 
     fn is_zero(&self) -> bool {
@@ -412,23 +664,43 @@ impl<T: RealElement> Zero for Node<T> {
     }
 }
 
-impl<T: RealElement + From<f64>> Element for Node<T> {}
+impl<T: RealElement + From<f64>, P: GraphPtr> Element for GenericNode<T, P> {}
 
-impl<T: RealElement + From<f64>> RealElement for Node<T> {
+impl<T: RealElement + From<f64>, P: GraphPtr> RealElement for GenericNode<T, P> {
     fn neg_inf() -> Self {
-        Node::new((-f64::INFINITY).into(), None)
+        GenericNode::new((-f64::INFINITY).into(), None)
+    }
+}
+
+impl<P: GraphPtr> Piecewise for GenericNode<f64, P> {
+    fn piecewise(
+        self,
+        positive: impl FnOnce(Self) -> Self,
+        non_positive: impl FnOnce(Self) -> Self,
+    ) -> Self {
+        if self.val() > 0. {
+            positive(self)
+        } else {
+            non_positive(self)
+        }
     }
 }
 
-impl Into<f64> for Node<f64> {
-    fn into(self) -> f64 {
-        self.val()
+impl<P: GraphPtr> SetValue for GenericNode<f64, P> {
+    fn set_value(&mut self, value: f64) {
+        self.set_val(value)
     }
 }
 
-impl From<usize> for Node<f64> {
+impl<P: GraphPtr> From<GenericNode<f64, P>> for f64 {
+    fn from(value: GenericNode<f64, P>) -> Self {
+        value.val()
+    }
+}
+
+impl<P: GraphPtr> From<usize> for GenericNode<f64, P> {
     fn from(value: usize) -> Self {
-        Node::new(value as f64, None)
+        GenericNode::new(value as f64, None)
     }
 }
 
@@ -652,6 +924,7 @@ mod tests {
         let node2 = Node::new(2.2, None);
 
         let mut node = node1.clone() + node2.clone();
+        node.retain_grad();
 
         assert!(node.grad().is_none());
         assert!(node1.grad().is_none());
@@ -673,6 +946,7 @@ mod tests {
         let node2 = Node::new(2.2, None);
 
         let mut node = node1.clone() * node2.clone();
+        node.retain_grad();
 
         assert!(node.grad().is_none());
         assert!(node1.grad().is_none());
@@ -697,6 +971,8 @@ mod tests {
 
         let node_d = node_a.clone() + node_b.clone();
         let mut node_f = node_d.clone() * node_c.clone();
+        node_d.retain_grad();
+        node_f.retain_grad();
 
         // Check all grads are None initially.
         assert!(node_f.grad().is_none());
@@ -738,6 +1014,11 @@ mod tests {
         let node_2x_squared = node_x_squared.clone() * node_2_.clone();
 
         let mut node_f = node_exp_5x.clone() + node_2x_squared.clone();
+        node_f.retain_grad();
+        node_exp_5x.retain_grad();
+        node_2x_squared.retain_grad();
+        node_x_squared.retain_grad();
+        node_5x.retain_grad();
 
         node_f.backward(1.0);
 
@@ -809,6 +1090,166 @@ mod tests {
         assert_eq!(grad1, 3.0 * value.clone().pow(2.0));
     }
 
+    #[test]
+    fn test_backward_on_prod_sum_sync_node() {
+        let node_a = SyncNode::new(3.0, None);
+        let node_b = SyncNode::new(2.0, None);
+        let node_c = SyncNode::new(2.0, None);
+
+        let node_d = node_a.clone() + node_b.clone();
+        let mut node_f = node_d.clone() * node_c.clone();
+        node_d.retain_grad();
+        node_f.retain_grad();
+        node_f.backward(10.0);
+
+        assert_eq!(node_f.grad().unwrap(), 10.0_f64);
+        assert_eq!(node_d.grad().unwrap(), 20.0_f64);
+        assert_eq!(node_c.grad().unwrap(), 50.0_f64);
+        assert_eq!(node_a.grad().unwrap(), 20.0_f64);
+        assert_eq!(node_b.grad().unwrap(), 20.0_f64);
+    }
+
+    #[test]
+    fn test_sync_node_is_send_and_sync() {
+        fn assert_send_sync<S: Send + Sync>() {}
+        assert_send_sync::<SyncNode<f64>>();
+        assert_send_sync::<tensors::TensorImpl<SyncNode<f64>>>();
+    }
+
+    #[test]
+    fn test_sync_node_backward_on_another_thread() {
+        let node_x = SyncNode::new(3.0, None);
+        let node_x_clone = node_x.clone();
+        let handle = std::thread::spawn(move || {
+            let mut node_xx = node_x_clone.clone() * node_x_clone;
+            node_xx.backward(1.0);
+            node_xx.val()
+        });
+
+        assert_eq!(handle.join().unwrap(), 9.0_f64);
+        // The graph is shared, so the grad is visible from this thread.
+        assert_eq!(node_x.grad().unwrap(), 6.0_f64);
+    }
+
+    /// f(x, y) = x^3 . ln(y) + exp(x . y) / y
+    fn two_var_fn(x: &Node<f64>, y: &Node<f64>) -> Node<f64> {
+        x.clone().pow(Node::new(3.0, None)) * y.clone().ln()
+            + (x.clone() * y.clone()).exp() / y.clone()
+    }
+
+    fn two_var_fn_grad(x: f64, y: f64) -> Vec<f64> {
+        let node_x = Node::new(x, None);
+        let node_y = Node::new(y, None);
+        two_var_fn(&node_x, &node_y)
+            .grad_wrt(&[node_x, node_y], false)
+            .iter()
+            .map(|grad| grad.val())
+            .collect()
+    }
+
+    #[test]
+    fn test_grad_wrt_matches_backward() {
+        let node_x = Node::new(0.7, None);
+        let node_y = Node::new(1.3, None);
+        let mut node_f = two_var_fn(&node_x, &node_y);
+
+        let grads = node_f.grad_wrt(&[node_x.clone(), node_y.clone()], false);
+        // grad_wrt leaves the grads untouched.
+        assert!(node_x.grad().is_none());
+
+        node_f.backward(1.0);
+        assert!(f64::abs(grads[0].val() - node_x.grad().unwrap()) < 1e-12);
+        assert!(f64::abs(grads[1].val() - node_y.grad().unwrap()) < 1e-12);
+    }
+
+    #[test]
+    fn test_grad_wrt_without_graph_returns_leaves() {
+        let node_x = Node::new(0.7, None);
+        let node_unused = Node::new(2.0, None);
+        let node_f = node_x.clone() * node_x.clone();
+        let grads = node_f.grad_wrt(&[node_x.clone(), node_unused], false);
+        assert!(grads[0].is_leaf());
+        assert_eq!(grads[0].val(), 1.4);
+        assert!(grads[1].is_zero());
+
+        let grads = node_f.grad_wrt(&[node_x], true);
+        assert!(!grads[0].is_leaf());
+        assert_eq!(grads[0].val(), 1.4);
+    }
+
+    #[test]
+    fn test_second_derivative_matches_finite_difference() {
+        let (x, y, h) = (0.7, 1.3, 1e-5);
+
+        let node_x = Node::new(x, None);
+        let node_y = Node::new(y, None);
+        let node_f = two_var_fn(&node_x, &node_y);
+        let grads = node_f.grad_wrt(&[node_x.clone(), node_y.clone()], true);
+
+        // First derivatives against central differences of f.
+        let f = |x: f64, y: f64| two_var_fn(&Node::new(x, None), &Node::new(y, None)).val();
+        let df_dx = (f(x + h, y) - f(x - h, y)) / (2.0 * h);
+        let df_dy = (f(x, y + h) - f(x, y - h)) / (2.0 * h);
+        assert!(f64::abs(grads[0].val() - df_dx) < 1e-6);
+        assert!(f64::abs(grads[1].val() - df_dy) < 1e-6);
+
+        // Second derivatives against central differences of the first derivatives.
+        let d2f_dx2 = grads[0].grad_wrt(&[node_x.clone(), node_y.clone()], false);
+        let d2f_dy2 = grads[1].grad_wrt(&[node_x.clone(), node_y.clone()], false);
+        let fd_x = (two_var_fn_grad(x + h, y)[0] - two_var_fn_grad(x - h, y)[0]) / (2.0 * h);
+        let fd_xy = (two_var_fn_grad(x, y + h)[0] - two_var_fn_grad(x, y - h)[0]) / (2.0 * h);
+        let fd_y = (two_var_fn_grad(x, y + h)[1] - two_var_fn_grad(x, y - h)[1]) / (2.0 * h);
+        assert!(f64::abs(d2f_dx2[0].val() - fd_x) < 1e-5);
+        assert!(f64::abs(d2f_dx2[1].val() - fd_xy) < 1e-5);
+        // Mixed partials are symmetric.
+        assert!(f64::abs(d2f_dy2[0].val() - d2f_dx2[1].val()) < 1e-10);
+        assert!(f64::abs(d2f_dy2[1].val() - fd_y) < 1e-5);
+    }
+
+    #[test]
+    fn test_hessian_vector_product() {
+        let (x, y, h) = (0.7, 1.3, 1e-5);
+        let v = [1.0, -2.0];
+
+        let node_x = Node::new(x, None);
+        let node_y = Node::new(y, None);
+        let grads = two_var_fn(&node_x, &node_y).grad_wrt(&[node_x.clone(), node_y.clone()], true);
+
+        // Hv = grad(grad(f) . v)
+        let grad_dot_v =
+            grads[0].clone() * Node::new(v[0], None) + grads[1].clone() * Node::new(v[1], None);
+        let hvp = grad_dot_v.grad_wrt(&[node_x, node_y], false);
+
+        let grad_plus = two_var_fn_grad(x + h * v[0], y + h * v[1]);
+        let grad_minus = two_var_fn_grad(x - h * v[0], y - h * v[1]);
+        for i in 0..2 {
+            let fd = (grad_plus[i] - grad_minus[i]) / (2.0 * h);
+            assert!(f64::abs(hvp[i].val() - fd) < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_gradient_penalty_backward() {
+        // loss(x, y) = f(x, y) + |grad f(x, y)|^2, with the grads of loss populated by backward.
+        let loss = |x: &Node<f64>, y: &Node<f64>| {
+            let grads = two_var_fn(x, y).grad_wrt(&[x.clone(), y.clone()], true);
+            two_var_fn(x, y)
+                + grads[0].clone() * grads[0].clone()
+                + grads[1].clone() * grads[1].clone()
+        };
+        let (x, y, h) = (0.7, 1.3, 1e-5);
+
+        let node_x = Node::new(x, None);
+        let node_y = Node::new(y, None);
+        loss(&node_x, &node_y).backward(1.0);
+
+        let loss_val = |x: f64, y: f64| loss(&Node::new(x, None), &Node::new(y, None)).val();
+        let fd_x = (loss_val(x + h, y) - loss_val(x - h, y)) / (2.0 * h);
+        let fd_y = (loss_val(x, y + h) - loss_val(x, y - h)) / (2.0 * h);
+        assert!(f64::abs(node_x.grad().unwrap() - fd_x) < 1e-4);
+        assert!(f64::abs(node_y.grad().unwrap() - fd_y) < 1e-4);
+    }
+
     #[test]
     fn test_cyclic_graph() {
         let val_a = 1.1;
@@ -850,4 +1291,132 @@ mod tests {
         assert_eq!(grad_d1, grad_d2);
         assert_eq!(grad_d1, 1.0);
     }
+
+    #[test]
+    fn test_intermediate_grads_not_retained_by_default() {
+        let node_x = Node::new(3.0, None);
+        let node_xx = node_x.clone() * node_x.clone();
+        let mut node_f = node_xx.clone() + Node::new(1.0, None);
+        node_f.backward(1.0);
+
+        assert!(node_f.grad().is_none());
+        assert!(node_xx.grad().is_none());
+        assert_eq!(node_x.grad().unwrap(), 6.0_f64);
+    }
+
+    #[test]
+    fn test_hook_modifies_propagated_grad() {
+        let node_x = Node::new(3.0, None);
+        let node_xx = node_x.clone() * node_x.clone();
+        node_xx.retain_grad();
+        // Clip the grad flowing through x^2.
+        node_xx.register_hook(|grad: f64| grad.clamp(-1.0, 1.0));
+        let mut node_f = node_xx.clone() * Node::new(10.0, None);
+        node_f.backward(1.0);
+
+        // The retained grad is the one returned by the hook.
+        assert_eq!(node_xx.grad().unwrap(), 1.0_f64);
+        assert_eq!(node_x.grad().unwrap(), 6.0_f64);
+    }
+
+    #[test]
+    fn test_hook_called_once_with_total_grad() {
+        use std::sync::{Arc, Mutex};
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+        let node_x = Node::new(3.0, None);
+        node_x.register_hook(move |grad: f64| {
+            seen_clone.lock().unwrap().push(grad);
+            grad
+        });
+        node_x.register_hook(|grad: f64| 2.0 * grad);
+        let node_y = Node::new(2.0, None);
+        // x is used by three ops.
+        let mut node_f = node_x.clone() * node_y.clone() + node_x.clone() + node_x.clone().exp();
+        node_f.backward(1.0);
+
+        let expected = 2.0 + 1.0 + 3.0_f64.exp();
+        assert_eq!(*seen.lock().unwrap(), vec![expected]);
+        // Hooks run in order, so the stored grad is doubled.
+        assert_eq!(node_x.grad().unwrap(), 2.0 * expected);
+    }
+
+    #[test]
+    fn test_local_hook() {
+        let node_x = Node::new(3.0, None);
+        let node_scale = Node::new(0.5, None);
+        node_x.register_local_hook({
+            let node_scale = node_scale.clone();
+            move |grad: f64| grad * node_scale.val()
+        });
+        (node_x.clone() * node_x.clone()).backward(1.0);
+        assert_eq!(node_x.grad().unwrap(), 3.0_f64);
+    }
+
+    #[test]
+    fn test_hook_on_sync_node() {
+        let node_x = SyncNode::new(3.0, None);
+        node_x.register_hook(|grad: f64| -grad);
+        let handle = std::thread::spawn({
+            let node_x = node_x.clone();
+            move || (node_x.clone() * node_x).backward(1.0)
+        });
+        handle.join().unwrap();
+        assert_eq!(node_x.grad().unwrap(), -6.0_f64);
+    }
+
+    #[test]
+    fn test_release_graph() {
+        let node_x = Node::new(3.0, None);
+        let node_xx = node_x.clone() * node_x.clone();
+        node_xx.retain_grad();
+        let mut node_f = node_xx.clone().exp();
+        node_f.backward(1.0);
+        let grad_x = node_x.grad().unwrap();
+
+        node_f.release_graph();
+        assert!(node_f.is_leaf());
+        assert!(node_xx.is_leaf());
+        assert!(node_f.children().is_empty());
+        // Values and grads are kept.
+        assert_eq!(node_f.val(), 9.0_f64.exp());
+        assert_eq!(node_xx.grad().unwrap(), 9.0_f64.exp());
+
+        // A second backward no longer reaches x.
+        node_f.backward(1.0);
+        assert_eq!(node_x.grad().unwrap(), grad_x);
+    }
+
+    #[test]
+    fn test_release_graph_drops_hooks() {
+        let node_x = Node::new(3.0, None);
+        let node_xx = node_x.clone() * node_x.clone();
+        let captured = node_xx.clone().ln();
+        let weak_captured = Rc::downgrade(&captured.ptr);
+        let weak_xx = Rc::downgrade(&node_xx.ptr);
+        // A cycle: the captured node holds `node_xx`, whose hook holds the captured node.
+        node_xx.register_local_hook(move |grad| grad + captured.val());
+        let mut node_f = node_xx.exp();
+        node_f.backward(1.0);
+
+        assert!(weak_captured.upgrade().is_some());
+        node_f.release_graph();
+        assert!(weak_captured.upgrade().is_none());
+        assert!(weak_xx.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_release_graph_keeps_leaf_hooks() {
+        // Eg. clipping the gradient of a parameter, across training steps.
+        let node_w = Node::new(3.0, None);
+        node_w.register_local_hook(|grad: f64| grad.min(1.0));
+        for _ in 0..2 {
+            node_w.clone().set_grad(0.0);
+            let mut node_f = node_w.clone() * node_w.clone();
+            node_f.backward(1.0);
+            assert_eq!(node_w.grad(), Some(1.0));
+            node_f.release_graph();
+        }
+    }
 }
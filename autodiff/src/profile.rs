@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use interfaces::tensors::RealElement;
+
+use crate::graph_ptr::GraphPtr;
+use crate::node::GenericNode;
+
+/// Statistics of the nodes of one op (`NodeContent` variant) in a computation graph.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpStats {
+    pub count: usize,
+    /// See `GenericNode::heap_bytes()`.
+    pub heap_bytes: usize,
+    /// Length of the longest path from the root to a node of this op.
+    pub max_depth: usize,
+    /// Number of nodes used as an operand more than once, ie. roots of shared subgraphs.
+    pub shared: usize,
+    /// Largest number of times a node of this op is used as an operand.
+    pub max_fan_out: usize,
+}
+
+impl OpStats {
+    fn add(&mut self, other: &OpStats) {
+        self.count += other.count;
+        self.heap_bytes += other.heap_bytes;
+        self.max_depth = self.max_depth.max(other.max_depth);
+        self.shared += other.shared;
+        self.max_fan_out = self.max_fan_out.max(other.max_fan_out);
+    }
+}
+
+/// Statistics of the computation graph below a root node (eg. a loss), for finding where the
+/// time and memory of a training step go. `Display` gives a one-line summary suitable for logging
+/// each iteration; the alternate form (`{:#}`) adds one line per op.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GraphStats {
+    /// Totals over all ops.
+    pub total: OpStats,
+    /// Keyed by op name (see `GenericNode::op_name()`).
+    pub per_op: BTreeMap<&'static str, OpStats>,
+    /// Wall time of `backward()`, when collected with `profile_backward()`.
+    pub backward_time: Option<Duration>,
+}
+
+impl GraphStats {
+    /// Walk the graph below `root`, visiting every node once.
+    pub fn of<T: RealElement + From<f64>, P: GraphPtr>(root: &GenericNode<T, P>) -> Self {
+        // Every node comes before its children, so its depth is final when it is visited.
+        let nodes: Vec<GenericNode<T, P>> = root.topological_order().into_iter().rev().collect();
+        let mut depths: HashMap<usize, usize> = HashMap::from([(root.id(), 0)]);
+        let mut fan_outs: HashMap<usize, usize> = HashMap::new();
+        for node in nodes.iter() {
+            let depth = depths[&node.id()];
+            for child in node.children() {
+                let child_depth = depths.entry(child.id()).or_insert(0);
+                *child_depth = (*child_depth).max(depth + 1);
+                *fan_outs.entry(child.id()).or_insert(0) += 1;
+            }
+        }
+
+        let mut per_op: BTreeMap<&'static str, OpStats> = BTreeMap::new();
+        for node in nodes.iter() {
+            let fan_out = fan_outs.get(&node.id()).copied().unwrap_or(0);
+            per_op.entry(node.op_name()).or_default().add(&OpStats {
+                count: 1,
+                heap_bytes: node.heap_bytes(),
+                max_depth: depths[&node.id()],
+                shared: usize::from(fan_out > 1),
+                max_fan_out: fan_out,
+            });
+        }
+        let mut total = OpStats::default();
+        for op_stats in per_op.values() {
+            total.add(op_stats);
+        }
+
+        GraphStats {
+            total,
+            per_op,
+            backward_time: None,
+        }
+    }
+}
+
+impl Display for GraphStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "graph: {}", self.total)?;
+        if let Some(backward_time) = self.backward_time {
+            write!(f, ", backward: {:?}", backward_time)?;
+        }
+        if f.alternate() {
+            for (op_name, op_stats) in self.per_op.iter() {
+                write!(f, "\n  {}: {}", op_name, op_stats)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for OpStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} bytes, max depth {}, {} shared (max fan-out {})",
+            self.count, self.heap_bytes, self.max_depth, self.shared, self.max_fan_out
+        )
+    }
+}
+
+/// Collect the statistics of the graph below `root`, then run `root.backward(grad)` and record
+/// its wall time.
+pub fn profile_backward<T: RealElement + From<f64>, P: GraphPtr>(
+    root: &mut GenericNode<T, P>,
+    grad: T,
+) -> GraphStats {
+    let mut stats = GraphStats::of(root);
+    let start = Instant::now();
+    root.backward(grad);
+    stats.backward_time = Some(start.elapsed());
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use interfaces::utils::Exp;
+
+    use super::*;
+    use crate::node::{Node, SyncNode};
+
+    #[test]
+    fn test_graph_stats() {
+        // f = exp(x . y) + x . y, with x . y shared and x used once in the product.
+        let x = Node::new(2.0, None);
+        let y = Node::new(3.0, None);
+        let xy = x.clone() * y.clone();
+        let f = xy.clone().exp() + xy.clone();
+
+        let stats = GraphStats::of(&f);
+        assert_eq!(stats.total.count, 5);
+        assert_eq!(stats.total.max_depth, 3);
+        assert_eq!(stats.total.shared, 1);
+        assert_eq!(stats.total.max_fan_out, 2);
+        assert_eq!(stats.total.heap_bytes, 5 * x.heap_bytes());
+
+        assert_eq!(
+            stats.per_op["Prod"],
+            OpStats {
+                count: 1,
+                heap_bytes: xy.heap_bytes(),
+                max_depth: 2,
+                shared: 1,
+                max_fan_out: 2,
+            }
+        );
+        assert_eq!(stats.per_op["Leaf"].count, 2);
+        assert_eq!(stats.per_op["Leaf"].max_depth, 3);
+        assert_eq!(stats.per_op["Leaf"].shared, 0);
+        assert_eq!(stats.per_op["Sum"].max_depth, 0);
+        assert!(!stats.per_op.contains_key("Quot"));
+        assert!(stats.backward_time.is_none());
+    }
+
+    #[test]
+    fn test_fan_out_counts_repeated_operands() {
+        let x = Node::new(2.0, None);
+        let f = x.clone() * x.clone() + x.clone();
+        let stats = GraphStats::of(&f);
+        assert_eq!(stats.per_op["Leaf"].max_fan_out, 3);
+        assert_eq!(stats.per_op["Leaf"].shared, 1);
+    }
+
+    #[test]
+    fn test_hooks_count_towards_heap_bytes() {
+        let x = Node::new(2.0, None);
+        let bytes = x.heap_bytes();
+        x.register_hook(|grad: f64| grad);
+        assert!(x.heap_bytes() > bytes);
+    }
+
+    #[test]
+    fn test_profile_backward() {
+        let x = SyncNode::new(2.0, None);
+        let mut f = x.clone() * x.clone();
+        let stats = profile_backward(&mut f, 1.0);
+        assert_eq!(x.grad().unwrap(), 4.0);
+        assert_eq!(stats.total.count, 2);
+        assert!(stats.backward_time.is_some());
+    }
+
+    #[test]
+    fn test_display() {
+        let x = Node::new(2.0, None);
+        let f = x.clone() * x.clone();
+        let mut stats = GraphStats::of(&f);
+        let bytes = x.heap_bytes();
+        assert_eq!(
+            stats.to_string(),
+            format!(
+                "graph: 2 nodes, {} bytes, max depth 1, 1 shared (max fan-out 2)",
+                2 * bytes
+            )
+        );
+
+        stats.backward_time = Some(Duration::from_millis(3));
+        let lines: Vec<String> = format!("{:#}", stats).lines().map(String::from).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with(", backward: 3ms"));
+        assert!(lines[1].starts_with("  Leaf: 1 nodes"));
+        assert!(lines[2].starts_with("  Prod: 1 nodes"));
+    }
+}
@@ -0,0 +1,475 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Display};
+use std::ops::{Add, AddAssign, Div, Mul, Sub};
+use std::rc::Rc;
+
+use interfaces::tensors::{Element, Piecewise, RealElement, SetValue};
+use interfaces::utils::{Exp, Ln, Pow};
+use num_traits::Zero;
+
+use crate::no_grad::is_grad_enabled;
+
+/// One recorded value: either a leaf (an input, a parameter or a constant) or the result of an
+/// op, with the partial derivatives of the result with respect to its (up to two) operands.
+#[derive(Debug, Clone)]
+struct Entry {
+    val: f64,
+    grad: Option<f64>,
+    parents: [(usize, f64); 2],
+    num_parents: usize,
+    /// The generation of the tape when the entry was recorded (see `Entries`).
+    generation: u64,
+}
+
+/// The entries of a `Tape`, and its generation: the number of truncations that dropped entries.
+/// A `Var` records the generation of its entry, which tells it apart from a later entry reusing
+/// its index after a truncation.
+#[derive(Default)]
+struct Entries {
+    entries: Vec<Entry>,
+    generation: u64,
+}
+
+/// A Wengert list: an arena in which every value computed with `Var`s is appended, in the order
+/// of computation. Operands are always recorded before their results, so backward is a single
+/// sweep over the arena in reverse, without the per-node allocation and pointer chasing of
+/// `Node`.
+///
+/// Each thread records on its own tape, returned by `Tape::current()`.
+#[derive(Clone)]
+pub struct Tape(Rc<RefCell<Entries>>);
+
+thread_local! {
+    static CURRENT_TAPE: Tape = Tape(Rc::new(RefCell::new(Entries::default())));
+}
+
+impl Tape {
+    /// The tape of the current thread.
+    pub fn current() -> Tape {
+        CURRENT_TAPE.with(|tape| tape.clone())
+    }
+
+    /// Number of recorded entries.
+    pub fn len(&self) -> usize {
+        self.0.borrow().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().entries.is_empty()
+    }
+
+    /// Drop every entry recorded after the first `len`, eg. the ops of the last training step,
+    /// keeping the parameters recorded before them. A tape only grows otherwise, so call this
+    /// once per step with the length of the tape after the model was built.
+    ///
+    /// Using a `Var` of a dropped entry afterwards panics, even once its index is reused by a new
+    /// entry.
+    pub fn truncate(&self, len: usize) {
+        let mut tape = self.0.borrow_mut();
+        if len < tape.entries.len() {
+            tape.entries.truncate(len);
+            tape.generation += 1;
+        }
+    }
+
+    fn push(&self, val: f64, parents: &[(usize, f64)]) -> Var {
+        let mut tape = self.0.borrow_mut();
+        let generation = tape.generation;
+        let mut entry = Entry {
+            val,
+            grad: None,
+            parents: [(0, 0.0); 2],
+            num_parents: 0,
+            generation,
+        };
+        // Without grad, results are recorded as leaves.
+        if is_grad_enabled() {
+            entry.parents[..parents.len()].copy_from_slice(parents);
+            entry.num_parents = parents.len();
+        }
+        tape.entries.push(entry);
+        Var {
+            tape: self.clone(),
+            idx: tape.entries.len() - 1,
+            generation,
+        }
+    }
+}
+
+/// Handle to a value recorded on a `Tape`: a drop-in alternative to `Node<f64>` as the element
+/// of a `TensorImpl`. Cloning a `Var` clones the handle, not the value.
+///
+/// All the `Var`s combined by an op must be on the same tape, ie. created on the same thread.
+#[derive(Clone)]
+pub struct Var {
+    tape: Tape,
+    idx: usize,
+    generation: u64,
+}
+
+impl Var {
+    /// Record a leaf on the tape of the current thread.
+    pub fn new(val: f64) -> Self {
+        Tape::current().push(val, &[])
+    }
+
+    fn entry<R>(&self, f: impl FnOnce(&Entry) -> R) -> R {
+        let tape = self.tape.0.borrow();
+        let entry = tape
+            .entries
+            .get(self.idx)
+            .filter(|entry| entry.generation == self.generation)
+            .expect("This Var was dropped by `Tape::truncate()`.");
+        f(entry)
+    }
+
+    fn entry_mut<R>(&self, f: impl FnOnce(&mut Entry) -> R) -> R {
+        let mut tape = self.tape.0.borrow_mut();
+        let generation = self.generation;
+        let entry = tape
+            .entries
+            .get_mut(self.idx)
+            .filter(|entry| entry.generation == generation)
+            .expect("This Var was dropped by `Tape::truncate()`.");
+        f(entry)
+    }
+
+    pub fn val(&self) -> f64 {
+        self.entry(|entry| entry.val)
+    }
+
+    pub fn grad(&self) -> Option<f64> {
+        self.entry(|entry| entry.grad)
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.entry(|entry| entry.num_parents == 0)
+    }
+
+    pub fn set_val(&mut self, new_val: f64) {
+        self.entry_mut(|entry| entry.val = new_val)
+    }
+
+    pub fn set_grad(&mut self, new_grad: f64) {
+        self.entry_mut(|entry| entry.grad = Some(new_grad))
+    }
+
+    /// Initiate backward propagation from `self`, seeded with `grad`. Gradients are accumulated
+    /// (added) into the `grad` of the leaves that `self` depends on; as for `Node`, intermediate
+    /// results do not keep their gradient.
+    pub fn backward(&mut self, grad: f64) {
+        // The entries below a valid `Var` were recorded before it, so are valid too.
+        self.entry(|_| ());
+        let mut tape = self.tape.0.borrow_mut();
+        let entries = &mut tape.entries;
+        let len = self.idx + 1;
+        let mut adjoints = vec![0.0; len];
+        // Only the entries `self` depends on are updated.
+        let mut reached = vec![false; len];
+        adjoints[self.idx] = grad;
+        reached[self.idx] = true;
+        for idx in (0..len).rev() {
+            if !reached[idx] {
+                continue;
+            }
+            let entry = &mut entries[idx];
+            let adjoint = adjoints[idx];
+            if entry.num_parents == 0 {
+                entry.grad = Some(entry.grad.unwrap_or(0.0) + adjoint);
+            }
+            for &(parent, partial) in entry.parents[..entry.num_parents].iter() {
+                adjoints[parent] += partial * adjoint;
+                reached[parent] = true;
+            }
+        }
+    }
+
+    fn binary_op(&self, rhs: &Var, val: f64, partials: (f64, f64)) -> Var {
+        assert!(
+            Rc::ptr_eq(&self.tape.0, &rhs.tape.0),
+            "Vars from different tapes cannot be combined."
+        );
+        self.entry(|_| ());
+        rhs.entry(|_| ());
+        self.tape
+            .push(val, &[(self.idx, partials.0), (rhs.idx, partials.1)])
+    }
+
+    fn unary_op(&self, val: f64, partial: f64) -> Var {
+        self.entry(|_| ());
+        self.tape.push(val, &[(self.idx, partial)])
+    }
+}
+
+impl Debug for Var {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.entry(|entry| {
+            f.debug_struct("Var")
+                .field("idx", &self.idx)
+                .field("val", &entry.val)
+                .field("grad", &entry.grad)
+                .finish()
+        })
+    }
+}
+
+impl Display for Var {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.val())
+    }
+}
+
+/// `Var`s compare by value.
+impl PartialEq for Var {
+    fn eq(&self, other: &Self) -> bool {
+        self.val() == other.val()
+    }
+}
+
+impl Add for Var {
+    type Output = Var;
+
+    fn add(self, rhs: Var) -> Var {
+        self.binary_op(&rhs, self.val() + rhs.val(), (1.0, 1.0))
+    }
+}
+
+impl AddAssign for Var {
+    fn add_assign(&mut self, rhs: Var) {
+        *self = self.clone() + rhs;
+    }
+}
+
+impl Sub for Var {
+    type Output = Var;
+
+    fn sub(self, rhs: Var) -> Var {
+        self.binary_op(&rhs, self.val() - rhs.val(), (1.0, -1.0))
+    }
+}
+
+impl Mul for Var {
+    type Output = Var;
+
+    fn mul(self, rhs: Var) -> Var {
+        let (a, b) = (self.val(), rhs.val());
+        self.binary_op(&rhs, a * b, (b, a))
+    }
+}
+
+impl Div for Var {
+    type Output = Var;
+
+    fn div(self, rhs: Var) -> Var {
+        let (num, denom) = (self.val(), rhs.val());
+        self.binary_op(&rhs, num / denom, (1.0 / denom, -num / (denom * denom)))
+    }
+}
+
+impl Exp for Var {
+    fn exp(self) -> Var {
+        let val = self.val().exp();
+        self.unary_op(val, val)
+    }
+}
+
+impl Ln for Var {
+    fn ln(self) -> Var {
+        let x = self.val();
+        self.unary_op(x.ln(), 1.0 / x)
+    }
+}
+
+impl Pow for Var {
+    fn pow(self, exp: Var) -> Var {
+        let (b, e) = (self.val(), exp.val());
+        let val = b.powf(e);
+        // exponent . base^(exponent - 1), base^exponent . ln(base)
+        self.binary_op(&exp, val, (e * b.powf(e - 1.0), val * b.ln()))
+    }
+}
+
+impl Zero for Var {
+    fn zero() -> Self {
+        Var::new(0.0)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.val() == 0.0
+    }
+}
+
+impl Element for Var {}
+
+impl RealElement for Var {
+    fn neg_inf() -> Self {
+        Var::new(-f64::INFINITY)
+    }
+}
+
+impl From<f64> for Var {
+    fn from(value: f64) -> Self {
+        Var::new(value)
+    }
+}
+
+impl Piecewise for Var {
+    fn piecewise(
+        self,
+        positive: impl FnOnce(Self) -> Self,
+        non_positive: impl FnOnce(Self) -> Self,
+    ) -> Self {
+        if self.val() > 0. {
+            positive(self)
+        } else {
+            non_positive(self)
+        }
+    }
+}
+
+impl SetValue for Var {
+    fn set_value(&mut self, value: f64) {
+        self.set_val(value)
+    }
+}
+
+impl From<Var> for f64 {
+    fn from(value: Var) -> Self {
+        value.val()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use interfaces::tensors::{RealTensor, Tensor};
+    use tensors::TensorImpl;
+
+    use super::*;
+    use crate::no_grad::no_grad;
+    use crate::node::Node;
+
+    /// f(x, y) = x^3 . ln(y) + exp(x . y) / y - x
+    fn two_var_fn<E: RealElement>(x: E, y: E) -> E {
+        x.clone().pow(E::from(3.0)) * y.clone().ln() + (x.clone() * y.clone()).exp() / y - x
+    }
+
+    #[test]
+    fn test_matches_node() {
+        let (x, y) = (Var::new(1.5), Var::new(0.7));
+        let mut f = two_var_fn(x.clone(), y.clone());
+        f.backward(1.0);
+
+        let (node_x, node_y) = (Node::new(1.5, None), Node::new(0.7, None));
+        let mut node_f = two_var_fn(node_x.clone(), node_y.clone());
+        node_f.backward(1.0);
+
+        assert_eq!(f.val(), node_f.val());
+        assert!(f64::abs(x.grad().unwrap() - node_x.grad().unwrap()) < 1e-12);
+        assert!(f64::abs(y.grad().unwrap() - node_y.grad().unwrap()) < 1e-12);
+    }
+
+    #[test]
+    fn test_shared_operand() {
+        let x = Var::new(3.0);
+        let xx = x.clone() * x.clone();
+        let mut f = xx.clone() + xx;
+        f.backward(1.0);
+        assert_eq!(x.grad().unwrap(), 12.0);
+        // Intermediates and unrelated leaves are untouched.
+        assert!(f.grad().is_none());
+        let unrelated = Var::new(1.0);
+        f.backward(1.0);
+        assert!(unrelated.grad().is_none());
+        assert_eq!(x.grad().unwrap(), 24.0);
+    }
+
+    #[test]
+    fn test_set_val_and_grad() {
+        let mut x = Var::new(1.0);
+        let y = x.clone();
+        x.set_val(2.0);
+        x.set_grad(0.5);
+        // Clones are handles to the same entry.
+        assert_eq!(y.val(), 2.0);
+        assert_eq!(y.grad(), Some(0.5));
+    }
+
+    #[test]
+    fn test_no_grad_records_leaves() {
+        let x = Var::new(2.0);
+        let mut y = {
+            let _guard = no_grad();
+            x.clone() * Var::new(3.0)
+        };
+        assert!(y.is_leaf());
+        y.backward(1.0);
+        assert!(x.grad().is_none());
+    }
+
+    #[test]
+    fn test_truncate() {
+        let tape = Tape::current();
+        let x = Var::new(2.0);
+        let len = tape.len();
+        for _ in 0..3 {
+            let mut f = x.clone() * x.clone() + Var::new(1.0);
+            f.backward(1.0);
+            tape.truncate(len);
+            assert_eq!(tape.len(), len);
+        }
+        assert_eq!(x.grad().unwrap(), 12.0);
+    }
+
+    #[test]
+    fn test_stale_var_after_truncate() {
+        let tape = Tape::current();
+        let x = Var::new(2.0);
+        let len = tape.len();
+        let stale = x.clone() * x.clone();
+        tape.truncate(len);
+        // A new entry reuses the index of `stale`.
+        let fresh = Var::new(5.0);
+        assert_eq!(fresh.val(), 5.0);
+        for use_stale in [
+            |stale: &Var, _: &Var| {
+                stale.val();
+            },
+            |stale: &Var, _: &Var| stale.clone().set_val(1.0),
+            |stale: &Var, _: &Var| stale.clone().backward(1.0),
+            |stale: &Var, x: &Var| {
+                let _ = stale.clone() + x.clone();
+            },
+            |stale: &Var, _: &Var| {
+                let _ = stale.clone().exp();
+            },
+        ] {
+            let result =
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| use_stale(&stale, &x)));
+            assert!(result.is_err());
+        }
+        assert_eq!(fresh.val(), 5.0);
+        assert!(fresh.grad().is_none());
+        assert_eq!(x.val(), 2.0);
+    }
+
+    #[test]
+    fn test_tensor_of_vars() {
+        let data: Vec<Var> = (0..6).map(|x| Var::new(x as f64)).collect();
+        let x = TensorImpl::from_vec(&vec![2, 3], &data).unwrap();
+        let w = TensorImpl::from_vec(&vec![3, 1], &vec![Var::new(0.5); 3]).unwrap();
+        let out = x.matmul(&w).unwrap().softmax(0);
+        assert_eq!(out.shape(), vec![2, 1]);
+
+        let mut loss = out.at(vec![0, 0]).unwrap().clone();
+        loss.backward(1.0);
+        assert!(data.iter().all(|x| x.grad().is_some()));
+    }
+
+    #[test]
+    #[should_panic(expected = "different tapes")]
+    fn test_vars_from_different_tapes() {
+        let x = Var::new(1.0);
+        let other = Tape(Rc::new(RefCell::new(Entries::default()))).push(2.0, &[]);
+        let _ = x + other;
+    }
+}
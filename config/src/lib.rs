@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub batch_size: usize,
     pub vocab_size: usize,
@@ -7,6 +7,10 @@ pub struct Config {
     pub num_head: usize,
     pub seed: u64,
     pub num_blocks: usize,
+    pub dropout: f64,
+    pub activation: String,
+    pub intermediate_dim: usize,
+    pub gated_feed_forward: bool,
 }
 
 #[cfg(test)]
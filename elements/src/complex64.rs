@@ -0,0 +1,246 @@
+use std::{
+    fmt::Display,
+    ops::{Add, AddAssign, Div, Mul, Sub},
+};
+
+use interfaces::{
+    tensors::Element,
+    utils::{Conj, Exp, Ln, Pow},
+};
+use num_traits::identities::Zero;
+
+/// Complex number with `f64` real and imaginary parts.
+///
+/// `ln` and `pow` are the principal branches, with the cut along the negative real axis: the
+/// argument is taken in `[-pi, pi]` (see `arg()`), and the sign of a zero imaginary part tells
+/// which side of the cut a negative real lies on, eg. the argument of `-1 - 0i` is `-pi`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Complex64 {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex64 {
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    /// The imaginary unit.
+    pub fn i() -> Self {
+        Self::new(0., 1.)
+    }
+
+    /// `r . e^(i theta)`.
+    pub fn from_polar(r: f64, theta: f64) -> Self {
+        Self::new(r * theta.cos(), r * theta.sin())
+    }
+
+    /// The modulus `|z|`.
+    pub fn norm(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// The argument, in `[-pi, pi]`: as `f64::atan2`, `-pi` for a negative real part and an
+    /// imaginary part of `-0.`, `pi` for `+0.`.
+    pub fn arg(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+}
+
+impl Display for Complex64 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.im.is_sign_negative() { '-' } else { '+' };
+        match f.precision() {
+            Some(p) => write!(f, "{:.*}{}{:.*}i", p, self.re, sign, p, self.im.abs()),
+            None => write!(f, "{}{}{}i", self.re, sign, self.im.abs()),
+        }
+    }
+}
+
+impl Add for Complex64 {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl AddAssign for Complex64 {
+    fn add_assign(&mut self, rhs: Self) {
+        self.re += rhs.re;
+        self.im += rhs.im;
+    }
+}
+
+impl Sub for Complex64 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex64 {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Div for Complex64 {
+    type Output = Self;
+    // Smith's algorithm: divide through by the larger part of `rhs`, so that `|rhs|^2` is never
+    // formed (it overflows or underflows well before the quotient does).
+    fn div(self, rhs: Self) -> Self {
+        if rhs.re.abs() >= rhs.im.abs() {
+            let ratio = rhs.im / rhs.re;
+            let denom = rhs.re + rhs.im * ratio;
+            Self::new(
+                (self.re + self.im * ratio) / denom,
+                (self.im - self.re * ratio) / denom,
+            )
+        } else {
+            let ratio = rhs.re / rhs.im;
+            let denom = rhs.re * ratio + rhs.im;
+            Self::new(
+                (self.re * ratio + self.im) / denom,
+                (self.im * ratio - self.re) / denom,
+            )
+        }
+    }
+}
+
+// e^(a + bi) = e^a (cos b + i sin b)
+impl Exp for Complex64 {
+    fn exp(self) -> Self {
+        Self::from_polar(self.re.exp(), self.im)
+    }
+}
+
+// ln z = ln|z| + i arg z
+impl Ln for Complex64 {
+    fn ln(self) -> Self {
+        Self::new(self.norm().ln(), self.arg())
+    }
+}
+
+// z^w = e^(w ln z)
+impl Pow for Complex64 {
+    fn pow(self, exp: Self) -> Self {
+        if exp.is_zero() {
+            return Self::new(1., 0.);
+        }
+        // ln 0 is -inf: 0^w is 0 for Re(w) > 0, and undefined otherwise.
+        if self.is_zero() {
+            return if exp.re > 0. {
+                Self::zero()
+            } else {
+                Self::new(f64::NAN, f64::NAN)
+            };
+        }
+        (exp * self.ln()).exp()
+    }
+}
+
+impl Conj for Complex64 {
+    fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+}
+
+impl Zero for Complex64 {
+    fn zero() -> Self {
+        Self::new(0., 0.)
+    }
+    fn is_zero(&self) -> bool {
+        self.re == 0. && self.im == 0.
+    }
+}
+
+impl Element for Complex64 {}
+
+impl From<f64> for Complex64 {
+    fn from(value: f64) -> Self {
+        Self::new(value, 0.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use float_cmp::assert_approx_eq;
+
+    fn assert_close(actual: Complex64, expected: Complex64) {
+        assert_approx_eq!(f64, actual.re, expected.re, epsilon = 1e-12);
+        assert_approx_eq!(f64, actual.im, expected.im, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let a = Complex64::new(1., 2.);
+        let b = Complex64::new(3., -1.);
+        assert_eq!(a + b, Complex64::new(4., 1.));
+        assert_eq!(a - b, Complex64::new(-2., 3.));
+        assert_eq!(a * b, Complex64::new(5., 5.));
+        assert_close(a / b, Complex64::new(0.1, 0.7));
+        assert_close((a / b) * b, a);
+        assert_eq!(Complex64::i() * Complex64::i(), Complex64::from(-1.));
+        let mut c = a;
+        c += b;
+        assert_eq!(c, a + b);
+    }
+
+    #[test]
+    fn test_div_no_overflow() {
+        let big = Complex64::new(1e300, 1e300);
+        assert_close(big / big, Complex64::from(1.));
+    }
+
+    #[test]
+    fn test_exp_ln() {
+        // Euler's identity
+        assert_close(Complex64::new(0., PI).exp(), Complex64::from(-1.));
+        let z = Complex64::new(-2., 0.5);
+        assert_close(z.ln().exp(), z);
+        // The principal branch: arg in [-pi, pi], on the side of the cut given by the sign of 0
+        assert_close(Complex64::from(-1.).ln(), Complex64::new(0., PI));
+        assert_close(Complex64::new(-1., -0.).ln(), Complex64::new(0., -PI));
+    }
+
+    #[test]
+    fn test_pow() {
+        // i^i = e^(-pi/2)
+        let i = Complex64::i();
+        assert_close(i.pow(i), Complex64::from((-PI / 2.).exp()));
+        // Principal square root of -4
+        assert_close(
+            Complex64::from(-4.).pow(Complex64::from(0.5)),
+            Complex64::new(0., 2.),
+        );
+        let z = Complex64::new(1., 1.);
+        assert_close(z.pow(Complex64::from(3.)), z * z * z);
+        assert_eq!(
+            Complex64::zero().pow(Complex64::zero()),
+            Complex64::from(1.)
+        );
+        assert_eq!(Complex64::zero().pow(z), Complex64::zero());
+    }
+
+    #[test]
+    fn test_conj_norm_arg() {
+        let z = Complex64::new(3., 4.);
+        assert_eq!(z.conj(), Complex64::new(3., -4.));
+        assert_eq!(z.norm(), 5.);
+        assert_close(z * z.conj(), Complex64::from(25.));
+        assert_close(Complex64::from_polar(z.norm(), z.arg()), z);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Complex64::new(1., -2.).to_string(), "1-2i");
+        assert_eq!(format!("{:.2}", Complex64::new(0.5, 0.25)), "0.50+0.25i");
+    }
+}
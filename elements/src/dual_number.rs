@@ -53,8 +53,7 @@ impl Mul for DualNumber {
 
 impl MulAssign for DualNumber {
     fn mul_assign(&mut self, rhs: Self) {
-        self.real *= rhs.real;
-        self.dual = self.real * rhs.dual + self.dual * rhs.real;
+        *self = *self * rhs;
     }
 }
 
@@ -64,18 +63,16 @@ impl Div for DualNumber {
     //  = (x + ye)(a - be) / ((a + be)(a - be))
     //  = (ax + (ay - xb)e) / (a**2)
     fn div(self, rhs: Self) -> Self::Output {
-        let denom = self.real * self.real;
+        let denom = rhs.real * rhs.real;
         let real = (self.real * rhs.real) / denom;
-        let dual = (self.real * rhs.dual - self.dual * rhs.real) / denom;
+        let dual = (rhs.real * self.dual - self.real * rhs.dual) / denom;
         Self::new(real, dual)
     }
 }
 
 impl DivAssign for DualNumber {
     fn div_assign(&mut self, rhs: Self) {
-        let denom = self.real * self.real;
-        self.real = (self.real * rhs.real) / denom;
-        self.dual = (self.real * rhs.dual - self.dual * rhs.real) / denom;
+        *self = *self / rhs;
     }
 }
 
@@ -99,7 +96,19 @@ impl Ln for DualNumber {
 impl Pow for DualNumber {
     fn pow(self, exp: Self) -> Self {
         let real = self.real.powf(exp.real);
-        let dual = real * (exp.dual * self.real.ln() + exp.real * self.dual / self.real);
+        // Skip the ln(base) term for a constant exponent, so that eg. x^2 works for negative x.
+        let exp_term = if exp.dual == 0. {
+            0.
+        } else {
+            exp.dual * self.real.ln()
+        };
+        // n * x^(n - 1) rather than n * x^n / x, which is NaN at x = 0.
+        let base_term = if exp.real == 0. {
+            0.
+        } else {
+            exp.real * self.real.powf(exp.real - 1.) * self.dual
+        };
+        let dual = real * exp_term + base_term;
         Self::new(real, dual)
     }
 }
@@ -142,6 +151,21 @@ mod tests {
         assert_eq!(dual_mul.dual, 17.);
     }
 
+    #[test]
+    fn test_dual_div() {
+        // d/dx (x / x^2) = -1 / x^2
+        let dual_x = DualNumber::new(2., 1.);
+        let result = dual_x / (dual_x * dual_x);
+        assert_approx_eq!(f64, result.real, 0.5);
+        assert_approx_eq!(f64, result.dual, -0.25);
+
+        let mut dual_assign = dual_x;
+        dual_assign *= dual_x;
+        dual_assign /= DualNumber::new(4., 0.);
+        assert_approx_eq!(f64, dual_assign.real, 1.);
+        assert_approx_eq!(f64, dual_assign.dual, 1.);
+    }
+
     fn cube(dual_number: DualNumber) -> DualNumber {
         dual_number.pow(DualNumber::new(3., 0.))
     }
@@ -153,4 +177,15 @@ mod tests {
         assert_approx_eq!(f64, result.real, 0.001);
         assert_approx_eq!(f64, result.dual, 0.03);
     }
+
+    #[test]
+    fn test_pow_zero_base() {
+        let zero = DualNumber::new(0., 1.);
+        let square = zero.pow(DualNumber::new(2., 0.));
+        assert_eq!((square.real, square.dual), (0., 0.));
+        let identity = zero.pow(DualNumber::new(1., 0.));
+        assert_eq!((identity.real, identity.dual), (0., 1.));
+        let one = zero.pow(DualNumber::new(0., 0.));
+        assert_eq!((one.real, one.dual), (1., 0.));
+    }
 }
@@ -0,0 +1,270 @@
+use std::{
+    fmt::Display,
+    ops::{Add, AddAssign, Div, Mul, Sub},
+};
+
+use interfaces::{
+    tensors::{Element, Piecewise, RealElement},
+    utils::{Exp, Ln, Pow},
+};
+use num_traits::identities::Zero;
+
+/// Hyper-dual number `real + e1 ε1 + e2 ε2 + e1e2 ε1ε2`, with `ε1^2 = ε2^2 = (ε1ε2)^2 = 0`.
+///
+/// Evaluating a function at `x + ε1 + ε2` (see `HyperDual::variable()`) gives its exact first
+/// derivative in `e1` (and `e2`) and its exact second derivative in `e1e2`:
+/// `f(a + b ε1 + c ε2 + d ε1ε2) = f(a) + f'(a) b ε1 + f'(a) c ε2 + (f'(a) d + f''(a) b c) ε1ε2`.
+/// Seeding `ε1` and `ε2` on different inputs gives a mixed second derivative.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HyperDual {
+    pub real: f64,
+    pub e1: f64,
+    pub e2: f64,
+    pub e1e2: f64,
+}
+
+impl Display for HyperDual {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "({}, {}, {}, {})",
+            self.real, self.e1, self.e2, self.e1e2
+        )
+    }
+}
+
+impl HyperDual {
+    pub fn new(real: f64, e1: f64, e2: f64, e1e2: f64) -> Self {
+        Self { real, e1, e2, e1e2 }
+    }
+
+    /// The variable to differentiate with respect to, `x + ε1 + ε2`.
+    pub fn variable(x: f64) -> Self {
+        Self::new(x, 1., 1., 0.)
+    }
+
+    /// Apply a scalar function given its value and first and second derivatives at `self.real`.
+    fn chain(self, f: f64, df: f64, d2f: f64) -> Self {
+        Self::new(
+            f,
+            df * self.e1,
+            df * self.e2,
+            df * self.e1e2 + d2f * self.e1 * self.e2,
+        )
+    }
+
+    fn is_real(&self) -> bool {
+        self.e1 == 0. && self.e2 == 0. && self.e1e2 == 0.
+    }
+}
+
+impl Add for HyperDual {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(
+            self.real + rhs.real,
+            self.e1 + rhs.e1,
+            self.e2 + rhs.e2,
+            self.e1e2 + rhs.e1e2,
+        )
+    }
+}
+
+impl AddAssign for HyperDual {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for HyperDual {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(
+            self.real - rhs.real,
+            self.e1 - rhs.e1,
+            self.e2 - rhs.e2,
+            self.e1e2 - rhs.e1e2,
+        )
+    }
+}
+
+impl Mul for HyperDual {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.real * rhs.real,
+            self.real * rhs.e1 + self.e1 * rhs.real,
+            self.real * rhs.e2 + self.e2 * rhs.real,
+            self.real * rhs.e1e2 + self.e1 * rhs.e2 + self.e2 * rhs.e1 + self.e1e2 * rhs.real,
+        )
+    }
+}
+
+impl Div for HyperDual {
+    type Output = Self;
+    // x / y = x . (1 / y), with d/dy (1 / y) = -1 / y^2 and d2/dy2 (1 / y) = 2 / y^3.
+    fn div(self, rhs: Self) -> Self {
+        let inv = 1. / rhs.real;
+        self * rhs.chain(inv, -inv * inv, 2. * inv * inv * inv)
+    }
+}
+
+impl Exp for HyperDual {
+    fn exp(self) -> Self {
+        let exp = self.real.exp();
+        self.chain(exp, exp, exp)
+    }
+}
+
+impl Ln for HyperDual {
+    fn ln(self) -> Self {
+        let inv = 1. / self.real;
+        self.chain(self.real.ln(), inv, -inv * inv)
+    }
+}
+
+impl Pow for HyperDual {
+    fn pow(self, exp: Self) -> Self {
+        if exp.is_real() {
+            // A constant exponent also works for negative bases, eg. x^2. The derivatives with a
+            // zero coefficient are zero, rather than `0 * inf` at a zero base.
+            let n = exp.real;
+            let df = if n == 0. {
+                0.
+            } else {
+                n * self.real.powf(n - 1.)
+            };
+            let d2f = if n == 0. || n == 1. {
+                0.
+            } else {
+                n * (n - 1.) * self.real.powf(n - 2.)
+            };
+            self.chain(self.real.powf(n), df, d2f)
+        } else {
+            (exp * self.ln()).exp()
+        }
+    }
+}
+
+impl Zero for HyperDual {
+    fn zero() -> Self {
+        Self::new(0., 0., 0., 0.)
+    }
+    fn is_zero(&self) -> bool {
+        self.real == 0. && self.is_real()
+    }
+}
+
+impl Element for HyperDual {}
+
+impl RealElement for HyperDual {
+    fn neg_inf() -> Self {
+        Self::new(-f64::INFINITY, 0., 0., 0.)
+    }
+}
+
+impl From<f64> for HyperDual {
+    fn from(value: f64) -> Self {
+        Self::new(value, 0., 0., 0.)
+    }
+}
+
+/// Branches on the real part.
+impl Piecewise for HyperDual {
+    fn piecewise(
+        self,
+        positive: impl FnOnce(Self) -> Self,
+        non_positive: impl FnOnce(Self) -> Self,
+    ) -> Self {
+        if self.real > 0. {
+            positive(self)
+        } else {
+            non_positive(self)
+        }
+    }
+}
+
+impl From<HyperDual> for f64 {
+    fn from(value: HyperDual) -> Self {
+        value.real
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use float_cmp::assert_approx_eq;
+
+    /// f(x) = x^3 . ln(x) + exp(2x) / x
+    fn f(x: HyperDual) -> HyperDual {
+        x.pow(3.0.into()) * x.ln() + (HyperDual::from(2.) * x).exp() / x
+    }
+
+    #[test]
+    fn test_first_and_second_derivative() {
+        let x = 1.3_f64;
+        let result = f(HyperDual::variable(x));
+
+        let e2x = (2. * x).exp();
+        let df = 3. * x * x * x.ln() + x * x + e2x * (2. * x - 1.) / (x * x);
+        let d2f = 6. * x * x.ln() + 5. * x + e2x * (4. * x * x - 4. * x + 2.) / (x * x * x);
+        assert_approx_eq!(f64, result.real, x.powi(3) * x.ln() + e2x / x, ulps = 4);
+        assert_approx_eq!(f64, result.e1, df, epsilon = 1e-12);
+        assert_approx_eq!(f64, result.e2, df, epsilon = 1e-12);
+        assert_approx_eq!(f64, result.e1e2, d2f, epsilon = 1e-11);
+    }
+
+    #[test]
+    fn test_mixed_derivative() {
+        // g(x, y) = x^2 . y^3, d2g/dxdy = 6 x y^2
+        let x = HyperDual::new(2., 1., 0., 0.);
+        let y = HyperDual::new(3., 0., 1., 0.);
+        let result = x.pow(2.0.into()) * y.pow(3.0.into());
+        assert_approx_eq!(f64, result.e1, 2. * 2. * 27.);
+        assert_approx_eq!(f64, result.e2, 4. * 3. * 9.);
+        assert_approx_eq!(f64, result.e1e2, 6. * 2. * 9.);
+    }
+
+    #[test]
+    fn test_div() {
+        // d2/dx2 (1 / x) = 2 / x^3
+        let result = HyperDual::from(1.) / HyperDual::variable(2.);
+        assert_approx_eq!(f64, result.real, 0.5);
+        assert_approx_eq!(f64, result.e1, -0.25);
+        assert_approx_eq!(f64, result.e1e2, 0.25);
+    }
+
+    #[test]
+    fn test_pow_negative_base() {
+        let result = HyperDual::variable(-2.).pow(3.0.into());
+        assert_approx_eq!(f64, result.real, -8.);
+        assert_approx_eq!(f64, result.e1, 12.);
+        assert_approx_eq!(f64, result.e1e2, -12.);
+    }
+
+    #[test]
+    fn test_pow_zero_base() {
+        let x = HyperDual::variable(0.);
+        assert_eq!(x.pow(0.0.into()), HyperDual::new(1., 0., 0., 0.));
+        assert_eq!(x.pow(1.0.into()), HyperDual::new(0., 1., 1., 0.));
+        assert_eq!(x.pow(2.0.into()), HyperDual::new(0., 0., 0., 2.));
+        assert_eq!(x.pow(3.0.into()), HyperDual::new(0., 0., 0., 0.));
+    }
+
+    #[test]
+    fn test_pow_variable_exponent() {
+        // d2/dx2 x^x = x^x ((ln(x) + 1)^2 + 1 / x)
+        let x = HyperDual::variable(1.5);
+        let result = x.pow(x);
+        let xx = 1.5_f64.powf(1.5);
+        let log_term = 1.5_f64.ln() + 1.;
+        assert_approx_eq!(f64, result.e1, xx * log_term, epsilon = 1e-12);
+        assert_approx_eq!(
+            f64,
+            result.e1e2,
+            xx * (log_term * log_term + 1. / 1.5),
+            epsilon = 1e-12
+        );
+    }
+}
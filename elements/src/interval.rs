@@ -0,0 +1,409 @@
+use std::{
+    fmt::Display,
+    ops::{Add, AddAssign, Div, Mul, Sub},
+};
+
+use interfaces::{
+    tensors::{Element, Piecewise, RealElement},
+    utils::{Exp, Ln, Pow},
+};
+use num_traits::identities::Zero;
+
+/// Closed interval `[lo, hi]` of reals, for computing guaranteed bounds: the result of every
+/// operation contains the result of the same operation on any values taken from the operands.
+///
+/// Bounds are rounded outward. The basic arithmetic operations are correctly rounded, so widening
+/// their result by one ulp is enough, and their bounds are guaranteed. The accuracy of `exp`, `ln`
+/// and `powf` depends on the platform's libm, which Rust does not specify: their result is widened
+/// by two ulps, which covers common implementations (eg. glibc documents errors of at most one
+/// ulp), but these bounds are a heuristic rather than a guarantee.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    lo: f64,
+    hi: f64,
+}
+
+/// Round outward by `ulps` units in the last place.
+fn down(x: f64, ulps: usize) -> f64 {
+    (0..ulps).fold(x, |x, _| x.next_down())
+}
+
+fn up(x: f64, ulps: usize) -> f64 {
+    (0..ulps).fold(x, |x, _| x.next_up())
+}
+
+impl Interval {
+    /// Panics if `lo > hi` or either bound is NaN.
+    pub fn new(lo: f64, hi: f64) -> Self {
+        assert!(lo <= hi, "Invalid interval [{}, {}].", lo, hi);
+        Self { lo, hi }
+    }
+
+    /// The interval containing only `x`.
+    pub fn point(x: f64) -> Self {
+        Self { lo: x, hi: x }
+    }
+
+    /// The interval of all reals.
+    pub fn entire() -> Self {
+        Self {
+            lo: -f64::INFINITY,
+            hi: f64::INFINITY,
+        }
+    }
+
+    pub fn lo(&self) -> f64 {
+        self.lo
+    }
+
+    pub fn hi(&self) -> f64 {
+        self.hi
+    }
+
+    pub fn width(&self) -> f64 {
+        self.hi - self.lo
+    }
+
+    pub fn contains(&self, x: f64) -> bool {
+        self.lo <= x && x <= self.hi
+    }
+
+    /// Smallest interval containing the `values` (NaNs are ignored), widened by `ulps`.
+    fn hull(values: &[f64], ulps: usize) -> Self {
+        let lo = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let hi = values.iter().copied().fold(-f64::INFINITY, f64::max);
+        Self {
+            lo: down(lo, ulps),
+            hi: up(hi, ulps),
+        }
+    }
+
+    fn is_point(&self) -> bool {
+        self.lo == self.hi
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+impl Add for Interval {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            lo: down(self.lo + rhs.lo, 1),
+            hi: up(self.hi + rhs.hi, 1),
+        }
+    }
+}
+
+impl AddAssign for Interval {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Interval {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            lo: down(self.lo - rhs.hi, 1),
+            hi: up(self.hi - rhs.lo, 1),
+        }
+    }
+}
+
+impl Mul for Interval {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        // 0 . inf is 0 here: a bound of 0 is attained, an infinite one is not.
+        let prod = |a: f64, b: f64| if a == 0. || b == 0. { 0. } else { a * b };
+        Self::hull(
+            &[
+                prod(self.lo, rhs.lo),
+                prod(self.lo, rhs.hi),
+                prod(self.hi, rhs.lo),
+                prod(self.hi, rhs.hi),
+            ],
+            1,
+        )
+    }
+}
+
+impl Div for Interval {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        // Division by an interval containing 0 is unbounded.
+        if rhs.contains(0.) {
+            return Self::entire();
+        }
+        Self::hull(
+            &[
+                self.lo / rhs.lo,
+                self.lo / rhs.hi,
+                self.hi / rhs.lo,
+                self.hi / rhs.hi,
+            ],
+            1,
+        )
+    }
+}
+
+impl Exp for Interval {
+    fn exp(self) -> Self {
+        Self {
+            lo: down(self.lo.exp(), 2).max(0.),
+            hi: up(self.hi.exp(), 2),
+        }
+    }
+}
+
+impl Ln for Interval {
+    /// Only the positive part of `self` is in the domain of `ln`, with `ln(0) = -inf` as for `f64`.
+    ///
+    /// Panics if `self` has no value in the domain, ie. `hi < 0`.
+    fn ln(self) -> Self {
+        assert!(
+            self.hi >= 0.,
+            "ln is undefined on the negative interval {}.",
+            self
+        );
+        Self {
+            lo: down(self.lo.max(0.).ln(), 2),
+            hi: up(self.hi.ln(), 2),
+        }
+    }
+}
+
+impl Pow for Interval {
+    /// A non-integer exponent only takes the positive part of `self` (as `ln`): if there is none,
+    /// ie. `hi < 0`, where `f64` gives NaN, the result is `Interval::entire()`.
+    fn pow(self, exp: Self) -> Self {
+        let n = exp.lo;
+        if exp.is_point() && n.fract() == 0. {
+            // An integer exponent also works for negative bases, eg. x^2.
+            if n < 0. {
+                return Self::point(1.) / self.pow(Self::point(-n));
+            }
+            let (lo, hi) = (self.lo.powf(n), self.hi.powf(n));
+            if n % 2. == 1. || self.lo >= 0. {
+                Self::hull(&[lo, hi], 2)
+            } else if self.hi <= 0. {
+                Self::hull(&[hi, lo], 2)
+            } else {
+                // An even power of an interval containing 0.
+                Self {
+                    lo: 0.,
+                    hi: up(lo.max(hi), 2),
+                }
+            }
+        } else if self.hi < 0. {
+            Self::entire()
+        } else {
+            (exp * self.ln()).exp()
+        }
+    }
+}
+
+impl Zero for Interval {
+    fn zero() -> Self {
+        Self::point(0.)
+    }
+    fn is_zero(&self) -> bool {
+        self.lo == 0. && self.hi == 0.
+    }
+}
+
+impl Element for Interval {}
+
+impl RealElement for Interval {
+    fn neg_inf() -> Self {
+        Self::point(-f64::INFINITY)
+    }
+}
+
+impl From<f64> for Interval {
+    fn from(value: f64) -> Self {
+        Self::point(value)
+    }
+}
+
+/// An interval straddling 0 takes both branches, each on its side of 0 (including 0), and
+/// returns the hull of their results: eg. the ReLU of `[-1, 2]` is `[0, 2]`.
+impl Piecewise for Interval {
+    fn piecewise(
+        self,
+        positive: impl FnOnce(Self) -> Self,
+        non_positive: impl FnOnce(Self) -> Self,
+    ) -> Self {
+        if self.lo > 0. {
+            positive(self)
+        } else if self.hi <= 0. {
+            non_positive(self)
+        } else {
+            let positive = positive(Self::new(0., self.hi));
+            let non_positive = non_positive(Self::new(self.lo, 0.));
+            Self {
+                lo: positive.lo.min(non_positive.lo),
+                hi: positive.hi.max(non_positive.hi),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Values spread over `x`, including its bounds.
+    fn samples(x: Interval) -> Vec<f64> {
+        (0..=10)
+            .map(|i| x.lo + (x.hi - x.lo) * i as f64 / 10.)
+            .collect()
+    }
+
+    fn check_binary(
+        x: Interval,
+        y: Interval,
+        op: fn(Interval, Interval) -> Interval,
+        f: fn(f64, f64) -> f64,
+    ) {
+        let result = op(x, y);
+        for a in samples(x) {
+            for b in samples(y) {
+                // Outside the domain of `f`, eg. a division by 0.
+                if f(a, b).is_nan() {
+                    continue;
+                }
+                assert!(
+                    result.contains(f(a, b)),
+                    "{} does not contain f({}, {}) = {}",
+                    result,
+                    a,
+                    b,
+                    f(a, b)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_arithmetic_contains_results() {
+        let intervals = [
+            Interval::new(-2., 3.),
+            Interval::new(0.1, 0.7),
+            Interval::new(-5., -1.5),
+            Interval::point(0.3),
+        ];
+        for x in intervals {
+            for y in intervals {
+                check_binary(x, y, |x, y| x + y, |a, b| a + b);
+                check_binary(x, y, |x, y| x - y, |a, b| a - b);
+                check_binary(x, y, |x, y| x * y, |a, b| a * b);
+                check_binary(x, y, |x, y| x / y, |a, b| a / b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_outward_rounding() {
+        // 0.1 + 0.2 is not representable: the rounded sum must not be the only candidate.
+        let sum = Interval::point(0.1) + Interval::point(0.2);
+        assert!(sum.lo() < 0.1 + 0.2 && 0.1 + 0.2 < sum.hi());
+        let third = Interval::point(1.) / Interval::point(3.);
+        assert!(third.contains(1. / 3.));
+        assert!(third.width() > 0.);
+    }
+
+    #[test]
+    fn test_mul() {
+        let result = Interval::new(-2., 3.) * Interval::new(-1., 4.);
+        assert!(result.lo() <= -8. && result.lo() > -8.0001);
+        assert!(result.hi() >= 12. && result.hi() < 12.0001);
+        let zero = Interval::zero() * Interval::entire();
+        assert!(zero.contains(0.) && zero.width() < 1e-300);
+    }
+
+    #[test]
+    fn test_div_by_interval_containing_zero() {
+        assert_eq!(
+            Interval::point(1.) / Interval::new(-1., 1.),
+            Interval::entire()
+        );
+    }
+
+    #[test]
+    fn test_exp_ln() {
+        let x = Interval::new(-1., 2.);
+        let exp = x.exp();
+        for a in samples(x) {
+            assert!(exp.contains(a.exp()));
+        }
+        let ln = exp.ln();
+        assert!(ln.lo() <= -1. && ln.hi() >= 2.);
+        assert_eq!(Interval::new(-1., 1.).ln().lo(), -f64::INFINITY);
+    }
+
+    #[test]
+    fn test_pow() {
+        let x = Interval::new(-2., 3.);
+        let square = x.pow(2.0.into());
+        assert_eq!(square.lo(), 0.);
+        assert!(square.hi() >= 9. && square.hi() < 9.0001);
+        let cube = x.pow(3.0.into());
+        assert!(cube.lo() <= -8. && cube.hi() >= 27.);
+        let inv_square = Interval::new(-2., -1.).pow((-2.0).into());
+        assert!(inv_square.contains(0.25) && inv_square.contains(1.));
+
+        let base = Interval::new(0.5, 2.);
+        let exp = Interval::new(1., 1.5);
+        check_binary(base, exp, |x, y| x.pow(y), f64::powf);
+    }
+
+    #[test]
+    fn test_ln_at_zero() {
+        let ln = Interval::new(-1., 0.).ln();
+        assert_eq!(ln.lo(), -f64::INFINITY);
+        assert!(ln.hi() < -1e300);
+        assert!(!ln.hi().is_nan());
+    }
+
+    #[test]
+    #[should_panic(expected = "ln is undefined on the negative interval")]
+    fn test_ln_of_negative_interval() {
+        Interval::new(-2., -1.).ln();
+    }
+
+    #[test]
+    fn test_pow_of_negative_interval() {
+        let sqrt = |x: Interval| x.pow(Interval::point(0.5));
+        assert_eq!(sqrt(Interval::new(-2., -1.)), Interval::entire());
+        let straddling = sqrt(Interval::new(-1., 4.));
+        assert!(straddling.lo() <= 0. && straddling.contains(2.));
+        assert!(Interval::new(-2., -1.)
+            .pow(Interval::point(2.))
+            .contains(4.));
+    }
+
+    #[test]
+    fn test_piecewise() {
+        let relu = |x: Interval| x.piecewise(|x| x, |_| Interval::zero());
+        assert_eq!(relu(Interval::new(-1., 0.5)), Interval::new(0., 0.5));
+        assert_eq!(relu(Interval::new(-1., -0.5)), Interval::zero());
+        assert_eq!(relu(Interval::new(0.5, 1.)), Interval::new(0.5, 1.));
+        // Each branch only sees its side of 0.
+        let abs = |x: Interval| x.piecewise(|x| x, |x| Interval::point(-1.) * x);
+        let result = abs(Interval::new(-2., 1.));
+        assert!(result.lo() <= 0. && result.lo() > -1e-300);
+        assert!(result.contains(2.) && result.hi() < 2.0001);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid interval")]
+    fn test_invalid_interval() {
+        Interval::new(1., 0.);
+    }
+}
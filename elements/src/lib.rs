@@ -1 +1,7 @@
+pub mod complex64;
 pub mod dual_number;
+pub mod hyper_dual;
+pub mod interval;
+pub mod qint8;
+pub mod rational;
+pub mod symbolic;
@@ -0,0 +1,187 @@
+use std::{
+    fmt::Display,
+    ops::{Add, AddAssign, Div, Mul, Sub},
+};
+
+use interfaces::tensors::Element;
+use num_traits::identities::Zero;
+
+/// Affine quantization parameters: the real value of the integer `q` is `scale . (q - zero_point)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QParams {
+    pub scale: f64,
+    pub zero_point: i8,
+}
+
+impl QParams {
+    /// Parameters mapping `[min, max]` onto the whole `i8` range. The range is extended to contain
+    /// 0, so that 0 (eg. padding, or the output of a ReLU) is represented exactly.
+    pub fn from_range(min: f64, max: f64) -> Self {
+        let (min, max) = (min.min(0.), max.max(0.));
+        if max == min {
+            return Self {
+                scale: 1.,
+                zero_point: 0,
+            };
+        }
+        let scale = (max - min) / (i8::MAX as f64 - i8::MIN as f64);
+        let zero_point = (i8::MIN as f64 - min / scale).round() as i8;
+        Self { scale, zero_point }
+    }
+
+    /// Parameters with a zero point of 0, mapping `[-max_abs, max_abs]` onto `[-127, 127]`. Used for
+    /// weights, so that the integer matmul needs no zero point correction on their side.
+    pub fn symmetric(max_abs: f64) -> Self {
+        Self {
+            scale: if max_abs > 0. {
+                max_abs / i8::MAX as f64
+            } else {
+                1.
+            },
+            zero_point: 0,
+        }
+    }
+
+    /// The nearest representable value, saturating outside of the range.
+    pub fn quantize(&self, x: f64) -> QInt8 {
+        let q = (x / self.scale).round() + self.zero_point as f64;
+        QInt8 {
+            value: q.clamp(i8::MIN as f64, i8::MAX as f64) as i8,
+            params: *self,
+        }
+    }
+}
+
+/// 8-bit quantized real, carrying its own `QParams` (so a tensor of them may be quantized per
+/// channel).
+///
+/// The arithmetic operators dequantize, compute in `f64` and requantize with the parameters of the
+/// left operand (or the right one if the left is an exact zero, eg. the start of a sum). They are
+/// for convenience only: results saturate, and rounding errors build up in long sums such as a
+/// `matmul`. Use `QuantizedLinLayer` (in `neural_nets`) for integer matmuls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QInt8 {
+    pub value: i8,
+    pub params: QParams,
+}
+
+impl QInt8 {
+    pub fn dequantize(&self) -> f64 {
+        self.params.scale * (self.value as i32 - self.params.zero_point as i32) as f64
+    }
+
+    fn binary_op(self, rhs: Self, op: fn(f64, f64) -> f64) -> Self {
+        let params = if self.is_zero() {
+            rhs.params
+        } else {
+            self.params
+        };
+        params.quantize(op(self.dequantize(), rhs.dequantize()))
+    }
+}
+
+impl Display for QInt8 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.dequantize())
+    }
+}
+
+impl Add for QInt8 {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        self.binary_op(rhs, |a, b| a + b)
+    }
+}
+
+impl AddAssign for QInt8 {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for QInt8 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        self.binary_op(rhs, |a, b| a - b)
+    }
+}
+
+impl Mul for QInt8 {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        self.binary_op(rhs, |a, b| a * b)
+    }
+}
+
+impl Div for QInt8 {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        self.binary_op(rhs, |a, b| a / b)
+    }
+}
+
+impl Zero for QInt8 {
+    fn zero() -> Self {
+        QParams::symmetric(0.).quantize(0.)
+    }
+    fn is_zero(&self) -> bool {
+        self.value == self.params.zero_point
+    }
+}
+
+impl Element for QInt8 {}
+
+impl From<QInt8> for f64 {
+    fn from(value: QInt8) -> Self {
+        value.dequantize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_from_range() {
+        let params = QParams::from_range(-1., 3.);
+        assert_eq!(params.quantize(-1.).value, i8::MIN);
+        assert_eq!(params.quantize(3.).value, i8::MAX);
+        // 0 is exact
+        assert_eq!(params.quantize(0.).dequantize(), 0.);
+        // A range not containing 0 is extended
+        let params = QParams::from_range(2., 4.);
+        assert_eq!(params.quantize(0.).value, i8::MIN);
+    }
+
+    #[test]
+    fn test_round_trip_error() {
+        let params = QParams::from_range(-2., 5.);
+        for i in 0..=100 {
+            let x = -2. + 7. * i as f64 / 100.;
+            let error = (params.quantize(x).dequantize() - x).abs();
+            assert!(error <= params.scale / 2. + 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_saturation() {
+        let params = QParams::symmetric(1.);
+        assert_eq!(params.quantize(10.).value, i8::MAX);
+        assert_eq!(params.quantize(-10.).value, -i8::MAX - 1);
+        assert_eq!(params.quantize(-1.).value, -i8::MAX);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let params = QParams::symmetric(4.);
+        let x = params.quantize(1.5);
+        let y = params.quantize(-0.5);
+        assert!(((x + y).dequantize() - 1.).abs() <= params.scale);
+        assert!(((x * y).dequantize() + 0.75).abs() <= params.scale);
+        // Summing from zero keeps the parameters of the summands
+        let sum = QInt8::zero() + x;
+        assert_eq!(sum.params, params);
+        assert_eq!(sum, x);
+    }
+}
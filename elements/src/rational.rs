@@ -0,0 +1,262 @@
+use std::{
+    fmt::Display,
+    ops::{Add, AddAssign, Div, Mul, Sub},
+};
+
+use anyhow::Error;
+use interfaces::tensors::{AsStdError, Element};
+use num_traits::identities::Zero;
+
+/// Exact rational number, `numer / denom` with `i128` parts.
+///
+/// Always kept normalised: the parts are coprime and `denom` is positive, so that equal values
+/// compare equal. The `checked_*` methods return an error on overflow (or division by zero); the
+/// arithmetic operators, needed for `Element`, panic with that error instead, like the integer
+/// operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rational {
+    numer: i128,
+    denom: i128,
+}
+
+// A gcd of 2^127 (only for `i128::MIN` and 0 or itself) wraps to `i128::MIN`, which still divides
+// them exactly.
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a as i128
+}
+
+fn overflow(op: &str) -> AsStdError {
+    Error::msg(format!("Rational overflow in {}.", op)).into()
+}
+
+impl Rational {
+    /// `numer / denom`, normalised. Errors if `denom` is 0, or if the normalised value is not
+    /// representable (eg. `1 / i128::MIN`).
+    pub fn new(numer: i128, denom: i128) -> Result<Self, AsStdError> {
+        if denom == 0 {
+            return Err(Error::msg("The denominator of a Rational must not be 0.").into());
+        }
+        let g = gcd(numer, denom);
+        let (numer, denom) = (numer / g, denom / g);
+        if denom < 0 {
+            Ok(Self {
+                numer: numer.checked_neg().ok_or_else(|| overflow("new"))?,
+                denom: denom.checked_neg().ok_or_else(|| overflow("new"))?,
+            })
+        } else {
+            Ok(Self { numer, denom })
+        }
+    }
+
+    pub fn integer(value: i128) -> Self {
+        Self {
+            numer: value,
+            denom: 1,
+        }
+    }
+
+    pub fn numer(&self) -> i128 {
+        self.numer
+    }
+
+    pub fn denom(&self) -> i128 {
+        self.denom
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.denom == 1
+    }
+
+    // a/b + c/d = (a.(d/g) + c.(b/g)) / (b/g . d), with g = gcd(b, d), to keep the intermediate
+    // products small.
+    pub fn checked_add(self, rhs: Self) -> Result<Self, AsStdError> {
+        let g = gcd(self.denom, rhs.denom);
+        let numer = self
+            .numer
+            .checked_mul(rhs.denom / g)
+            .zip(rhs.numer.checked_mul(self.denom / g))
+            .and_then(|(a, b)| a.checked_add(b));
+        let denom = (self.denom / g).checked_mul(rhs.denom);
+        match (numer, denom) {
+            (Some(numer), Some(denom)) => Self::new(numer, denom),
+            _ => Err(overflow("addition")),
+        }
+    }
+
+    pub fn checked_neg(self) -> Result<Self, AsStdError> {
+        Ok(Self {
+            numer: self
+                .numer
+                .checked_neg()
+                .ok_or_else(|| overflow("negation"))?,
+            denom: self.denom,
+        })
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Result<Self, AsStdError> {
+        self.checked_add(rhs.checked_neg()?)
+    }
+
+    // Cancel across before multiplying: (a/b).(c/d) = ((a/g1).(c/g2)) / ((b/g2).(d/g1)), with
+    // g1 = gcd(a, d) and g2 = gcd(c, b). The result is then already normalised.
+    pub fn checked_mul(self, rhs: Self) -> Result<Self, AsStdError> {
+        let g1 = gcd(self.numer, rhs.denom);
+        let g2 = gcd(rhs.numer, self.denom);
+        let numer = (self.numer / g1).checked_mul(rhs.numer / g2);
+        let denom = (self.denom / g2).checked_mul(rhs.denom / g1);
+        match (numer, denom) {
+            (Some(numer), Some(denom)) => Ok(Self { numer, denom }),
+            _ => Err(overflow("multiplication")),
+        }
+    }
+
+    /// Errors if `self` is 0.
+    pub fn checked_recip(self) -> Result<Self, AsStdError> {
+        if self.numer == 0 {
+            return Err(Error::msg("Division of a Rational by 0.").into());
+        }
+        Self::new(self.denom, self.numer)
+    }
+
+    pub fn checked_div(self, rhs: Self) -> Result<Self, AsStdError> {
+        self.checked_mul(rhs.checked_recip()?)
+    }
+}
+
+impl Display for Rational {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.numer)
+        } else {
+            write!(f, "{}/{}", self.numer, self.denom)
+        }
+    }
+}
+
+impl Add for Rational {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        self.checked_add(rhs).unwrap()
+    }
+}
+
+impl AddAssign for Rational {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Rational {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        self.checked_sub(rhs).unwrap()
+    }
+}
+
+impl Mul for Rational {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        self.checked_mul(rhs).unwrap()
+    }
+}
+
+impl Div for Rational {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        self.checked_div(rhs).unwrap()
+    }
+}
+
+impl Zero for Rational {
+    fn zero() -> Self {
+        Self::integer(0)
+    }
+    fn is_zero(&self) -> bool {
+        self.numer == 0
+    }
+}
+
+impl Element for Rational {}
+
+impl From<i64> for Rational {
+    fn from(value: i64) -> Self {
+        Self::integer(value as i128)
+    }
+}
+
+impl From<Rational> for f64 {
+    fn from(value: Rational) -> Self {
+        value.numer as f64 / value.denom as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn r(numer: i128, denom: i128) -> Rational {
+        Rational::new(numer, denom).unwrap()
+    }
+
+    #[test]
+    fn test_normalisation() {
+        assert_eq!(r(2, 4), r(1, 2));
+        assert_eq!(r(3, -6), r(-1, 2));
+        assert_eq!(r(-3, -6).numer(), 1);
+        assert_eq!(r(0, -5), Rational::zero());
+        assert_eq!(r(0, -5).denom(), 1);
+        assert!(Rational::new(1, 0).is_err());
+        assert!(Rational::new(1, i128::MIN).is_err());
+        assert_eq!(r(2, i128::MIN), r(-1, -(i128::MIN / 2)));
+        assert_eq!(r(i128::MIN, i128::MIN), Rational::integer(1));
+        assert_eq!(r(0, i128::MIN), Rational::zero());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(r(1, 2) + r(1, 3), r(5, 6));
+        assert_eq!(r(1, 2) - r(1, 3), r(1, 6));
+        assert_eq!(r(2, 3) * r(9, 4), r(3, 2));
+        assert_eq!(r(2, 3) / r(4, 9), r(3, 2));
+        assert_eq!(r(1, 6) + r(1, 3), r(1, 2));
+        let mut sum = Rational::zero();
+        for k in 1..=10 {
+            sum += r(1, k * (k + 1));
+        }
+        assert_eq!(sum, r(10, 11));
+    }
+
+    #[test]
+    fn test_errors() {
+        let max = Rational::integer(i128::MAX);
+        assert!(max.checked_add(Rational::integer(1)).is_err());
+        assert!(max.checked_mul(Rational::integer(2)).is_err());
+        assert!(Rational::integer(i128::MIN).checked_neg().is_err());
+        assert!(r(1, 2).checked_div(Rational::zero()).is_err());
+        // Cancelling keeps products of large but reducible values in range.
+        assert_eq!(
+            max.checked_mul(r(1, i128::MAX)).unwrap(),
+            Rational::integer(1)
+        );
+        let big = r(1, i128::MAX / 3);
+        assert_eq!(big.checked_add(big).unwrap(), r(2, i128::MAX / 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "Rational overflow in addition.")]
+    fn test_operator_overflow_panics() {
+        let _ = Rational::integer(i128::MAX) + Rational::integer(1);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(r(-3, 4).to_string(), "-3/4");
+        assert_eq!(r(8, 4).to_string(), "2");
+        assert_eq!(f64::from(r(3, 4)), 0.75);
+    }
+}
@@ -0,0 +1,472 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::{Display, Formatter, Result as FmtResult},
+    ops::{Add, AddAssign, Div, Mul, Sub},
+    rc::Rc,
+};
+
+use interfaces::{
+    tensors::{Element, RealElement},
+    utils::{Exp, Ln, Pow},
+};
+use num_traits::identities::Zero;
+
+/// Symbolic expression: an element which records the operations applied to it, eg. to print the
+/// formula computed by a module (`TensorImpl<Expr>` goes through `forward()` like any other
+/// tensor). Subexpressions are shared, so cloning is cheap.
+///
+/// The operators simplify as they build: constants are folded and identities such as `x + 0`,
+/// `1 * x`, `x^1` or `ln(exp(x))` are removed.
+///
+/// `Display` renders plain text, `Expr::latex()` LaTeX. Both take a precision for constants, eg.
+/// `format!("{:.3}", expr.latex())`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Const(f64),
+    Var(String),
+    Add(Rc<Expr>, Rc<Expr>),
+    Sub(Rc<Expr>, Rc<Expr>),
+    Mul(Rc<Expr>, Rc<Expr>),
+    Div(Rc<Expr>, Rc<Expr>),
+    Pow(Rc<Expr>, Rc<Expr>),
+    Exp(Rc<Expr>),
+    Ln(Rc<Expr>),
+}
+
+impl Expr {
+    pub fn var(name: &str) -> Self {
+        Expr::Var(name.to_string())
+    }
+
+    /// Variables for every element of a tensor of `shape`, in row-major order, named after their
+    /// indices: `vars("w", &[2, 3])` gives `w_0_0, w_0_1, ..., w_1_2`.
+    pub fn vars(prefix: &str, shape: &[usize]) -> Vec<Self> {
+        let len: usize = shape.iter().product();
+        (0..len)
+            .map(|mut flat_idx| {
+                let mut indices = vec![0; shape.len()];
+                for (idx, dim) in indices.iter_mut().zip(shape.iter()).rev() {
+                    *idx = flat_idx % dim;
+                    flat_idx /= dim;
+                }
+                let name = indices
+                    .iter()
+                    .fold(prefix.to_string(), |name, idx| format!("{}_{}", name, idx));
+                Expr::Var(name)
+            })
+            .collect()
+    }
+
+    /// Value of the expression with the variables in `vars`, or `None` if a variable is missing.
+    pub fn eval(&self, vars: &HashMap<&str, f64>) -> Option<f64> {
+        Some(match self {
+            Expr::Const(c) => *c,
+            Expr::Var(name) => *vars.get(name.as_str())?,
+            Expr::Add(a, b) => a.eval(vars)? + b.eval(vars)?,
+            Expr::Sub(a, b) => a.eval(vars)? - b.eval(vars)?,
+            Expr::Mul(a, b) => a.eval(vars)? * b.eval(vars)?,
+            Expr::Div(a, b) => a.eval(vars)? / b.eval(vars)?,
+            Expr::Pow(a, b) => a.eval(vars)?.powf(b.eval(vars)?),
+            Expr::Exp(a) => a.eval(vars)?.exp(),
+            Expr::Ln(a) => a.eval(vars)?.ln(),
+        })
+    }
+
+    /// Names of the variables the expression depends on, eg. to check which inputs and parameters
+    /// reach an output of a module.
+    pub fn variables(&self) -> BTreeSet<&str> {
+        let mut variables = BTreeSet::new();
+        self.collect_variables(&mut variables);
+        variables
+    }
+
+    fn collect_variables<'a>(&'a self, variables: &mut BTreeSet<&'a str>) {
+        match self {
+            Expr::Const(_) => {}
+            Expr::Var(name) => {
+                variables.insert(name.as_str());
+            }
+            Expr::Add(a, b)
+            | Expr::Sub(a, b)
+            | Expr::Mul(a, b)
+            | Expr::Div(a, b)
+            | Expr::Pow(a, b) => {
+                a.collect_variables(variables);
+                b.collect_variables(variables);
+            }
+            Expr::Exp(a) | Expr::Ln(a) => a.collect_variables(variables),
+        }
+    }
+
+    /// LaTeX rendering, through `Display`.
+    pub fn latex(&self) -> Latex<'_> {
+        Latex(self)
+    }
+
+    fn as_const(&self) -> Option<f64> {
+        match self {
+            Expr::Const(c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn is_const(&self, value: f64) -> bool {
+        self.as_const() == Some(value)
+    }
+
+    fn is_negative_const(&self) -> bool {
+        self.as_const().is_some_and(|c| c < 0.)
+    }
+
+    /// Binding strength when rendered: an operand binding less tightly than its operator is put
+    /// in parentheses.
+    fn precedence(&self, style: Style) -> u8 {
+        match self {
+            Expr::Const(c) if *c < 0. => 0,
+            Expr::Add(..) | Expr::Sub(..) => 1,
+            Expr::Div(..) if style == Style::Latex => 4,
+            Expr::Mul(..) | Expr::Div(..) => 2,
+            Expr::Exp(..) if style == Style::Latex => 3,
+            Expr::Pow(..) => 3,
+            Expr::Const(_) | Expr::Var(_) | Expr::Exp(_) | Expr::Ln(_) => 4,
+        }
+    }
+
+    fn render(&self, f: &mut Formatter<'_>, style: Style) -> FmtResult {
+        let operand = |f: &mut Formatter<'_>, expr: &Expr, parens: bool| -> FmtResult {
+            if !parens {
+                return expr.render(f, style);
+            }
+            let (open, close) = match style {
+                Style::Plain => ("(", ")"),
+                Style::Latex => ("\\left(", "\\right)"),
+            };
+            write!(f, "{}", open)?;
+            expr.render(f, style)?;
+            write!(f, "{}", close)
+        };
+        let mul = match style {
+            Style::Plain => " * ",
+            Style::Latex => " \\cdot ",
+        };
+
+        match self {
+            Expr::Const(c) => match f.precision() {
+                Some(precision) => write!(f, "{:.*}", precision, c),
+                None => write!(f, "{}", c),
+            },
+            Expr::Var(name) => match style {
+                Style::Plain => write!(f, "{}", name),
+                // `w_0_1` is rendered as `w_{0,1}`.
+                Style::Latex => match name.split_once('_') {
+                    Some((base, indices)) => {
+                        write!(f, "{}_{{{}}}", base, indices.replace('_', ","))
+                    }
+                    None => write!(f, "{}", name),
+                },
+            },
+            Expr::Add(a, b) => {
+                operand(f, a, false)?;
+                match b.as_const() {
+                    Some(c) if c < 0. => {
+                        write!(f, " - ")?;
+                        Expr::Const(-c).render(f, style)
+                    }
+                    _ => {
+                        write!(f, " + ")?;
+                        operand(f, b, false)
+                    }
+                }
+            }
+            Expr::Sub(a, b) => {
+                operand(f, a, false)?;
+                write!(f, " - ")?;
+                operand(f, b, b.precedence(style) <= 1)
+            }
+            Expr::Mul(a, b) => {
+                operand(f, a, a.precedence(style) < 2 && !a.is_negative_const())?;
+                write!(f, "{}", mul)?;
+                operand(f, b, b.precedence(style) < 2)
+            }
+            Expr::Div(a, b) => match style {
+                Style::Plain => {
+                    operand(f, a, a.precedence(style) < 2 && !a.is_negative_const())?;
+                    write!(f, " / ")?;
+                    operand(f, b, b.precedence(style) <= 2)
+                }
+                Style::Latex => {
+                    write!(f, "\\frac{{")?;
+                    a.render(f, style)?;
+                    write!(f, "}}{{")?;
+                    b.render(f, style)?;
+                    write!(f, "}}")
+                }
+            },
+            Expr::Pow(a, b) => {
+                operand(f, a, a.precedence(style) <= 3)?;
+                match style {
+                    Style::Plain => {
+                        write!(f, "^")?;
+                        operand(f, b, b.precedence(style) < 3)
+                    }
+                    Style::Latex => {
+                        write!(f, "^{{")?;
+                        b.render(f, style)?;
+                        write!(f, "}}")
+                    }
+                }
+            }
+            Expr::Exp(a) => match style {
+                Style::Plain => {
+                    write!(f, "exp(")?;
+                    a.render(f, style)?;
+                    write!(f, ")")
+                }
+                Style::Latex => {
+                    write!(f, "e^{{")?;
+                    a.render(f, style)?;
+                    write!(f, "}}")
+                }
+            },
+            Expr::Ln(a) => {
+                write!(f, "{}", if style == Style::Plain { "ln" } else { "\\ln" })?;
+                operand(f, a, true)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Style {
+    Plain,
+    Latex,
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        self.render(f, Style::Plain)
+    }
+}
+
+/// LaTeX rendering of an `Expr`, see `Expr::latex()`.
+pub struct Latex<'a>(&'a Expr);
+
+impl Display for Latex<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        self.0.render(f, Style::Latex)
+    }
+}
+
+impl Add for Expr {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        match (self.as_const(), rhs.as_const()) {
+            (Some(a), Some(b)) => Expr::Const(a + b),
+            (Some(0.), _) => rhs,
+            (_, Some(0.)) => self,
+            _ if self == rhs => Expr::Const(2.) * self,
+            _ => Expr::Add(Rc::new(self), Rc::new(rhs)),
+        }
+    }
+}
+
+impl AddAssign for Expr {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = self.clone() + rhs;
+    }
+}
+
+impl Sub for Expr {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        match (self.as_const(), rhs.as_const()) {
+            (Some(a), Some(b)) => Expr::Const(a - b),
+            (_, Some(0.)) => self,
+            _ if self == rhs => Expr::Const(0.),
+            _ => Expr::Sub(Rc::new(self), Rc::new(rhs)),
+        }
+    }
+}
+
+impl Mul for Expr {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        match (self.as_const(), rhs.as_const()) {
+            (Some(a), Some(b)) => Expr::Const(a * b),
+            (Some(0.), _) | (_, Some(0.)) => Expr::Const(0.),
+            (Some(1.), _) => rhs,
+            (_, Some(1.)) => self,
+            // Coefficients first: 2 * x rather than x * 2.
+            (None, Some(_)) => Expr::Mul(Rc::new(rhs), Rc::new(self)),
+            _ => Expr::Mul(Rc::new(self), Rc::new(rhs)),
+        }
+    }
+}
+
+impl Div for Expr {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        match (self.as_const(), rhs.as_const()) {
+            (Some(a), Some(b)) => Expr::Const(a / b),
+            (_, Some(1.)) => self,
+            _ => Expr::Div(Rc::new(self), Rc::new(rhs)),
+        }
+    }
+}
+
+impl Exp for Expr {
+    fn exp(self) -> Self {
+        match self {
+            Expr::Const(c) => Expr::Const(c.exp()),
+            Expr::Ln(a) => Rc::unwrap_or_clone(a),
+            _ => Expr::Exp(Rc::new(self)),
+        }
+    }
+}
+
+impl Ln for Expr {
+    fn ln(self) -> Self {
+        match self {
+            Expr::Const(c) => Expr::Const(c.ln()),
+            Expr::Exp(a) => Rc::unwrap_or_clone(a),
+            _ => Expr::Ln(Rc::new(self)),
+        }
+    }
+}
+
+impl Pow for Expr {
+    fn pow(self, exp: Self) -> Self {
+        match (self.as_const(), exp.as_const()) {
+            (Some(a), Some(b)) => Expr::Const(a.powf(b)),
+            (_, Some(0.)) => Expr::Const(1.),
+            (_, Some(1.)) => self,
+            (Some(1.), _) => Expr::Const(1.),
+            _ => Expr::Pow(Rc::new(self), Rc::new(exp)),
+        }
+    }
+}
+
+impl Zero for Expr {
+    fn zero() -> Self {
+        Expr::Const(0.)
+    }
+    fn is_zero(&self) -> bool {
+        self.is_const(0.)
+    }
+}
+
+impl Element for Expr {}
+
+impl RealElement for Expr {
+    fn neg_inf() -> Self {
+        Expr::Const(-f64::INFINITY)
+    }
+}
+
+impl From<f64> for Expr {
+    fn from(value: f64) -> Self {
+        Expr::Const(value)
+    }
+}
+
+/// The value of an expression without variables, NaN otherwise.
+impl From<Expr> for f64 {
+    fn from(value: Expr) -> Self {
+        value.eval(&HashMap::new()).unwrap_or(f64::NAN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn x() -> Expr {
+        Expr::var("x")
+    }
+
+    fn y() -> Expr {
+        Expr::var("y")
+    }
+
+    #[test]
+    fn test_simplification() {
+        let zero = Expr::zero();
+        let one = Expr::from(1.);
+        assert_eq!(x() + zero.clone(), x());
+        assert_eq!(zero.clone() + x(), x());
+        assert_eq!(x() - zero.clone(), x());
+        assert_eq!(x() - x(), zero);
+        assert_eq!(x() * zero.clone(), zero);
+        assert_eq!(one.clone() * x(), x());
+        assert_eq!(x() / one.clone(), x());
+        assert_eq!(x().pow(one.clone()), x());
+        assert_eq!(x().pow(zero.clone()), one);
+        assert_eq!(x().exp().ln(), x());
+        assert_eq!(x().ln().exp(), x());
+        assert_eq!(
+            Expr::from(2.) * Expr::from(3.) + Expr::from(1.),
+            Expr::from(7.)
+        );
+        assert_eq!((x() + x()).to_string(), "2 * x");
+        assert_eq!((x() * Expr::from(3.)).to_string(), "3 * x");
+    }
+
+    #[test]
+    fn test_plain_rendering() {
+        let expr = (x() + y()) * (x() - y()) / (x() * y());
+        assert_eq!(expr.to_string(), "(x + y) * (x - y) / (x * y)");
+        assert_eq!((x() - (y() - x())).to_string(), "x - (y - x)");
+        assert_eq!((x() + Expr::from(-2.)).to_string(), "x - 2");
+        assert_eq!((Expr::from(-2.) * x()).to_string(), "-2 * x");
+        assert_eq!((x() * Expr::from(-2.) * y()).to_string(), "-2 * x * y");
+        assert_eq!(
+            (x() + y()).pow(Expr::from(2.)).exp().to_string(),
+            "exp((x + y)^2)"
+        );
+        assert_eq!(x().pow(y().pow(x())).to_string(), "x^y^x");
+        assert_eq!((x() * y()).ln().to_string(), "ln(x * y)");
+        assert_eq!(format!("{:.2}", x() + Expr::from(1. / 3.)), "x + 0.33");
+    }
+
+    #[test]
+    fn test_latex_rendering() {
+        let w = Expr::vars("w", &[2, 2]);
+        let expr = (w[1].clone() * x() + y()) / (x() + Expr::from(1.)).exp();
+        assert_eq!(
+            expr.latex().to_string(),
+            "\\frac{w_{0,1} \\cdot x + y}{e^{x + 1}}"
+        );
+        assert_eq!(
+            (x() + y()).pow(Expr::from(2.)).ln().latex().to_string(),
+            "\\ln\\left(\\left(x + y\\right)^{2}\\right)"
+        );
+        assert_eq!(
+            format!("{:.1}", (Expr::from(0.25) * x()).latex()),
+            "0.2 \\cdot x"
+        );
+    }
+
+    #[test]
+    fn test_vars() {
+        let names: Vec<String> = Expr::vars("w", &[2, 3])
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        assert_eq!(
+            names,
+            ["w_0_0", "w_0_1", "w_0_2", "w_1_0", "w_1_1", "w_1_2"]
+        );
+    }
+
+    #[test]
+    fn test_eval_and_variables() {
+        let expr = (x() * y()).exp() / y() + Expr::from(1.);
+        let vars = HashMap::from([("x", 0.5), ("y", 2.)]);
+        assert_eq!(expr.eval(&vars), Some(1_f64.exp() / 2. + 1.));
+        assert_eq!(expr.eval(&HashMap::from([("x", 0.5)])), None);
+        assert_eq!(expr.variables(), BTreeSet::from(["x", "y"]));
+        assert!(f64::from(expr).is_nan());
+        assert_eq!(f64::from(Expr::from(2.).exp()), 2_f64.exp());
+    }
+}
@@ -79,6 +79,7 @@ where
 mod tests {
 
     use super::*;
+    use elements::symbolic::Expr;
     use tensors::TensorImpl;
 
     #[test]
@@ -122,4 +123,28 @@ mod tests {
         assert_eq!(result.shape(), vec![2, 4, 4]);
         assert_eq!(result.at(vec![0, 1, 0]).unwrap(), &4.8414709848078965_f64);
     }
+
+    #[test]
+    fn test_symbolic_forward() {
+        let x = TensorImpl::from_vec(&vec![1, 2, 4], &Expr::vars("x", &[2, 4])).unwrap();
+        let result = PELayer::new().forward(&x).unwrap();
+        let formulas: Vec<String> = result
+            .get_data()
+            .iter()
+            .map(|y| format!("{:.3}", y))
+            .collect();
+        assert_eq!(
+            formulas,
+            [
+                "x_0_0",
+                "x_0_1 + 1.000",
+                "x_0_2",
+                "x_0_3 + 1.000",
+                "x_1_0 + 0.841",
+                "x_1_1 + 0.540",
+                "x_1_2 + 0.010",
+                "x_1_3 + 1.000"
+            ]
+        );
+    }
 }
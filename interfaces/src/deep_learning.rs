@@ -1,4 +1,5 @@
-use crate::tensors::{AsAnyhowError, Element, Tensor};
+use crate::tensors::{AsAnyhowError, AsStdError, Element, SetValue, Tensor};
+use anyhow::Error;
 use std::fmt::Debug;
 
 /// Deep Learning Module, generic over a Tensor object T and it's elements E.
@@ -15,6 +16,162 @@ where
     fn forward(&self, x: &T) -> Result<T, Self::DLModuleError>;
 
     fn params(&self) -> Vec<E>;
+
+    /// A name for each of the `params()`, in the same order, as its path through the module (eg.
+    /// `2.w[0, 1]` for an element of the weights of the third layer of a `Serial`). Defaults to the
+    /// index of the parameter, eg. `[3]`.
+    fn param_paths(&self) -> Vec<String> {
+        (0..self.params().len())
+            .map(|idx| format!("[{}]", idx))
+            .collect()
+    }
+
+    /// Each parameter tensor of the module as `(path, shape, tensor)`, in `params()` order, eg.
+    /// `blocks.0.attn.query.0.w` for the weights of the query of the first head of the first block
+    /// of a transformer. Defaults to none, for modules without parameters.
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, T)> {
+        Vec::new()
+    }
+
+    /// The tensors of `named_parameters()`, in the same order, for `load_state_dict()`. Errors if
+    /// the parameters cannot be set right now. Modules whose parameters cannot be set at all (eg.
+    /// quantized ones) keep this default.
+    fn parameters_mut(&mut self) -> Result<Vec<&mut T>, AsStdError> {
+        Ok(Vec::new())
+    }
+
+    /// A copy of the values of the `named_parameters()`.
+    fn state_dict(&self) -> StateDict
+    where
+        E: Into<f64>,
+    {
+        StateDict(
+            self.named_parameters()
+                .into_iter()
+                .map(|(path, shape, tensor)| {
+                    (path, shape, tensor.into_iter().map(E::into).collect())
+                })
+                .collect(),
+        )
+    }
+
+    /// Set the parameters to the values of `state`, which must have the paths and shapes of
+    /// `named_parameters()`. Nothing is set if it does not.
+    ///
+    /// The values are set in place: an optimiser of graph nodes collected from `params()` before
+    /// the load steps the loaded values.
+    fn load_state_dict(&mut self, state: &StateDict) -> Result<(), AsStdError>
+    where
+        E: SetValue,
+    {
+        let named = self.named_parameters();
+        let mut state_values = Vec::with_capacity(named.len());
+        for (path, shape, _) in named.iter() {
+            let (_, state_shape, values) = state.get(path).ok_or(Error::msg(format!(
+                "The state dict has no parameter {}.",
+                path
+            )))?;
+            if state_shape != shape || values.len() != shape.iter().product() {
+                return Err(Error::msg(format!(
+                    "The parameter {} has shape {:?}, but the state dict has shape {:?} with {} values.",
+                    path,
+                    shape,
+                    state_shape,
+                    values.len()
+                ))
+                .into());
+            }
+            state_values.push(values);
+        }
+        if let Some((path, _, _)) = state
+            .0
+            .iter()
+            .find(|(path, _, _)| !named.iter().any(|(name, _, _)| name == path))
+        {
+            return Err(Error::msg(format!("The module has no parameter {}.", path)).into());
+        }
+
+        let targets = self.parameters_mut()?;
+        if targets.len() != state_values.len() {
+            return Err(Error::msg("The parameters of the module cannot be set.").into());
+        }
+        for ((target, (_, shape, _)), values) in targets.into_iter().zip(named).zip(state_values) {
+            for (idxs, value) in element_idxs(&shape).zip(values) {
+                target
+                    .at_mut(idxs)
+                    .expect("Ensured the values fit the shape.")
+                    .set_value(*value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch the module (and its sub-modules) between training (`true`) and evaluation mode, eg.
+    /// to only apply dropout during training. Modules whose forward pass is the same in both
+    /// modes, and without sub-modules, can keep this default, which does nothing.
+    fn train(&mut self, _training: bool) {}
+
+    /// Whether the module is in training mode (see `train()`). Defaults to `false` for modules
+    /// that keep the default `train()`.
+    fn is_training(&self) -> bool {
+        false
+    }
+}
+
+/// The paths of the elements of the tensor `name` of shape `shape`, in row major order, eg.
+/// `w[0, 0]`, `w[0, 1]`... For implementing `DLModule::param_paths()`.
+pub fn element_paths(name: &str, shape: &[usize]) -> Vec<String> {
+    element_idxs(shape)
+        .map(|idxs| {
+            let idxs: Vec<String> = idxs.iter().map(|idx| idx.to_string()).collect();
+            format!("{}[{}]", name, idxs.join(", "))
+        })
+        .collect()
+}
+
+/// The indices of the elements of a tensor of shape `shape`, in row major order.
+fn element_idxs(shape: &[usize]) -> impl Iterator<Item = Vec<usize>> + '_ {
+    let num_elements: usize = shape.iter().product();
+    (0..num_elements).map(move |mut flat_idx| {
+        let mut idxs = vec![0; shape.len()];
+        for (idx, dim) in idxs.iter_mut().zip(shape.iter()).rev() {
+            *idx = flat_idx % dim;
+            flat_idx /= dim;
+        }
+        idxs
+    })
+}
+
+/// Prefix each of `paths` with the name of the sub-module they belong to, eg. `blocks.0`.
+pub fn prefix_paths(prefix: &str, paths: Vec<String>) -> Vec<String> {
+    paths
+        .into_iter()
+        .map(|path| format!("{}.{}", prefix, path))
+        .collect()
+}
+
+/// Prefix the paths of `named_parameters` with the name of the sub-module they belong to, as
+/// `prefix_paths()`. For implementing `DLModule::named_parameters()`.
+pub fn prefix_named_parameters<T>(
+    prefix: &str,
+    named_parameters: Vec<(String, Vec<usize>, T)>,
+) -> Vec<(String, Vec<usize>, T)> {
+    named_parameters
+        .into_iter()
+        .map(|(path, shape, tensor)| (format!("{}.{}", prefix, path), shape, tensor))
+        .collect()
+}
+
+/// The values of the parameters of a module as `(path, shape, values)`, the values in row major
+/// order. Returned by `DLModule::state_dict()` and loaded by `DLModule::load_state_dict()`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StateDict(pub Vec<(String, Vec<usize>, Vec<f64>)>);
+
+impl StateDict {
+    /// The entry of the parameter `path`, if any.
+    pub fn get(&self, path: &str) -> Option<&(String, Vec<usize>, Vec<f64>)> {
+        self.0.iter().find(|(name, _, _)| name == path)
+    }
 }
 
 /// A convenince-only Subtrait of `DLModule` to specifiy a module that does linear transformation.
@@ -110,6 +110,24 @@ pub trait RealElement: Element + Exp + Pow + Ln + From<f64> {
     fn neg_inf() -> Self;
 }
 
+/// Elements on which piecewise functions (eg. the ReLU of an activation layer) can choose their
+/// branch.
+pub trait Piecewise: Sized {
+    /// `positive(self)` if `self > 0`, `non_positive(self)` otherwise. Elements standing for a set
+    /// of values (eg. intervals) may combine both branches when the set straddles 0.
+    fn piecewise(
+        self,
+        positive: impl FnOnce(Self) -> Self,
+        non_positive: impl FnOnce(Self) -> Self,
+    ) -> Self;
+}
+
+/// Elements whose value can be set in place, eg. by `DLModule::load_state_dict()`. A graph node
+/// keeps its identity, so that the clones of it (eg. held by an optimiser) see the new value.
+pub trait SetValue {
+    fn set_value(&mut self, value: f64);
+}
+
 // Below are some implementations of `Element` and `RealElement` "for free". This should facilitate
 // unit testing with these types.
 impl Element for usize {}
@@ -123,3 +141,23 @@ impl RealElement for f64 {
         -std::f64::INFINITY
     }
 }
+
+impl SetValue for f64 {
+    fn set_value(&mut self, value: f64) {
+        *self = value;
+    }
+}
+
+impl Piecewise for f64 {
+    fn piecewise(
+        self,
+        positive: impl FnOnce(Self) -> Self,
+        non_positive: impl FnOnce(Self) -> Self,
+    ) -> Self {
+        if self > 0. {
+            positive(self)
+        } else {
+            non_positive(self)
+        }
+    }
+}
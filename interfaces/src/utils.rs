@@ -15,6 +15,11 @@ pub trait Ln {
     fn ln(self) -> Self;
 }
 
+/// Take the complex conjugate of `self`. The identity for real numbers.
+pub trait Conj {
+    fn conj(self) -> Self;
+}
+
 // The below implementations are required for f64 to implement `RealElement`.
 impl Exp for f64 {
     fn exp(self) -> Self {
@@ -33,3 +38,9 @@ impl Ln for f64 {
         self.ln()
     }
 }
+
+impl Conj for f64 {
+    fn conj(self) -> Self {
+        self
+    }
+}
@@ -1,12 +1,182 @@
 // This module contains the activation layer struct and its implementation.
+use anyhow::Error;
 use interfaces::{
     deep_learning::{ActivationLayer, DLModule},
-    tensors::{Element, Tensor},
+    tensors::{AsStdError, Piecewise, RealElement, Tensor},
 };
 use std::iter::Iterator;
 use std::marker::PhantomData;
+use std::{fmt::Display, str::FromStr};
 
-pub struct ActLayer<T: Tensor<E>, E: Element> {
+/// The element-wise activation functions of `ActLayer`.
+///
+/// Each is written in terms of the arithmetic, `Exp` and `Ln` of the elements, so that it is
+/// differentiable through `Node` (and the other differentiable elements). Piecewise functions
+/// choose their branch with `Piecewise`.
+///
+/// Also parsed from (and displayed as) a config string: `relu`, `leaky_relu`, `gelu`,
+/// `gelu_tanh`, `silu` (or `swish`), `sigmoid`, `tanh`, `softplus` and `elu`, with the parameter of
+/// `leaky_relu` (default 0.01) and `elu` (default 1) optionally in brackets, eg. `elu(0.5)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activation {
+    ReLU,
+    /// `x` for positive `x`, else `slope * x`.
+    LeakyReLU(f64),
+    /// The exact GELU `x * Phi(x)`, with `Phi` the standard normal CDF, ie.
+    /// `0.5 * (1 + erf(x / sqrt(2)))`. erf is evaluated to within 2e-15.
+    GELU,
+    /// The tanh approximation of GELU:
+    /// `0.5 * x * (1 + tanh(sqrt(2 / pi) * (x + 0.044715 * x^3)))`.
+    GELUTanh,
+    /// Also known as Swish: `x * sigmoid(x)`.
+    SiLU,
+    Sigmoid,
+    Tanh,
+    /// `ln(1 + e^x)`.
+    Softplus,
+    /// `x` for positive `x`, else `alpha * (e^x - 1)`.
+    ELU(f64),
+}
+
+fn neg<E: RealElement>(x: E) -> E {
+    E::from(-1.0) * x
+}
+
+/// Computed from `e^-|x|`, which cannot overflow.
+fn sigmoid<E: RealElement + Piecewise>(x: E) -> E {
+    x.piecewise(
+        |x| E::from(1.0) / (E::from(1.0) + neg(x).exp()),
+        |x| {
+            let exp = x.exp();
+            exp.clone() / (E::from(1.0) + exp)
+        },
+    )
+}
+
+fn tanh<E: RealElement + Piecewise>(x: E) -> E {
+    E::from(2.0) * sigmoid(E::from(2.0) * x) - E::from(1.0)
+}
+
+/// erf to within 2e-15, ie. exact up to rounding, extended to negative `x` by symmetry.
+fn erf<E: RealElement + Piecewise>(x: E) -> E {
+    x.piecewise(erf_non_negative, |x| neg(erf_non_negative(neg(x))))
+}
+
+/// Below this, erf is summed from its series, above, from the continued fraction of erfc.
+const ERF_SPLIT: f64 = 2.5;
+
+fn erf_non_negative<E: RealElement + Piecewise>(x: E) -> E {
+    (x - E::from(ERF_SPLIT)).piecewise(
+        |d| E::from(1.0) - erfc_continued_fraction(d + E::from(ERF_SPLIT)),
+        |d| erf_series(d + E::from(ERF_SPLIT)),
+    )
+}
+
+/// `2 / sqrt(pi) * e^-x^2 * sum_n x * (2 * x^2)^n / (1 * 3 * ... * (2n + 1))`, whose terms are all
+/// positive (no cancellation). 40 terms are enough up to `ERF_SPLIT`.
+fn erf_series<E: RealElement>(x: E) -> E {
+    let two_x_sq = E::from(2.0) * x.clone() * x.clone();
+    let (_, sum) = (1..=40).fold((x.clone(), x.clone()), |(term, sum), n| {
+        let term = term * two_x_sq.clone() / E::from((2 * n + 1) as f64);
+        (term.clone(), sum + term)
+    });
+    E::from(std::f64::consts::FRAC_2_SQRT_PI) * neg(x.clone() * x).exp() * sum
+}
+
+/// Laplace's continued fraction
+/// `erfc(x) = e^-x^2 / sqrt(pi) / (x + (1/2) / (x + (2/2) / (x + (3/2) / (x + ...))))`, cut after 30
+/// terms, enough from `ERF_SPLIT` on.
+fn erfc_continued_fraction<E: RealElement>(x: E) -> E {
+    let denominator = (1..=30).rev().fold(x.clone(), |acc, k| {
+        x.clone() + E::from(k as f64 / 2.0) / acc
+    });
+    E::from(0.5 * std::f64::consts::FRAC_2_SQRT_PI) * neg(x.clone() * x).exp() / denominator
+}
+
+impl Activation {
+    pub fn apply<E: RealElement + Piecewise>(&self, x: E) -> E {
+        match self {
+            Activation::ReLU => x.piecewise(|x| x, |_| E::zero()),
+            Activation::LeakyReLU(slope) => x.piecewise(|x| x, |x| E::from(*slope) * x),
+            Activation::GELU => {
+                let cdf = E::from(0.5)
+                    * (E::from(1.0) + erf(x.clone() / E::from(std::f64::consts::SQRT_2)));
+                x * cdf
+            }
+            Activation::GELUTanh => {
+                let inner = E::from((2.0 / std::f64::consts::PI).sqrt())
+                    * (x.clone() + E::from(0.044715) * x.clone() * x.clone() * x.clone());
+                E::from(0.5) * x * (E::from(1.0) + tanh(inner))
+            }
+            Activation::SiLU => x.clone() * sigmoid(x),
+            Activation::Sigmoid => sigmoid(x),
+            Activation::Tanh => tanh(x),
+            // x + ln(1 + e^-x) for positive x, so that the exponential cannot overflow.
+            Activation::Softplus => x.piecewise(
+                |x| x.clone() + (E::from(1.0) + neg(x).exp()).ln(),
+                |x| (E::from(1.0) + x.exp()).ln(),
+            ),
+            Activation::ELU(alpha) => {
+                x.piecewise(|x| x, |x| E::from(*alpha) * (x.exp() - E::from(1.0)))
+            }
+        }
+    }
+}
+
+impl Display for Activation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Activation::ReLU => write!(f, "relu"),
+            Activation::LeakyReLU(slope) => write!(f, "leaky_relu({})", slope),
+            Activation::GELU => write!(f, "gelu"),
+            Activation::GELUTanh => write!(f, "gelu_tanh"),
+            Activation::SiLU => write!(f, "silu"),
+            Activation::Sigmoid => write!(f, "sigmoid"),
+            Activation::Tanh => write!(f, "tanh"),
+            Activation::Softplus => write!(f, "softplus"),
+            Activation::ELU(alpha) => write!(f, "elu({})", alpha),
+        }
+    }
+}
+
+impl FromStr for Activation {
+    type Err = AsStdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, param) = match s.strip_suffix(')').and_then(|s| s.split_once('(')) {
+            Some((name, param)) => {
+                let param = param.trim().parse::<f64>().map_err(|_| {
+                    Error::msg(format!("Invalid parameter in the activation {:?}.", s))
+                })?;
+                (name.trim(), Some(param))
+            }
+            None => (s, None),
+        };
+        let activation = match name {
+            "leaky_relu" => return Ok(Activation::LeakyReLU(param.unwrap_or(0.01))),
+            "elu" => return Ok(Activation::ELU(param.unwrap_or(1.0))),
+            "relu" => Activation::ReLU,
+            "gelu" => Activation::GELU,
+            "gelu_tanh" => Activation::GELUTanh,
+            "silu" | "swish" => Activation::SiLU,
+            "sigmoid" => Activation::Sigmoid,
+            "tanh" => Activation::Tanh,
+            "softplus" => Activation::Softplus,
+            _ => return Err(Error::msg(format!("Unknown activation {:?}.", s)).into()),
+        };
+        match param {
+            Some(_) => {
+                Err(Error::msg(format!("The activation {} takes no parameter.", name)).into())
+            }
+            None => Ok(activation),
+        }
+    }
+}
+
+/// Applies an `Activation` element-wise, to a tensor of any shape.
+pub struct ActLayer<T: Tensor<E>, E: RealElement> {
+    pub activation: Activation,
     tensor_phantom: PhantomData<T>,
     tensor_element_phantom: PhantomData<E>,
 }
@@ -14,26 +184,17 @@ pub struct ActLayer<T: Tensor<E>, E: Element> {
 impl<T, E> DLModule<T, E> for ActLayer<T, E>
 where
     T: Tensor<E>,
-    E: Element + Into<f64>,
+    E: RealElement + Piecewise,
 {
     type DLModuleError = <T as Tensor<E>>::TensorError;
 
     fn forward(&self, x: &T) -> Result<T, Self::DLModuleError> {
-        let input_shape = x.shape();
-        // The shape of the input tensor must be (B, T, C)
-        if input_shape.len() != 3 {
-            return Err(
-                anyhow::Error::msg("The shape of the input tensor must be (B, T, C)").into(),
-            );
-        } else {
-            // The activation function is the ReLU function
-            let relu: Vec<_> = x
-                .clone()
-                .into_iter()
-                .map(|x| if x.clone().into() > 0.0 { x } else { E::zero() })
-                .collect();
-            return Ok(T::from_vec(&input_shape, &relu)?);
-        }
+        let activated: Vec<E> = x
+            .clone()
+            .into_iter()
+            .map(|x| self.activation.apply(x))
+            .collect();
+        T::from_vec(&x.shape(), &activated)
     }
 
     // The activation layer has no parameters, return an empty vector
@@ -45,17 +206,23 @@ where
 impl<T, E> ActivationLayer<T, E> for ActLayer<T, E>
 where
     T: Tensor<E>,
-    E: Element + Into<f64>,
+    E: RealElement + Piecewise,
 {
 }
 
 impl<T, E> ActLayer<T, E>
 where
     T: Tensor<E>,
-    E: Element,
+    E: RealElement,
 {
+    /// A ReLU layer.
     pub fn new() -> Self {
+        Self::with_activation(Activation::ReLU)
+    }
+
+    pub fn with_activation(activation: Activation) -> Self {
         ActLayer {
+            activation,
             tensor_phantom: PhantomData,
             tensor_element_phantom: PhantomData,
         }
@@ -65,8 +232,21 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use autodiff::node::Node;
     use tensors::TensorImpl;
 
+    const ACTIVATIONS: [Activation; 9] = [
+        Activation::ReLU,
+        Activation::LeakyReLU(0.1),
+        Activation::GELU,
+        Activation::GELUTanh,
+        Activation::SiLU,
+        Activation::Sigmoid,
+        Activation::Tanh,
+        Activation::Softplus,
+        Activation::ELU(0.5),
+    ];
+
     #[test]
     fn construct_act_layer() {
         // The activation layer is constructed without any errors
@@ -83,6 +263,17 @@ mod tests {
         assert_eq!(out.shape(), vec![2, 2, 2]);
     }
 
+    #[test]
+    fn any_rank_forward() {
+        let layer: ActLayer<TensorImpl<f64>, f64> = ActLayer::with_activation(Activation::Tanh);
+        for shape in [vec![3], vec![3, 1], vec![1, 3, 1, 1]] {
+            let x = TensorImpl::from_vec(&shape, &vec![0.0, 1.0, -1.0]).unwrap();
+            let out = layer.forward(&x).unwrap();
+            assert_eq!(out.shape(), shape);
+            assert_eq!(out.get_data()[0], 0.0);
+        }
+    }
+
     #[test]
     fn no_negative_values() {
         // The ReLU function is applied to the input tensor
@@ -94,4 +285,123 @@ mod tests {
             TensorImpl::from_vec(&vec![1, 2, 2], &vec![0.0, 0.0, 0.0, 1.0]).unwrap()
         )
     }
+
+    #[test]
+    fn activation_values() {
+        let expected = |activation: Activation, x: f64| match activation {
+            Activation::ReLU => x.max(0.0),
+            Activation::LeakyReLU(slope) => x.max(slope * x),
+            // Phi(x) for x = -3 .. 3
+            Activation::GELU => {
+                let cdf = [
+                    0.0013498980316301,
+                    0.0227501319481792,
+                    0.1586552539314571,
+                    0.5,
+                    0.8413447460685429,
+                    0.9772498680518208,
+                    0.9986501019683699,
+                ];
+                x * cdf[(x + 3.0) as usize]
+            }
+            Activation::GELUTanh => {
+                let inner = (2.0 / std::f64::consts::PI).sqrt() * (x + 0.044715 * x.powi(3));
+                0.5 * x * (1.0 + inner.tanh())
+            }
+            Activation::SiLU => x / (1.0 + (-x).exp()),
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            Activation::Tanh => x.tanh(),
+            Activation::Softplus => x.exp().ln_1p(),
+            Activation::ELU(alpha) => {
+                if x > 0.0 {
+                    x
+                } else {
+                    alpha * x.exp_m1()
+                }
+            }
+        };
+        for activation in ACTIVATIONS {
+            for x in [-3.0, -2.0, -1.0, 0.0, 1.0, 2.0, 3.0] {
+                let (actual, expected) = (activation.apply(x), expected(activation, x));
+                assert!((actual - expected).abs() < 1e-6, "{} of {}", activation, x);
+            }
+        }
+        // No overflow for large inputs.
+        assert_eq!(Activation::Sigmoid.apply(-1000.0), 0.0);
+        assert_eq!(Activation::Tanh.apply(1000.0), 1.0);
+        assert_eq!(Activation::Softplus.apply(1000.0), 1000.0);
+    }
+
+    #[test]
+    fn erf_values() {
+        let expected = [
+            (0.1, 0.1124629160182849),
+            (0.5, 0.5204998778130465),
+            (1.0, 0.8427007929497149),
+            (2.0, 0.9953222650189527),
+            (2.5, 0.999593047982555),
+            (3.0, 0.9999779095030014),
+            (4.0, 0.9999999845827421),
+            (6.0, 1.0),
+        ];
+        for (x, erf_x) in expected {
+            assert!((erf(x) - erf_x).abs() < 2e-15, "erf({}) = {}", x, erf(x));
+            assert!((erf(-x) + erf_x).abs() < 2e-15, "erf({}) = {}", -x, erf(-x));
+        }
+        assert_eq!(erf(0.0), 0.0);
+        assert_eq!(erf(f64::INFINITY), 1.0);
+        // Both sides of the split have the derivative 2 / sqrt(pi) * e^-x^2.
+        for x in [0.3, 2.4, 2.6, 5.0] {
+            let input = Node::new(x, None);
+            let mut output = erf(input.clone());
+            output.backward(1.0);
+            let expected = std::f64::consts::FRAC_2_SQRT_PI * (-x * x).exp();
+            assert!((input.grad().unwrap() - expected).abs() < 1e-14);
+        }
+    }
+
+    #[test]
+    fn activation_gradients() {
+        // Away from the kinks of the piecewise activations.
+        let h = 1e-6;
+        for activation in ACTIVATIONS {
+            for x in [-2.5, -0.7, -0.1, 0.3, 1.4, 4.0] {
+                let input = Node::new(x, None);
+                let mut output = activation.apply(input.clone());
+                output.backward(1.0);
+                let numeric = (activation.apply(x + h) - activation.apply(x - h)) / (2.0 * h);
+                let grad = input.grad().unwrap_or(0.0);
+                assert!(
+                    (grad - numeric).abs() < 1e-6,
+                    "{} at {}: {} != {}",
+                    activation,
+                    x,
+                    grad,
+                    numeric
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parse_activation() {
+        for activation in ACTIVATIONS {
+            assert_eq!(
+                activation.to_string().parse::<Activation>().unwrap(),
+                activation
+            );
+        }
+        assert_eq!("swish".parse::<Activation>().unwrap(), Activation::SiLU);
+        assert_eq!(
+            "leaky_relu".parse::<Activation>().unwrap(),
+            Activation::LeakyReLU(0.01)
+        );
+        assert_eq!(
+            " elu( 2 ) ".parse::<Activation>().unwrap(),
+            Activation::ELU(2.0)
+        );
+        assert!("gelu(1)".parse::<Activation>().is_err());
+        assert!("elu(x)".parse::<Activation>().is_err());
+        assert!("mish".parse::<Activation>().is_err());
+    }
 }
@@ -0,0 +1,387 @@
+// This module contains the gradient checkpointing wrapper and its implementation.
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::Error;
+use autodiff::graph_ptr::{GraphPtr, Local, Shared};
+use autodiff::no_grad::{is_grad_enabled, no_grad};
+use autodiff::node::{GenericNode, Node, SyncNode};
+use interfaces::deep_learning::DLModule;
+use interfaces::tensors::{AsStdError, Tensor};
+use tensors::TensorImpl;
+
+/// Gradient checkpointing: trade compute for memory by not keeping the computation graph of the
+/// wrapped module alive between the forward and the backward pass.
+///
+/// `forward()` runs the module without building a graph and returns fresh nodes holding only the
+/// output values; the input is kept instead. When `backward()` reaches those outputs, the forward
+/// pass of the module is recomputed with grad enabled and the output gradients are propagated
+/// through it, into the parameters of the module and the input. The peak memory of a stack of
+/// checkpointed `Block`s is then roughly that of a single block plus the block boundaries.
+///
+/// Only `Node::backward()` recomputes checkpointed segments, `grad_wrt()` and the functions in
+/// `autodiff::functional` see the outputs as constants. If the input of a checkpointed module is
+/// also used elsewhere in the graph, backward still computes correct gradients, but hooks on the
+/// nodes below the input are called once per path rather than once with the total gradient.
+///
+/// The graph of a checkpointed forward pass shares the module, whose parameters cannot be set
+/// until that graph is dropped (or released, see `release_graph()`): `load_state_dict()` errors
+/// in the meantime. `train()` can switch modes at any time, the backward pass of a graph
+/// recomputes the module in the mode of its forward pass.
+pub struct Checkpoint<M> {
+    module: Arc<Mutex<M>>,
+}
+
+impl<M> Checkpoint<M> {
+    pub fn new(module: M) -> Self {
+        Checkpoint {
+            module: Arc::new(Mutex::new(module)),
+        }
+    }
+
+    /// The wrapped module, locked until the guard is dropped.
+    pub fn module(&self) -> MutexGuard<'_, M> {
+        lock(&self.module)
+    }
+}
+
+fn lock<M>(module: &Mutex<M>) -> MutexGuard<'_, M> {
+    module.lock().expect("Lock poisoned by a panicking thread.")
+}
+
+impl<M, P> DLModule<TensorImpl<GenericNode<f64, P>>, GenericNode<f64, P>> for Checkpoint<M>
+where
+    M: DLModule<TensorImpl<GenericNode<f64, P>>, GenericNode<f64, P>, DLModuleError = AsStdError>,
+    P: GraphPtr,
+    Segment<M, P>: RegisterSegment<P>,
+{
+    type DLModuleError = AsStdError;
+
+    fn forward(
+        &self,
+        x: &TensorImpl<GenericNode<f64, P>>,
+    ) -> Result<TensorImpl<GenericNode<f64, P>>, Self::DLModuleError> {
+        let module = self.module();
+        if !is_grad_enabled() {
+            return module.forward(x);
+        }
+        let values = {
+            let _guard = no_grad();
+            module.forward(x)?
+        };
+
+        // Every output depends on the same `anchor` leaf, which backward therefore reaches after
+        // the gradients of all the outputs are complete.
+        let output_grads = Arc::new(Mutex::new(vec![0.0; values.get_data().len()]));
+        let anchor = GenericNode::new(0.0, None);
+        let outputs: Vec<GenericNode<f64, P>> = values
+            .get_data()
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| {
+                let output = GenericNode::new(value.val(), None) + anchor.clone();
+                let output_grads = output_grads.clone();
+                output.register_hook(move |grad| {
+                    output_grads
+                        .lock()
+                        .expect("Lock poisoned by a panicking thread.")[idx] += grad;
+                    grad
+                });
+                output
+            })
+            .collect();
+
+        Segment {
+            module: self.module.clone(),
+            training: module.is_training(),
+            input: x.clone(),
+            output_grads,
+        }
+        .register_on(&anchor);
+        TensorImpl::from_vec(&values.shape(), &outputs)
+    }
+
+    fn params(&self) -> Vec<GenericNode<f64, P>> {
+        self.module().params()
+    }
+
+    fn param_paths(&self) -> Vec<String> {
+        self.module().param_paths()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, TensorImpl<GenericNode<f64, P>>)> {
+        self.module().named_parameters()
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut TensorImpl<GenericNode<f64, P>>>, AsStdError> {
+        Arc::get_mut(&mut self.module)
+            .ok_or(Error::msg(
+                "Cannot set parameters while the graph of a checkpointed forward pass is alive.",
+            ))?
+            .get_mut()
+            .expect("Lock poisoned by a panicking thread.")
+            .parameters_mut()
+    }
+
+    fn train(&mut self, training: bool) {
+        self.module().train(training);
+    }
+
+    fn is_training(&self) -> bool {
+        self.module().is_training()
+    }
+}
+
+/// The state needed to recompute a checkpointed module during backward.
+pub struct Segment<M, P: GraphPtr> {
+    module: Arc<Mutex<M>>,
+    /// The mode of the module during the forward pass.
+    training: bool,
+    input: TensorImpl<GenericNode<f64, P>>,
+    output_grads: Arc<Mutex<Vec<f64>>>,
+}
+
+impl<M, P> Segment<M, P>
+where
+    M: DLModule<TensorImpl<GenericNode<f64, P>>, GenericNode<f64, P>, DLModuleError = AsStdError>,
+    P: GraphPtr,
+{
+    fn recompute_backward(&self) {
+        let output_grads = {
+            let mut output_grads = self
+                .output_grads
+                .lock()
+                .expect("Lock poisoned by a panicking thread.");
+            let zeros = vec![0.0; output_grads.len()];
+            std::mem::replace(&mut *output_grads, zeros)
+        };
+        let outputs = {
+            // Not held during backward, which may recompute other segments of the module.
+            let mut module = lock(&self.module);
+            let training = module.is_training();
+            module.train(self.training);
+            let outputs = module.forward(&self.input);
+            module.train(training);
+            outputs.expect("The forward pass succeeded before the recomputation.")
+        };
+
+        // Backward from several outputs at once: the gradient of sum(output_grad . output).
+        let mut weighted_sum = outputs
+            .into_iter()
+            .zip(output_grads)
+            .fold(GenericNode::new(0.0, None), |acc, (output, output_grad)| {
+                acc + output * GenericNode::new(output_grad, None)
+            });
+        weighted_sum.backward(1.0);
+    }
+}
+
+/// Registration of the recomputation as a hook on the anchor node, which for `Node`s may capture
+/// data that cannot be shared between threads.
+pub trait RegisterSegment<P: GraphPtr> {
+    fn register_on(self, anchor: &GenericNode<f64, P>);
+}
+
+impl<M> RegisterSegment<Local> for Segment<M, Local>
+where
+    M: DLModule<TensorImpl<Node<f64>>, Node<f64>, DLModuleError = AsStdError> + 'static,
+{
+    fn register_on(self, anchor: &Node<f64>) {
+        anchor.register_local_hook(move |grad| {
+            self.recompute_backward();
+            grad
+        });
+    }
+}
+
+impl<M> RegisterSegment<Shared> for Segment<M, Shared>
+where
+    M: DLModule<TensorImpl<SyncNode<f64>>, SyncNode<f64>, DLModuleError = AsStdError>
+        + Send
+        + Sync
+        + 'static,
+{
+    fn register_on(self, anchor: &SyncNode<f64>) {
+        anchor.register_hook(move |grad| {
+            self.recompute_backward();
+            grad
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::act_layer::ActLayer;
+    use crate::dropout::Dropout;
+    use crate::lin_layer::LinLayer;
+    use crate::serial::Serial;
+
+    use super::*;
+
+    type Tn = TensorImpl<Node<f64>>;
+
+    fn mlp(seed: u64) -> Serial<Tn, Node<f64>> {
+        Serial::new(vec![
+            Box::new(LinLayer::new(4, 3, seed)),
+            Box::new(ActLayer::new()),
+            Box::new(LinLayer::new(3, 2, seed + 1)),
+        ])
+    }
+
+    fn input() -> Tn {
+        let data: Vec<Node<f64>> = (0..8)
+            .map(|x| Node::new((x as f64 - 3.5) / 2.0, None))
+            .collect();
+        TensorImpl::from_vec(&vec![2, 1, 4], &data).unwrap()
+    }
+
+    fn sum_of_squares<P: GraphPtr>(out: TensorImpl<GenericNode<f64, P>>) -> GenericNode<f64, P> {
+        out.into_iter()
+            .fold(GenericNode::new(0.0, None), |acc, y| acc + y.clone() * y)
+    }
+
+    fn grads(params: &[Node<f64>]) -> Vec<f64> {
+        params.iter().map(|p| p.grad().unwrap_or(0.0)).collect()
+    }
+
+    fn assert_all_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!(f64::abs(a - e) < 1e-12, "{} != {}", a, e);
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_matches_plain_module() {
+        let plain = mlp(0);
+        let x_plain = input();
+        let out_plain = plain.forward(&x_plain).unwrap();
+        sum_of_squares(out_plain.clone()).backward(1.0);
+
+        let checkpointed = Checkpoint::new(mlp(0));
+        let x = input();
+        let out = checkpointed.forward(&x).unwrap();
+        assert_eq!(out.shape(), out_plain.shape());
+        for (y, y_plain) in out.get_data().iter().zip(out_plain.get_data().iter()) {
+            assert_eq!(y.val(), y_plain.val());
+        }
+        sum_of_squares(out).backward(1.0);
+
+        assert_all_close(&grads(&checkpointed.params()), &grads(&plain.params()));
+        assert_all_close(&grads(x.get_data()), &grads(x_plain.get_data()));
+    }
+
+    #[test]
+    fn test_stacked_checkpoints() {
+        let plain = (mlp(0), LinLayer::<Tn, Node<f64>>::new(2, 3, 5));
+        let x_plain = input();
+        let out_plain = plain
+            .1
+            .forward(&plain.0.forward(&x_plain).unwrap())
+            .unwrap();
+        sum_of_squares(out_plain).backward(1.0);
+
+        let checkpointed = (
+            Checkpoint::new(mlp(0)),
+            Checkpoint::new(LinLayer::<Tn, Node<f64>>::new(2, 3, 5)),
+        );
+        let x = input();
+        let hidden = checkpointed.0.forward(&x).unwrap();
+        let out = checkpointed.1.forward(&hidden).unwrap();
+        sum_of_squares(out).backward(1.0);
+
+        assert_all_close(&grads(&checkpointed.0.params()), &grads(&plain.0.params()));
+        assert_all_close(&grads(&checkpointed.1.params()), &grads(&plain.1.params()));
+        assert_all_close(&grads(x.get_data()), &grads(x_plain.get_data()));
+    }
+
+    #[test]
+    fn test_outputs_hold_no_graph_of_the_module() {
+        let checkpointed = Checkpoint::new(mlp(0));
+        let out = checkpointed.forward(&input()).unwrap();
+        for y in out.get_data().iter() {
+            // The output value and the anchor.
+            assert!(y.children().iter().all(|child| child.is_leaf()));
+        }
+    }
+
+    #[test]
+    fn test_train_while_graph_alive() {
+        let with_dropout = |seed| {
+            let mut module: Serial<Tn, Node<f64>> = Serial::new(vec![
+                Box::new(LinLayer::new(4, 3, seed)),
+                Box::new(Dropout::new(0.5, seed)),
+            ]);
+            module.train(false);
+            module
+        };
+        let plain = with_dropout(0);
+        sum_of_squares(plain.forward(&input()).unwrap()).backward(1.0);
+
+        // Switching to training mode does not change the recomputation of an evaluation pass.
+        let mut checkpointed = Checkpoint::new(with_dropout(0));
+        let mut loss = sum_of_squares(checkpointed.forward(&input()).unwrap());
+        checkpointed.train(true);
+        assert!(checkpointed.is_training());
+        loss.backward(1.0);
+        assert_all_close(&grads(&checkpointed.params()), &grads(&plain.params()));
+        assert!(checkpointed.is_training());
+    }
+
+    #[test]
+    fn test_load_state_dict_while_graph_alive() {
+        let mut checkpointed = Checkpoint::new(mlp(0));
+        let state = mlp(1).state_dict();
+        let before = checkpointed.state_dict();
+        let mut loss = sum_of_squares(checkpointed.forward(&input()).unwrap());
+        let err = checkpointed.load_state_dict(&state).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("graph of a checkpointed forward pass is alive"));
+        assert_eq!(checkpointed.state_dict(), before);
+
+        // Releasing the graph after backward frees the module.
+        loss.backward(1.0);
+        loss.release_graph();
+        checkpointed.load_state_dict(&state).unwrap();
+        assert_eq!(checkpointed.state_dict(), state);
+    }
+
+    #[test]
+    fn test_backward_twice_accumulates_twice() {
+        let checkpointed = Checkpoint::new(mlp(0));
+        let mut loss = sum_of_squares(checkpointed.forward(&input()).unwrap());
+        loss.backward(1.0);
+        let once = grads(&checkpointed.params());
+        loss.backward(1.0);
+        let twice: Vec<f64> = once.iter().map(|grad| 2.0 * grad).collect();
+        assert_all_close(&grads(&checkpointed.params()), &twice);
+    }
+
+    #[test]
+    fn test_no_grad_forward() {
+        let checkpointed = Checkpoint::new(mlp(0));
+        let _guard = no_grad();
+        let out = checkpointed.forward(&input()).unwrap();
+        assert!(out.get_data().iter().all(|y| y.is_leaf()));
+    }
+
+    #[test]
+    fn test_checkpoint_sync_node() {
+        type Ts = TensorImpl<SyncNode<f64>>;
+        let plain = LinLayer::<Ts, SyncNode<f64>>::new(4, 3, 0);
+        let checkpointed = Checkpoint::new(LinLayer::<Ts, SyncNode<f64>>::new(4, 3, 0));
+        let x = TensorImpl::from_vec(&vec![1, 4], &vec![SyncNode::new(1.5, None); 4]).unwrap();
+
+        sum_of_squares(plain.forward(&x).unwrap()).backward(1.0);
+        let handle = std::thread::spawn(move || {
+            sum_of_squares(checkpointed.forward(&x).unwrap()).backward(1.0);
+            checkpointed
+        });
+        let checkpointed = handle.join().unwrap();
+
+        let grads = |params: Vec<SyncNode<f64>>| -> Vec<f64> {
+            params.iter().map(|p| p.grad().unwrap()).collect()
+        };
+        assert_all_close(&grads(checkpointed.params()), &grads(plain.params()));
+    }
+}
@@ -0,0 +1,152 @@
+// This module contains the dropout layer and its implementation.
+use interfaces::{
+    deep_learning::DLModule,
+    tensors::{RealElement, Tensor},
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Dropout ([Srivastava et al.](https://jmlr.org/papers/v15/srivastava14a.html)): in training mode,
+/// zero each element of the input with probability `p` and scale the others by `1 / (1 - p)`
+/// ("inverted" dropout), so that evaluation mode is the identity.
+///
+/// The masks are reproducible: the `n`th forward pass in training mode draws its mask from stream
+/// `n` of a `ChaCha8Rng` seeded with `seed`. A module recomputed by a `Checkpoint` therefore draws
+/// a different mask in the recomputation: checkpoint modules with dropout in evaluation mode only.
+pub struct Dropout<T: Tensor<E>, E: RealElement> {
+    pub p: f64,
+    pub seed: u64,
+    training: bool,
+    num_calls: AtomicU64,
+    tensor_phantom: PhantomData<T>,
+    tensor_element_phantom: PhantomData<E>,
+}
+
+impl<T, E> DLModule<T, E> for Dropout<T, E>
+where
+    T: Tensor<E>,
+    E: RealElement,
+{
+    type DLModuleError = <T as Tensor<E>>::TensorError;
+
+    fn forward(&self, x: &T) -> Result<T, Self::DLModuleError> {
+        if !self.training || self.p == 0. {
+            return Ok(x.clone());
+        }
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        rng.set_stream(self.num_calls.fetch_add(1, Ordering::Relaxed));
+        let scale = 1. / (1. - self.p);
+        let dropped: Vec<E> = x
+            .clone()
+            .into_iter()
+            .map(|x| {
+                if rng.gen::<f64>() < self.p {
+                    E::zero()
+                } else {
+                    x * E::from(scale)
+                }
+            })
+            .collect();
+        T::from_vec(&x.shape(), &dropped)
+    }
+
+    // The dropout layer has no parameters, return an empty vector
+    fn params(&self) -> Vec<E> {
+        Vec::new()
+    }
+
+    fn train(&mut self, training: bool) {
+        self.training = training;
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
+    }
+}
+
+impl<T, E> Dropout<T, E>
+where
+    T: Tensor<E>,
+    E: RealElement,
+{
+    /// Dropout with probability `p`, in `[0, 1)`, starting in training mode.
+    pub fn new(p: f64, seed: u64) -> Self {
+        assert!(
+            (0. ..1.).contains(&p),
+            "The dropout probability must be in [0, 1), got {}.",
+            p
+        );
+        Dropout {
+            p,
+            seed,
+            training: true,
+            num_calls: AtomicU64::new(0),
+            tensor_phantom: PhantomData,
+            tensor_element_phantom: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use autodiff::node::Node;
+    use tensors::TensorImpl;
+
+    use super::*;
+
+    #[test]
+    fn test_train_and_eval() {
+        let mut layer: Dropout<TensorImpl<f64>, f64> = Dropout::new(0.25, 0);
+        assert!(layer.is_training());
+        let x = TensorImpl::from_vec(&vec![10, 10, 10], &vec![3.; 1000]).unwrap();
+        let out = layer.forward(&x).unwrap();
+        assert_eq!(out.shape(), vec![10, 10, 10]);
+        let num_dropped = out.get_data().iter().filter(|y| **y == 0.).count();
+        assert!((200..300).contains(&num_dropped), "{}", num_dropped);
+        assert!(out.get_data().iter().all(|y| *y == 0. || *y == 4.));
+        // A fresh mask for each call.
+        assert_ne!(layer.forward(&x).unwrap(), out);
+
+        layer.train(false);
+        assert!(!layer.is_training());
+        assert_eq!(layer.forward(&x).unwrap(), x);
+    }
+
+    #[test]
+    fn test_seeded() {
+        let x = TensorImpl::from_vec(&vec![4, 8], &vec![1.; 32]).unwrap();
+        let masks = |seed| {
+            let layer: Dropout<TensorImpl<f64>, f64> = Dropout::new(0.5, seed);
+            (0..3)
+                .map(|_| layer.forward(&x).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(masks(7), masks(7));
+        assert_ne!(masks(7), masks(8));
+    }
+
+    #[test]
+    fn test_gradients() {
+        let layer: Dropout<TensorImpl<Node<f64>>, Node<f64>> = Dropout::new(0.5, 1);
+        let data: Vec<Node<f64>> = (0..16).map(|x| Node::new(x as f64, None)).collect();
+        let x = TensorImpl::from_vec(&vec![16], &data).unwrap();
+        let out = layer.forward(&x).unwrap();
+        let mut loss = out
+            .clone()
+            .into_iter()
+            .fold(Node::new(0., None), |acc, y| acc + y);
+        loss.backward(1.);
+        for (x, y) in x.get_data().iter().zip(out.get_data().iter()) {
+            let expected = if y.val() == 0. { 0. } else { 2. };
+            assert_eq!(x.grad().unwrap_or(0.), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The dropout probability must be in [0, 1), got 1.")]
+    fn test_invalid_probability() {
+        let _: Dropout<TensorImpl<f64>, f64> = Dropout::new(1., 0);
+    }
+}
@@ -1,12 +1,12 @@
-use interfaces::deep_learning::{DLModule, EmbeddingLayer};
-use interfaces::tensors::Element;
+use interfaces::deep_learning::{element_paths, DLModule, EmbeddingLayer};
 use interfaces::tensors::Tensor;
-use rand::distributions::Distribution;
+use interfaces::tensors::{AsStdError, Element};
 use rand::SeedableRng;
 use rand_chacha::ChaCha8Rng;
-use statrs::distribution::Normal;
 use std::marker::PhantomData;
 
+use crate::initializer::Initializer;
+
 pub struct EmbeddingTable<T: Tensor<E>, E: Element> {
     table: T,
     vocab_size: usize,
@@ -46,6 +46,18 @@ where
     fn params(&self) -> Vec<E> {
         self.table.clone().into()
     }
+
+    fn param_paths(&self) -> Vec<String> {
+        element_paths("table", &self.table.shape())
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, T)> {
+        vec![("table".to_string(), self.table.shape(), self.table.clone())]
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut T>, AsStdError> {
+        Ok(vec![&mut self.table])
+    }
 }
 
 impl<T, E> EmbeddingLayer<T, E> for EmbeddingTable<T, E>
@@ -60,18 +72,31 @@ where
     T: Tensor<E>,
     E: Element + From<f64>,
 {
+    /// Embeddings drawn from N(0, 1).
     pub fn new(n_emb: usize, vocab_size: usize, seed: u64) -> Self {
-        // He weight initialisation
-        // https://machinelearningmastery.com/weight-initialization-for-deep-learning-neural-networks/
-        let noise_mean = 0.0;
-        let noise_std = 1.0;
-        let rng = ChaCha8Rng::seed_from_u64(seed);
-        let normal = Normal::new(noise_mean, noise_std).unwrap();
-        let normal_itr = normal.sample_iter(rng);
-
-        let table_data: Vec<E> = normal_itr.take(n_emb * vocab_size).map(E::from).collect();
+        Self::with_initializer(
+            n_emb,
+            vocab_size,
+            Initializer::Normal {
+                mean: 0.0,
+                std: 1.0,
+            },
+            seed,
+        )
+    }
 
-        let table = T::from_vec(&vec![vocab_size, n_emb], &table_data)
+    /// The table of shape (vocab_size, n_emb) drawn from `init`, with fan-in `vocab_size` and
+    /// fan-out `n_emb`.
+    pub fn with_initializer(n_emb: usize, vocab_size: usize, init: Initializer, seed: u64) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let shape = vec![vocab_size, n_emb];
+        let table_data: Vec<E> = init
+            .sample(&shape, vocab_size, n_emb, &mut rng)
+            .into_iter()
+            .map(E::from)
+            .collect();
+
+        let table = T::from_vec(&shape, &table_data)
             .expect("Ensured data can be arranged into a matrix of the given size.");
 
         EmbeddingTable {
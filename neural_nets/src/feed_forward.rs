@@ -0,0 +1,403 @@
+// This module contains the feed-forward networks of a transformer block.
+use interfaces::{
+    deep_learning::{
+        prefix_named_parameters, prefix_paths, ActivationLayer, DLModule, LinearLayer,
+    },
+    tensors::{AsStdError, Piecewise, RealElement, Tensor},
+};
+use std::marker::PhantomData;
+use tensors::TensorImpl;
+
+use crate::act_layer::{ActLayer, Activation};
+use crate::initializer::derive_seed;
+use crate::lin_layer::LinLayer;
+
+/// Gated feed-forward network ([Shazeer](https://arxiv.org/abs/2002.05202)):
+/// `down(act(gate(x)) * up(x))`, with `gate` and `up` projecting to the hidden size and `down`
+/// back. SwiGLU with a SiLU activation, GeGLU with GELU.
+pub struct GatedFeedForward<L, Al, T, E>
+where
+    L: LinearLayer<T, E>,
+    Al: ActivationLayer<T, E>,
+    T: Tensor<E>,
+    E: RealElement,
+{
+    pub gate: L, // i: C, o: hidden
+    pub up: L,   // i: C, o: hidden
+    pub activation_layer: Al,
+    pub down: L, // i: hidden, o: C
+    training: bool,
+    _marker_t: PhantomData<T>,
+    _marker_e: PhantomData<E>,
+}
+
+impl<L, Al, T, E> DLModule<T, E> for GatedFeedForward<L, Al, T, E>
+where
+    L: LinearLayer<T, E, DLModuleError = <T as Tensor<E>>::TensorError>,
+    Al: ActivationLayer<T, E, DLModuleError = <T as Tensor<E>>::TensorError>,
+    T: Tensor<E>,
+    E: RealElement,
+{
+    type DLModuleError = <T as Tensor<E>>::TensorError;
+
+    fn forward(&self, x: &T) -> Result<T, Self::DLModuleError> {
+        let gate = self.activation_layer.forward(&self.gate.forward(x)?)?;
+        let hidden = gate * self.up.forward(x)?;
+        self.down.forward(&hidden)
+    }
+
+    fn params(&self) -> Vec<E> {
+        let mut params = self.gate.params();
+        params.extend(self.up.params());
+        params.extend(self.activation_layer.params());
+        params.extend(self.down.params());
+        params
+    }
+
+    fn param_paths(&self) -> Vec<String> {
+        let mut paths = prefix_paths("gate", self.gate.param_paths());
+        paths.extend(prefix_paths("up", self.up.param_paths()));
+        paths.extend(prefix_paths("act", self.activation_layer.param_paths()));
+        paths.extend(prefix_paths("down", self.down.param_paths()));
+        paths
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, T)> {
+        let mut params = prefix_named_parameters("gate", self.gate.named_parameters());
+        params.extend(prefix_named_parameters("up", self.up.named_parameters()));
+        params.extend(prefix_named_parameters(
+            "act",
+            self.activation_layer.named_parameters(),
+        ));
+        params.extend(prefix_named_parameters(
+            "down",
+            self.down.named_parameters(),
+        ));
+        params
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut T>, AsStdError> {
+        let mut params = self.gate.parameters_mut()?;
+        params.extend(self.up.parameters_mut()?);
+        params.extend(self.activation_layer.parameters_mut()?);
+        params.extend(self.down.parameters_mut()?);
+        Ok(params)
+    }
+
+    fn train(&mut self, training: bool) {
+        self.training = training;
+        self.gate.train(training);
+        self.up.train(training);
+        self.activation_layer.train(training);
+        self.down.train(training);
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
+    }
+}
+
+impl<E: RealElement + Piecewise>
+    GatedFeedForward<LinLayer<TensorImpl<E>, E>, ActLayer<TensorImpl<E>, E>, TensorImpl<E>, E>
+{
+    /// Project from (and back to) `embed_dim` through `hidden_dim`, gating with `activation`,
+    /// starting in training mode.
+    pub fn new(embed_dim: usize, hidden_dim: usize, activation: Activation, seed: u64) -> Self {
+        GatedFeedForward {
+            gate: LinLayer::new(embed_dim, hidden_dim, derive_seed(seed, "gate")),
+            up: LinLayer::new(embed_dim, hidden_dim, derive_seed(seed, "up")),
+            activation_layer: ActLayer::with_activation(activation),
+            down: LinLayer::new(hidden_dim, embed_dim, derive_seed(seed, "down")),
+            training: true,
+            _marker_t: PhantomData,
+            _marker_e: PhantomData,
+        }
+    }
+}
+
+impl<L, Al, T, E> GatedFeedForward<L, Al, T, E>
+where
+    L: LinearLayer<T, E>,
+    Al: ActivationLayer<T, E>,
+    T: Tensor<E>,
+    E: RealElement,
+{
+    /// Replace each linear layer `l` with `f(l)`, in `params()` order.
+    pub fn try_map_linear_layers<L2, Er>(
+        self,
+        f: &mut impl FnMut(L) -> Result<L2, Er>,
+    ) -> Result<GatedFeedForward<L2, Al, T, E>, Er>
+    where
+        L2: LinearLayer<T, E>,
+    {
+        Ok(GatedFeedForward {
+            gate: f(self.gate)?,
+            up: f(self.up)?,
+            activation_layer: self.activation_layer,
+            down: f(self.down)?,
+            training: self.training,
+            _marker_t: PhantomData,
+            _marker_e: PhantomData,
+        })
+    }
+}
+
+/// The feed-forward half of a transformer block: either the classic `linear1 -> act -> linear2`,
+/// or a `GatedFeedForward`.
+pub enum FeedForward<L, Al, T, E>
+where
+    L: LinearLayer<T, E>,
+    Al: ActivationLayer<T, E>,
+    T: Tensor<E>,
+    E: RealElement,
+{
+    Classic {
+        linear_layer1: L, // i: C, o: hidden
+        activation_layer: Al,
+        linear_layer2: L, // i: hidden, o: C
+        training: bool,
+    },
+    Gated(GatedFeedForward<L, Al, T, E>),
+}
+
+impl<L, Al, T, E> DLModule<T, E> for FeedForward<L, Al, T, E>
+where
+    L: LinearLayer<T, E, DLModuleError = <T as Tensor<E>>::TensorError>,
+    Al: ActivationLayer<T, E, DLModuleError = <T as Tensor<E>>::TensorError>,
+    T: Tensor<E>,
+    E: RealElement,
+{
+    type DLModuleError = <T as Tensor<E>>::TensorError;
+
+    fn forward(&self, x: &T) -> Result<T, Self::DLModuleError> {
+        match self {
+            FeedForward::Classic {
+                linear_layer1,
+                activation_layer,
+                linear_layer2,
+                ..
+            } => linear_layer2.forward(&activation_layer.forward(&linear_layer1.forward(x)?)?),
+            FeedForward::Gated(gated) => gated.forward(x),
+        }
+    }
+
+    fn params(&self) -> Vec<E> {
+        match self {
+            FeedForward::Classic {
+                linear_layer1,
+                activation_layer,
+                linear_layer2,
+                ..
+            } => {
+                let mut params = linear_layer1.params();
+                params.extend(activation_layer.params());
+                params.extend(linear_layer2.params());
+                params
+            }
+            FeedForward::Gated(gated) => gated.params(),
+        }
+    }
+
+    fn param_paths(&self) -> Vec<String> {
+        match self {
+            FeedForward::Classic {
+                linear_layer1,
+                activation_layer,
+                linear_layer2,
+                ..
+            } => {
+                let mut paths = prefix_paths("linear1", linear_layer1.param_paths());
+                paths.extend(prefix_paths("act", activation_layer.param_paths()));
+                paths.extend(prefix_paths("linear2", linear_layer2.param_paths()));
+                paths
+            }
+            FeedForward::Gated(gated) => gated.param_paths(),
+        }
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, T)> {
+        match self {
+            FeedForward::Classic {
+                linear_layer1,
+                activation_layer,
+                linear_layer2,
+                ..
+            } => {
+                let mut params =
+                    prefix_named_parameters("linear1", linear_layer1.named_parameters());
+                params.extend(prefix_named_parameters(
+                    "act",
+                    activation_layer.named_parameters(),
+                ));
+                params.extend(prefix_named_parameters(
+                    "linear2",
+                    linear_layer2.named_parameters(),
+                ));
+                params
+            }
+            FeedForward::Gated(gated) => gated.named_parameters(),
+        }
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut T>, AsStdError> {
+        match self {
+            FeedForward::Classic {
+                linear_layer1,
+                activation_layer,
+                linear_layer2,
+                ..
+            } => {
+                let mut params = linear_layer1.parameters_mut()?;
+                params.extend(activation_layer.parameters_mut()?);
+                params.extend(linear_layer2.parameters_mut()?);
+                Ok(params)
+            }
+            FeedForward::Gated(gated) => gated.parameters_mut(),
+        }
+    }
+
+    fn train(&mut self, training: bool) {
+        match self {
+            FeedForward::Classic {
+                linear_layer1,
+                activation_layer,
+                linear_layer2,
+                training: is_training,
+            } => {
+                *is_training = training;
+                linear_layer1.train(training);
+                activation_layer.train(training);
+                linear_layer2.train(training);
+            }
+            FeedForward::Gated(gated) => gated.train(training),
+        }
+    }
+
+    fn is_training(&self) -> bool {
+        match self {
+            FeedForward::Classic { training, .. } => *training,
+            FeedForward::Gated(gated) => gated.is_training(),
+        }
+    }
+}
+
+impl<E: RealElement + Piecewise>
+    FeedForward<LinLayer<TensorImpl<E>, E>, ActLayer<TensorImpl<E>, E>, TensorImpl<E>, E>
+{
+    /// The classic feed-forward network, from (and back to) `embed_dim` through `hidden_dim`,
+    /// starting in training mode.
+    pub fn classic(embed_dim: usize, hidden_dim: usize, activation: Activation, seed: u64) -> Self {
+        FeedForward::Classic {
+            linear_layer1: LinLayer::new(embed_dim, hidden_dim, derive_seed(seed, "linear1")),
+            activation_layer: ActLayer::with_activation(activation),
+            linear_layer2: LinLayer::new(hidden_dim, embed_dim, derive_seed(seed, "linear2")),
+            training: true,
+        }
+    }
+
+    /// See `GatedFeedForward::new()`.
+    pub fn gated(embed_dim: usize, hidden_dim: usize, activation: Activation, seed: u64) -> Self {
+        FeedForward::Gated(GatedFeedForward::new(
+            embed_dim, hidden_dim, activation, seed,
+        ))
+    }
+}
+
+impl<L, Al, T, E> FeedForward<L, Al, T, E>
+where
+    L: LinearLayer<T, E>,
+    Al: ActivationLayer<T, E>,
+    T: Tensor<E>,
+    E: RealElement,
+{
+    /// Replace each linear layer `l` with `f(l)`, in `params()` order.
+    pub fn try_map_linear_layers<L2, Er>(
+        self,
+        f: &mut impl FnMut(L) -> Result<L2, Er>,
+    ) -> Result<FeedForward<L2, Al, T, E>, Er>
+    where
+        L2: LinearLayer<T, E>,
+    {
+        Ok(match self {
+            FeedForward::Classic {
+                linear_layer1,
+                activation_layer,
+                linear_layer2,
+                training,
+            } => FeedForward::Classic {
+                linear_layer1: f(linear_layer1)?,
+                activation_layer,
+                linear_layer2: f(linear_layer2)?,
+                training,
+            },
+            FeedForward::Gated(gated) => FeedForward::Gated(gated.try_map_linear_layers(f)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use autodiff::node::Node;
+
+    #[test]
+    fn test_gated_forward() {
+        let mut ff: GatedFeedForward<_, _, TensorImpl<f64>, f64> =
+            GatedFeedForward::new(2, 3, Activation::ReLU, 0);
+        ff.gate.w = TensorImpl::from_vec(&vec![2, 3], &vec![1., 0., -1., 0., 1., 1.]).unwrap();
+        ff.gate.b = TensorImpl::from_vec(&vec![1, 3], &vec![0.; 3]).unwrap();
+        ff.up.w = TensorImpl::from_vec(&vec![2, 3], &vec![1., 1., 1., 1., 1., 1.]).unwrap();
+        ff.up.b = TensorImpl::from_vec(&vec![1, 3], &vec![0., 0., 1.]).unwrap();
+        ff.down.w = TensorImpl::from_vec(&vec![3, 2], &vec![1., 0., 0., 1., 1., 1.]).unwrap();
+        ff.down.b = TensorImpl::from_vec(&vec![1, 2], &vec![0.; 2]).unwrap();
+
+        // gate: relu([2, 3, 1]) = [2, 3, 1], up: [5, 5, 6], hidden: [10, 15, 6]
+        let x = TensorImpl::from_vec(&vec![1, 1, 2], &vec![2., 3.]).unwrap();
+        let out = ff.forward(&x).unwrap();
+        assert_eq!(out.shape(), vec![1, 1, 2]);
+        assert_eq!(out.get_data(), &vec![16., 21.]);
+        // A negative gate closes.
+        let x = TensorImpl::from_vec(&vec![1, 1, 2], &vec![-2., -3.]).unwrap();
+        assert_eq!(ff.forward(&x).unwrap().get_data(), &vec![0., 0.]);
+    }
+
+    #[test]
+    fn test_feed_forward_variants() {
+        let classic: FeedForward<_, _, TensorImpl<Node<f64>>, Node<f64>> =
+            FeedForward::classic(4, 16, Activation::ReLU, 0);
+        let gated: FeedForward<_, _, TensorImpl<Node<f64>>, Node<f64>> =
+            FeedForward::gated(4, 6, Activation::SiLU, 0);
+        assert_eq!(classic.params().len(), (4 * 16 + 16) + (16 * 4 + 4));
+        assert_eq!(gated.params().len(), 2 * (4 * 6 + 6) + (6 * 4 + 4));
+        assert_eq!(classic.param_paths()[80], "linear2.w[0, 0]");
+        let paths = gated.param_paths();
+        assert_eq!(paths.len(), gated.params().len());
+        assert_eq!(paths[30], "up.w[0, 0]");
+        assert_eq!(paths.last().unwrap(), "down.b[0, 3]");
+
+        let data: Vec<Node<f64>> = (0..8).map(|x| Node::new(x as f64 / 4., None)).collect();
+        let x = TensorImpl::from_vec(&vec![1, 2, 4], &data).unwrap();
+        for ff in [classic, gated] {
+            let out = ff.forward(&x).unwrap();
+            assert_eq!(out.shape(), vec![1, 2, 4]);
+            let mut loss = out.into_iter().fold(Node::new(0., None), |acc, y| acc + y);
+            loss.backward(1.);
+            assert!(ff.params().iter().any(|p| p.grad().unwrap_or(0.) != 0.));
+        }
+    }
+
+    #[test]
+    fn test_train() {
+        let classic: FeedForward<_, _, TensorImpl<f64>, f64> =
+            FeedForward::classic(4, 16, Activation::ReLU, 0);
+        let gated: FeedForward<_, _, TensorImpl<f64>, f64> =
+            FeedForward::gated(4, 6, Activation::SiLU, 0);
+        for mut ff in [classic, gated] {
+            assert!(ff.is_training());
+            ff.train(false);
+            assert!(!ff.is_training());
+            ff.train(true);
+            assert!(ff.is_training());
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// Gradient clipping, and detection of non-finite gradients, between the backward pass and the
+// optimizer step. A missing gradient counts as 0 throughout.
+use std::fmt::Display;
+
+use crate::optim::{Optimizer, Parameter};
+
+/// The Euclidean norm of the gradients of all the `params` together.
+pub fn grad_norm<P: Parameter>(params: &[P]) -> f64 {
+    params
+        .iter()
+        .map(|p| p.grad_or_zero().powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Scale the gradients of the `params` so that their global norm (see `grad_norm()`) is at most
+/// `max_norm`, keeping their direction. Returns the norm before clipping.
+///
+/// The gradients are left as they are if the norm is not finite: see `check_finite_grads()`.
+pub fn clip_grad_norm<P: Parameter>(params: &mut [P], max_norm: f64) -> f64 {
+    let norm = grad_norm(params);
+    if norm.is_finite() && norm > max_norm {
+        let scale = max_norm / norm;
+        for p in params.iter_mut() {
+            p.set_grad(p.grad_or_zero() * scale);
+        }
+    }
+    norm
+}
+
+/// Clamp each gradient of the `params` to `[-clip_value, clip_value]`.
+pub fn clip_grad_value<P: Parameter>(params: &mut [P], clip_value: f64) {
+    for p in params.iter_mut() {
+        p.set_grad(p.grad_or_zero().clamp(-clip_value, clip_value));
+    }
+}
+
+/// A parameter with a NaN or infinite gradient.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NonFiniteGrad {
+    /// Index of the parameter in the `params()` of the module.
+    pub index: usize,
+    /// Path of the parameter in the module (see `DLModule::param_paths()`).
+    pub path: String,
+    pub grad: f64,
+}
+
+impl Display for NonFiniteGrad {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Non-finite gradient {} for {}.", self.grad, self.path)
+    }
+}
+
+impl std::error::Error for NonFiniteGrad {}
+
+/// Find the first of the `params` with a non-finite gradient, reported with its name in `paths`
+/// (typically `module.params()` and `module.param_paths()`).
+pub fn check_finite_grads<P: Parameter>(
+    params: &[P],
+    paths: &[String],
+) -> Result<(), NonFiniteGrad> {
+    match params
+        .iter()
+        .enumerate()
+        .find(|(_, p)| !p.grad_or_zero().is_finite())
+    {
+        Some((index, p)) => Err(NonFiniteGrad {
+            index,
+            path: paths
+                .get(index)
+                .cloned()
+                .unwrap_or_else(|| format!("[{}]", index)),
+            grad: p.grad_or_zero(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Step the `optimizer` (over the `params`) unless one of the gradients is non-finite, in which
+/// case the step is skipped and the offending parameter returned. Eg. for a model `model`:
+///
+/// ```ignore
+/// let (mut params, paths) = (model.params(), model.param_paths());
+/// // ... zero_grad(), forward and backward passes
+/// clip_grad_norm(&mut params, 1.);
+/// if let Err(bad) = step_if_finite(&mut optim, &params, &paths) {
+///     println!("Skipped step: {}", bad);
+/// }
+/// ```
+pub fn step_if_finite<P: Parameter>(
+    optimizer: &mut dyn Optimizer,
+    params: &[P],
+    paths: &[String],
+) -> Result<(), NonFiniteGrad> {
+    check_finite_grads(params, paths)?;
+    optimizer.step();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use autodiff::node::Node;
+    use interfaces::deep_learning::DLModule;
+    use tensors::TensorImpl;
+
+    use super::*;
+    use crate::{
+        act_layer::ActLayer,
+        lin_layer::LinLayer,
+        optim::{Adam, OptimSGD},
+        serial::Serial,
+    };
+
+    fn params_with_grads(grads: &[Option<f64>]) -> Vec<Node<f64>> {
+        grads.iter().map(|grad| Node::new(1., *grad)).collect()
+    }
+
+    fn grads(params: &[Node<f64>]) -> Vec<Option<f64>> {
+        params.iter().map(|p| p.grad()).collect()
+    }
+
+    #[test]
+    fn test_clip_grad_norm() {
+        let mut params = params_with_grads(&[Some(3.), None, Some(-4.)]);
+        assert_eq!(grad_norm(&params), 5.);
+        assert_eq!(clip_grad_norm(&mut params, 10.), 5.);
+        assert_eq!(grads(&params), vec![Some(3.), None, Some(-4.)]);
+        assert_eq!(clip_grad_norm(&mut params, 1.), 5.);
+        let clipped = grads(&params);
+        assert!((clipped[0].unwrap() - 0.6).abs() < 1e-12);
+        assert_eq!(clipped[1], Some(0.));
+        assert!((clipped[2].unwrap() + 0.8).abs() < 1e-12);
+
+        // Non-finite gradients are left for `check_finite_grads()` to find.
+        let mut params = params_with_grads(&[Some(3.), Some(f64::INFINITY)]);
+        assert_eq!(clip_grad_norm(&mut params, 1.), f64::INFINITY);
+        assert_eq!(grads(&params), vec![Some(3.), Some(f64::INFINITY)]);
+    }
+
+    #[test]
+    fn test_clip_grad_value() {
+        let mut params = params_with_grads(&[Some(3.), None, Some(-0.5), Some(-4.)]);
+        clip_grad_value(&mut params, 1.);
+        assert_eq!(
+            grads(&params),
+            vec![Some(1.), Some(0.), Some(-0.5), Some(-1.)]
+        );
+    }
+
+    #[test]
+    fn test_skip_non_finite_step() {
+        let model: Serial<TensorImpl<Node<f64>>, Node<f64>> = Serial::new(vec![
+            Box::new(LinLayer::new(2, 3, 0)),
+            Box::new(ActLayer::new()),
+            Box::new(LinLayer::new(3, 1, 1)),
+        ]);
+        let (mut params, paths) = (model.params(), model.param_paths());
+        assert_eq!(paths.len(), params.len());
+        assert_eq!(paths[0], "0.w[0, 0]");
+        assert_eq!(paths[8], "0.b[0, 2]");
+        assert_eq!(paths[10], "2.w[1, 0]");
+
+        let mut adam = Adam::new(0.1, model.params());
+        let mut sgd = OptimSGD::new(0.1, 10, model.params());
+        let vals: Vec<f64> = params.iter().map(|p| p.val()).collect();
+        // The grads are missing, ie. 0: the steps go through without changing anything.
+        step_if_finite(&mut adam, &params, &paths).unwrap();
+        step_if_finite(&mut sgd, &params, &paths).unwrap();
+
+        params[10].set_grad(f64::NAN);
+        let vals_before: Vec<f64> = params.iter().map(|p| p.val()).collect();
+        assert_eq!(vals_before, vals);
+        let bad = step_if_finite(&mut adam, &params, &paths).unwrap_err();
+        assert_eq!(bad.index, 10);
+        assert_eq!(bad.path, "2.w[1, 0]");
+        assert!(bad.grad.is_nan());
+        assert_eq!(bad.to_string(), "Non-finite gradient NaN for 2.w[1, 0].");
+        let vals_after: Vec<f64> = params.iter().map(|p| p.val()).collect();
+        assert_eq!(vals_after, vals);
+    }
+}
@@ -0,0 +1,298 @@
+// Weight initialisation schemes, and the derivation of the seeds of the layers of a model.
+use rand::{distributions::Distribution, Rng};
+use rand_chacha::ChaCha8Rng;
+use statrs::distribution::Normal;
+
+/// How to draw the initial values of a parameter tensor, given its fan-in and fan-out (for a
+/// linear layer: its input and output sizes).
+/// See [Glorot & Bengio](https://proceedings.mlr.press/v9/glorot10a.html) for Xavier and
+/// [He et al.](https://arxiv.org/abs/1502.01852) for He initialisation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Initializer {
+    /// `U(-a, a)` with `a = sqrt(6 / (fan_in + fan_out))`.
+    XavierUniform,
+    /// `N(0, 2 / (fan_in + fan_out))`.
+    XavierNormal,
+    /// `U(-a, a)` with `a = sqrt(6 / fan_in)`.
+    HeUniform,
+    /// `N(0, 2 / fan_in)`.
+    HeNormal,
+    Normal {
+        mean: f64,
+        std: f64,
+    },
+    /// `Normal`, redrawing values more than 2 standard deviations from the mean.
+    TruncatedNormal {
+        mean: f64,
+        std: f64,
+    },
+    /// A (semi-)orthogonal matrix times `gain`, viewing the tensor as a matrix of `shape[0]` rows
+    /// ([Saxe et al.](https://arxiv.org/abs/1312.6120)).
+    Orthogonal {
+        gain: f64,
+    },
+    Zeros,
+    Constant(f64),
+}
+
+impl Initializer {
+    /// Draw the values of a tensor of shape `shape` from `rng`, in row major order.
+    ///
+    /// Panics if the distribution is undefined: a normal with a mean that is not finite or a
+    /// standard deviation that is not positive and finite, `fan_in` 0 for He and
+    /// `fan_in + fan_out` 0 for Xavier initialisation.
+    pub fn sample(
+        &self,
+        shape: &[usize],
+        fan_in: usize,
+        fan_out: usize,
+        rng: &mut ChaCha8Rng,
+    ) -> Vec<f64> {
+        self.check(fan_in, fan_out);
+        let len: usize = shape.iter().product();
+        let normal = |mean: f64, std: f64| Normal::new(mean, std).expect("Ensured std > 0.");
+        let (fan_in, fan_out) = (fan_in as f64, fan_out as f64);
+        match *self {
+            Initializer::XavierUniform => uniform((6.0 / (fan_in + fan_out)).sqrt(), len, rng),
+            Initializer::XavierNormal => normal(0.0, (2.0 / (fan_in + fan_out)).sqrt())
+                .sample_iter(rng)
+                .take(len)
+                .collect(),
+            Initializer::HeUniform => uniform((6.0 / fan_in).sqrt(), len, rng),
+            Initializer::HeNormal => normal(0.0, (2.0 / fan_in).sqrt())
+                .sample_iter(rng)
+                .take(len)
+                .collect(),
+            Initializer::Normal { mean, std } => {
+                normal(mean, std).sample_iter(rng).take(len).collect()
+            }
+            Initializer::TruncatedNormal { mean, std } => normal(mean, std)
+                .sample_iter(rng)
+                .filter(|x| (x - mean).abs() <= 2.0 * std)
+                .take(len)
+                .collect(),
+            Initializer::Orthogonal { gain } => orthogonal(shape, gain, rng),
+            Initializer::Zeros => vec![0.0; len],
+            Initializer::Constant(value) => vec![value; len],
+        }
+    }
+
+    fn check(&self, fan_in: usize, fan_out: usize) {
+        match *self {
+            Initializer::XavierUniform | Initializer::XavierNormal => assert!(
+                fan_in + fan_out > 0,
+                "Xavier initialisation needs a positive fan_in + fan_out, got 0."
+            ),
+            Initializer::HeUniform | Initializer::HeNormal => {
+                assert!(
+                    fan_in > 0,
+                    "He initialisation needs a positive fan_in, got 0."
+                )
+            }
+            Initializer::Normal { mean, std } | Initializer::TruncatedNormal { mean, std } => {
+                assert!(
+                    mean.is_finite() && std.is_finite() && std > 0.0,
+                    "A normal initialisation needs a finite mean and a positive, finite std, got \
+                     mean {} and std {}.",
+                    mean,
+                    std
+                )
+            }
+            Initializer::Orthogonal { .. } | Initializer::Zeros | Initializer::Constant(_) => {}
+        }
+    }
+}
+
+fn uniform(limit: f64, len: usize, rng: &mut ChaCha8Rng) -> Vec<f64> {
+    (0..len).map(|_| rng.gen_range(-limit..limit)).collect()
+}
+
+/// Gram-Schmidt orthonormalisation of `min(rows, cols)` Gaussian vectors of size
+/// `max(rows, cols)`, which are the columns of the matrix if it is tall, its rows otherwise.
+fn orthogonal(shape: &[usize], gain: f64, rng: &mut ChaCha8Rng) -> Vec<f64> {
+    let rows = shape.first().copied().unwrap_or(1);
+    let cols: usize = shape.iter().skip(1).product();
+    let (size, num_vectors) = (rows.max(cols), rows.min(cols));
+    let normal = Normal::new(0.0, 1.0).expect("Ensured std > 0.");
+
+    let mut vectors: Vec<Vec<f64>> = Vec::with_capacity(num_vectors);
+    while vectors.len() < num_vectors {
+        let mut v: Vec<f64> = normal.sample_iter(&mut *rng).take(size).collect();
+        for u in vectors.iter() {
+            let dot: f64 = v.iter().zip(u.iter()).map(|(v, u)| v * u).sum();
+            v.iter_mut().zip(u.iter()).for_each(|(v, u)| *v -= dot * u);
+        }
+        let norm = v.iter().map(|v| v * v).sum::<f64>().sqrt();
+        // Redraw the (almost surely not) linearly dependent vectors.
+        if norm > 1e-8 {
+            vectors.push(v.into_iter().map(|v| v / norm).collect());
+        }
+    }
+
+    (0..rows * cols)
+        .map(|idx| {
+            let (row, col) = (idx / cols, idx % cols);
+            if rows >= cols {
+                gain * vectors[col][row]
+            } else {
+                gain * vectors[row][col]
+            }
+        })
+        .collect()
+}
+
+/// The seed of the sub-module `name` (eg. `query.0`) of a module constructed with `seed`, so that
+/// the sub-modules of a model are initialised independently from a single seed.
+pub fn derive_seed(seed: u64, name: &str) -> u64 {
+    // FNV-1a of the name, then the SplitMix64 finaliser to mix in the seed.
+    let hash = name.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    let mut z = seed.wrapping_add(hash).wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn sample(init: Initializer, shape: &[usize], fan_in: usize, fan_out: usize) -> Vec<f64> {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        init.sample(shape, fan_in, fan_out, &mut rng)
+    }
+
+    fn mean_and_std(xs: &[f64]) -> (f64, f64) {
+        let mean = xs.iter().sum::<f64>() / xs.len() as f64;
+        let var = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / xs.len() as f64;
+        (mean, var.sqrt())
+    }
+
+    #[test]
+    fn test_distributions() {
+        let (fan_in, fan_out) = (100, 300);
+        let shape = [fan_in, fan_out];
+        let check = |init, expected_std: f64| {
+            let xs = sample(init, &shape, fan_in, fan_out);
+            assert_eq!(xs.len(), fan_in * fan_out);
+            let (mean, std) = mean_and_std(&xs);
+            assert!(mean.abs() < 0.01 * expected_std.max(1.0), "{:?}", init);
+            assert!((std / expected_std - 1.0).abs() < 0.02, "{:?}", init);
+            xs
+        };
+        // A uniform distribution on (-a, a) has std a / sqrt(3).
+        let xavier = (2.0 / 400.0_f64).sqrt();
+        let limit = (3.0_f64).sqrt() * xavier;
+        let xs = check(Initializer::XavierUniform, xavier);
+        assert!(xs.iter().all(|x| x.abs() < limit));
+        check(Initializer::XavierNormal, xavier);
+        let he = (2.0 / 100.0_f64).sqrt();
+        let xs = check(Initializer::HeUniform, he);
+        assert!(xs.iter().all(|x| x.abs() < (3.0_f64).sqrt() * he));
+        check(Initializer::HeNormal, he);
+        check(
+            Initializer::Normal {
+                mean: 0.0,
+                std: 2.0,
+            },
+            2.0,
+        );
+
+        // A normal truncated at 2 std has std ~0.88 of the original.
+        let xs = sample(
+            Initializer::TruncatedNormal {
+                mean: 1.0,
+                std: 0.5,
+            },
+            &shape,
+            fan_in,
+            fan_out,
+        );
+        assert!(xs.iter().all(|x| (x - 1.0).abs() <= 1.0));
+        let (mean, std) = mean_and_std(&xs);
+        assert!((mean - 1.0).abs() < 0.01 && (std / 0.5 - 0.88).abs() < 0.01);
+
+        assert_eq!(sample(Initializer::Zeros, &[2, 3], 2, 3), vec![0.0; 6]);
+        assert_eq!(
+            sample(Initializer::Constant(0.1), &[1, 3], 1, 3),
+            vec![0.1; 3]
+        );
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        let panics = |init: Initializer, fan_in: usize, fan_out: usize| {
+            std::panic::catch_unwind(|| sample(init, &[2, 2], fan_in, fan_out)).is_err()
+        };
+        for std in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(panics(Initializer::Normal { mean: 0.0, std }, 2, 2));
+            assert!(panics(
+                Initializer::TruncatedNormal { mean: 0.0, std },
+                2,
+                2
+            ));
+        }
+        assert!(panics(
+            Initializer::Normal {
+                mean: f64::NAN,
+                std: 1.0
+            },
+            2,
+            2
+        ));
+        assert!(panics(Initializer::HeUniform, 0, 2));
+        assert!(panics(Initializer::HeNormal, 0, 2));
+        assert!(panics(Initializer::XavierUniform, 0, 0));
+        assert!(panics(Initializer::XavierNormal, 0, 0));
+        // Only the fans the scheme uses must be positive.
+        assert_eq!(sample(Initializer::HeUniform, &[2, 2], 2, 0).len(), 4);
+        assert_eq!(sample(Initializer::XavierNormal, &[2, 2], 0, 2).len(), 4);
+        assert_eq!(sample(Initializer::Zeros, &[2, 2], 0, 0), vec![0.0; 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "He initialisation needs a positive fan_in, got 0.")]
+    fn test_he_without_fan_in() {
+        sample(Initializer::HeNormal, &[0, 3], 0, 3);
+    }
+
+    #[test]
+    fn test_orthogonal() {
+        for (rows, cols) in [(5, 3), (3, 5), (4, 4), (1, 4)] {
+            let gain = 2.0;
+            let w = sample(Initializer::Orthogonal { gain }, &[rows, cols], rows, cols);
+            // W W^T (wide) or W^T W (tall) is gain^2 times the identity.
+            let (n, k) = (rows.min(cols), rows.max(cols));
+            let at = |i: usize, j: usize| {
+                if rows >= cols {
+                    w[j * cols + i]
+                } else {
+                    w[i * cols + j]
+                }
+            };
+            for i in 0..n {
+                for j in 0..n {
+                    let dot: f64 = (0..k).map(|l| at(i, l) * at(j, l)).sum();
+                    let expected = if i == j { gain * gain } else { 0.0 };
+                    assert!((dot - expected).abs() < 1e-9, "{}x{}", rows, cols);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_derive_seed() {
+        let seeds: Vec<u64> = ["query.0", "key.0", "value.0", "query.1"]
+            .iter()
+            .map(|name| derive_seed(0, name))
+            .collect();
+        for (idx, seed) in seeds.iter().enumerate() {
+            assert!(!seeds[idx + 1..].contains(seed));
+        }
+        assert_eq!(derive_seed(0, "query.0"), seeds[0]);
+        assert_ne!(derive_seed(1, "query.0"), seeds[0]);
+    }
+}
@@ -0,0 +1,313 @@
+// Normalisation layers, normalising each vector along the last dimension of the input.
+use interfaces::{
+    deep_learning::{element_paths, DLModule},
+    tensors::{AsStdError, RealElement, Tensor},
+};
+use std::marker::PhantomData;
+
+/// Layer normalisation ([Ba et al.](https://arxiv.org/abs/1607.06450)): each vector `x` along the
+/// last dimension becomes `gamma * (x - mean(x)) / sqrt(var(x) + eps) + beta`, with the learnable
+/// `gamma` and `beta` (of shape `[dim]`) initialised to ones and zeros.
+#[derive(Clone)]
+pub struct LayerNorm<T: Tensor<E>, E: RealElement> {
+    pub gamma: T,
+    pub beta: T,
+    pub eps: f64,
+    tensor_element_phantom: PhantomData<E>,
+}
+
+/// Root mean square normalisation ([Zhang & Sennrich](https://arxiv.org/abs/1910.07467)): the
+/// variant of `LayerNorm` without the centering, ie. `gamma * x / sqrt(mean(x^2) + eps)`, and without
+/// `beta`.
+#[derive(Clone)]
+pub struct RMSNorm<T: Tensor<E>, E: RealElement> {
+    pub gamma: T,
+    pub eps: f64,
+    tensor_element_phantom: PhantomData<E>,
+}
+
+fn ones<T: Tensor<E>, E: RealElement>(dim: usize) -> T {
+    // One element per parameter, rather than clones of the same element.
+    let data: Vec<E> = (0..dim).map(|_| E::from(1.)).collect();
+    T::from_vec(&vec![dim], &data).expect("Ensured data can be arranged into a vector.")
+}
+
+fn mean_of<E: RealElement>(xs: impl Iterator<Item = E>, len: usize) -> E {
+    xs.fold(E::zero(), |acc, x| acc + x) / E::from(len as f64)
+}
+
+/// Apply `normalise` to each vector along the last dimension of `x`, which must have size `dim`,
+/// a positive size.
+fn normalise_last_dim<T, E>(
+    x: &T,
+    dim: usize,
+    normalise: impl Fn(&[E]) -> Vec<E>,
+) -> Result<T, <T as Tensor<E>>::TensorError>
+where
+    T: Tensor<E>,
+    E: RealElement,
+{
+    let shape = x.shape();
+    if dim == 0 {
+        return Err(anyhow::Error::msg("Cannot normalise vectors of size 0.").into());
+    }
+    if shape.last() != Some(&dim) {
+        return Err(anyhow::Error::msg(format!(
+            "The last dimension of the input (shape {:?}) must have size {}.",
+            shape, dim
+        ))
+        .into());
+    }
+    let data: Vec<E> = x.clone().into();
+    let normalised: Vec<E> = data.chunks(dim).flat_map(normalise).collect();
+    T::from_vec(&shape, &normalised)
+}
+
+impl<T, E> DLModule<T, E> for LayerNorm<T, E>
+where
+    T: Tensor<E>,
+    E: RealElement,
+{
+    type DLModuleError = <T as Tensor<E>>::TensorError;
+
+    fn forward(&self, x: &T) -> Result<T, Self::DLModuleError> {
+        let gamma: Vec<E> = self.gamma.clone().into();
+        let beta: Vec<E> = self.beta.clone().into();
+        normalise_last_dim(x, gamma.len(), |xs| {
+            let mean = mean_of(xs.iter().cloned(), xs.len());
+            let centred: Vec<E> = xs.iter().map(|x| x.clone() - mean.clone()).collect();
+            let var = mean_of(centred.iter().map(|x| x.clone() * x.clone()), xs.len());
+            let std = (var + E::from(self.eps)).pow(E::from(0.5));
+            centred
+                .into_iter()
+                .zip(gamma.iter().zip(beta.iter()))
+                .map(|(x, (g, b))| g.clone() * x / std.clone() + b.clone())
+                .collect()
+        })
+    }
+
+    fn params(&self) -> Vec<E> {
+        let mut res: Vec<E> = self.gamma.clone().into();
+        res.extend(self.beta.clone().into());
+        res
+    }
+
+    fn param_paths(&self) -> Vec<String> {
+        let mut paths = element_paths("gamma", &self.gamma.shape());
+        paths.extend(element_paths("beta", &self.beta.shape()));
+        paths
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, T)> {
+        vec![
+            ("gamma".to_string(), self.gamma.shape(), self.gamma.clone()),
+            ("beta".to_string(), self.beta.shape(), self.beta.clone()),
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut T>, AsStdError> {
+        Ok(vec![&mut self.gamma, &mut self.beta])
+    }
+}
+
+impl<T, E> LayerNorm<T, E>
+where
+    T: Tensor<E>,
+    E: RealElement,
+{
+    /// Normalise vectors of size `dim`, positive, adding `eps` to the variance (eg. `1e-5`).
+    pub fn new(dim: usize, eps: f64) -> Self {
+        assert!(dim > 0, "Cannot normalise vectors of size 0.");
+        let beta: Vec<E> = (0..dim).map(|_| E::zero()).collect();
+        LayerNorm {
+            gamma: ones(dim),
+            beta: T::from_vec(&vec![dim], &beta)
+                .expect("Ensured data can be arranged into a vector."),
+            eps,
+            tensor_element_phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, E> DLModule<T, E> for RMSNorm<T, E>
+where
+    T: Tensor<E>,
+    E: RealElement,
+{
+    type DLModuleError = <T as Tensor<E>>::TensorError;
+
+    fn forward(&self, x: &T) -> Result<T, Self::DLModuleError> {
+        let gamma: Vec<E> = self.gamma.clone().into();
+        normalise_last_dim(x, gamma.len(), |xs| {
+            let mean_sq = mean_of(xs.iter().map(|x| x.clone() * x.clone()), xs.len());
+            let rms = (mean_sq + E::from(self.eps)).pow(E::from(0.5));
+            xs.iter()
+                .zip(gamma.iter())
+                .map(|(x, g)| g.clone() * x.clone() / rms.clone())
+                .collect()
+        })
+    }
+
+    fn params(&self) -> Vec<E> {
+        self.gamma.clone().into()
+    }
+
+    fn param_paths(&self) -> Vec<String> {
+        element_paths("gamma", &self.gamma.shape())
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, T)> {
+        vec![("gamma".to_string(), self.gamma.shape(), self.gamma.clone())]
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut T>, AsStdError> {
+        Ok(vec![&mut self.gamma])
+    }
+}
+
+impl<T, E> RMSNorm<T, E>
+where
+    T: Tensor<E>,
+    E: RealElement,
+{
+    /// Normalise vectors of size `dim`, positive, adding `eps` to the mean square (eg. `1e-6`).
+    pub fn new(dim: usize, eps: f64) -> Self {
+        assert!(dim > 0, "Cannot normalise vectors of size 0.");
+        RMSNorm {
+            gamma: ones(dim),
+            eps,
+            tensor_element_phantom: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use autodiff::node::Node;
+    use tensors::TensorImpl;
+
+    use super::*;
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1e-6, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn node_tensor(shape: &[usize], data: &[f64]) -> TensorImpl<Node<f64>> {
+        let data: Vec<Node<f64>> = data.iter().map(|x| Node::new(*x, None)).collect();
+        TensorImpl::from_vec(&shape.to_vec(), &data).unwrap()
+    }
+
+    #[test]
+    fn test_layer_norm_forward() {
+        let mut layer: LayerNorm<TensorImpl<f64>, f64> = LayerNorm::new(4, 0.);
+        let x =
+            TensorImpl::from_vec(&vec![1, 2, 4], &vec![1., 2., 3., 4., -2., -2., 2., 2.]).unwrap();
+        let out = layer.forward(&x).unwrap();
+        assert_eq!(out.shape(), vec![1, 2, 4]);
+        // The first row has mean 2.5 and variance 1.25.
+        let s = 1.25_f64.sqrt();
+        assert_close(
+            out.get_data(),
+            &[-1.5 / s, -0.5 / s, 0.5 / s, 1.5 / s, -1., -1., 1., 1.],
+        );
+
+        layer.gamma = TensorImpl::from_vec(&vec![4], &vec![2.; 4]).unwrap();
+        layer.beta = TensorImpl::from_vec(&vec![4], &vec![0., 1., 0., 1.]).unwrap();
+        let out = layer.forward(&x).unwrap();
+        assert_close(&out.get_data()[4..], &[-2., -1., 2., 3.]);
+
+        // eps keeps a constant vector finite.
+        let layer: LayerNorm<TensorImpl<f64>, f64> = LayerNorm::new(4, 1e-5);
+        let constant = TensorImpl::from_vec(&vec![4], &vec![3.; 4]).unwrap();
+        assert_close(layer.forward(&constant).unwrap().get_data(), &[0.; 4]);
+
+        assert!(layer.forward(&x.transpose()).is_err());
+        assert_eq!(layer.param_paths()[4], "beta[0]");
+    }
+
+    #[test]
+    fn test_rms_norm_forward() {
+        let layer: RMSNorm<TensorImpl<f64>, f64> = RMSNorm::new(2, 0.);
+        let x = TensorImpl::from_vec(&vec![2, 2], &vec![3., 4., -1., 1.]).unwrap();
+        let out = layer.forward(&x).unwrap();
+        let rms = 12.5_f64.sqrt();
+        assert_close(out.get_data(), &[3. / rms, 4. / rms, -1., 1.]);
+        assert_eq!(layer.params().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot normalise vectors of size 0.")]
+    fn test_layer_norm_of_size_0() {
+        let _: LayerNorm<TensorImpl<f64>, f64> = LayerNorm::new(0, 1e-5);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot normalise vectors of size 0.")]
+    fn test_rms_norm_of_size_0() {
+        let _: RMSNorm<TensorImpl<f64>, f64> = RMSNorm::new(0, 1e-6);
+    }
+
+    #[test]
+    fn test_forward_with_empty_gamma() {
+        // The parameters are public: a layer emptied after construction errors rather than panics.
+        let mut layer: RMSNorm<TensorImpl<f64>, f64> = RMSNorm::new(2, 1e-6);
+        layer.gamma = TensorImpl::from_vec(&vec![0], &vec![]).unwrap();
+        let x = TensorImpl::from_vec(&vec![2, 0], &vec![]).unwrap();
+        assert!(layer.forward(&x).is_err());
+    }
+
+    /// The gradients of `sum(w * norm(x))` with respect to `x` and the parameters match central
+    /// finite differences.
+    fn check_grads<L>(make_layer: impl Fn() -> L)
+    where
+        L: DLModule<TensorImpl<Node<f64>>, Node<f64>>,
+    {
+        let x_data = [0.3, -1.2, 2.0, 0.7, 1.1, 1.0, -0.4, 0.2];
+        let w = [0.5, -1., 2., 1.5, -0.3, 0.8, 1., -2.];
+        let loss = |layer: &L, x: &TensorImpl<Node<f64>>| {
+            let out = layer.forward(x).unwrap();
+            out.into_iter()
+                .zip(w.iter())
+                .fold(Node::new(0., None), |acc, (y, w)| acc + y * Node::from(*w))
+        };
+
+        let layer = make_layer();
+        let x = node_tensor(&[2, 4], &x_data);
+        let mut params = layer.params();
+        // Make the parameters differ from their initial values.
+        for (idx, p) in params.iter_mut().enumerate() {
+            p.set_val(p.val() + 0.1 * idx as f64);
+        }
+        loss(&layer, &x).backward(1.);
+
+        let h = 1e-6;
+        for (idx, x_i) in x.get_data().iter().enumerate() {
+            let shifted = |delta: f64| {
+                let mut data = x_data;
+                data[idx] += delta;
+                loss(&layer, &node_tensor(&[2, 4], &data)).val()
+            };
+            let numeric = (shifted(h) - shifted(-h)) / (2. * h);
+            assert!((x_i.grad().unwrap() - numeric).abs() < 1e-5);
+        }
+        for p in params.iter_mut() {
+            let val = p.val();
+            p.set_val(val + h);
+            let plus = loss(&layer, &x).val();
+            p.set_val(val - h);
+            let minus = loss(&layer, &x).val();
+            p.set_val(val);
+            let numeric = (plus - minus) / (2. * h);
+            assert!((p.grad().unwrap() - numeric).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_gradients() {
+        check_grads(|| LayerNorm::new(4, 1e-5));
+        check_grads(|| RMSNorm::new(4, 1e-5));
+    }
+}
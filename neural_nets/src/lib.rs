@@ -1,6 +1,14 @@
 pub mod act_layer;
+pub mod checkpoint;
+pub mod dropout;
 pub mod embedding_table;
+pub mod feed_forward;
+pub mod initializer;
+pub mod grad_clip;
+pub mod layer_norm;
 pub mod lin_layer;
+pub mod lr_scheduler;
 pub mod optim;
+pub mod quantized_lin_layer;
 pub mod serial;
 pub mod xor_generator;
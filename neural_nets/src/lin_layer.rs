@@ -1,11 +1,12 @@
-use interfaces::deep_learning::{DLModule, LinearLayer};
-use interfaces::tensors::{Element, Tensor};
-use rand::distributions::Distribution;
+use interfaces::deep_learning::{element_paths, DLModule, LinearLayer};
+use interfaces::tensors::{AsStdError, Element, Tensor};
 use rand::SeedableRng;
 use rand_chacha::ChaCha8Rng;
-use statrs::distribution::Normal;
 use std::marker::PhantomData;
 
+use crate::initializer::Initializer;
+
+#[derive(Clone)]
 pub struct LinLayer<T: Tensor<E>, E: Element> {
     pub w: T,
     pub b: T,
@@ -41,6 +42,23 @@ where
         res.extend(self.b.clone().into());
         res
     }
+
+    fn param_paths(&self) -> Vec<String> {
+        let mut paths = element_paths("w", &self.w.shape());
+        paths.extend(element_paths("b", &self.b.shape()));
+        paths
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, T)> {
+        vec![
+            ("w".to_string(), self.w.shape(), self.w.clone()),
+            ("b".to_string(), self.b.shape(), self.b.clone()),
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut T>, AsStdError> {
+        Ok(vec![&mut self.w, &mut self.b])
+    }
 }
 
 impl<T, E> LinearLayer<T, E> for LinLayer<T, E>
@@ -55,34 +73,40 @@ where
     T: Tensor<E>,
     E: Element + From<f64>,
 {
+    /// He normal initialisation of both the weights and the bias.
     pub fn new(i_size: usize, o_size: usize, seed: u64) -> Self {
-        // He weight initialisation
         // https://machinelearningmastery.com/weight-initialization-for-deep-learning-neural-networks/
-        let noise_mean = 0.0;
-        let noise_std = f64::sqrt(2.0 / (i_size as f64));
-        let rng = ChaCha8Rng::seed_from_u64(seed);
-        let normal = Normal::new(noise_mean, noise_std).unwrap();
-        let normal_itr = normal.sample_iter(rng);
-
-        let (w_data, b_data): (Vec<E>, Vec<E>) = normal_itr
-            .take(o_size * (i_size + 1))
-            .map(E::from)
-            .enumerate()
-            .fold((Vec::new(), Vec::new()), |mut acc, (idx, el)| {
-                if idx < o_size * i_size {
-                    acc.0.push(el);
-                    acc
-                } else {
-                    acc.1.push(el);
-                    acc
-                }
-            });
-
-        let weights = T::from_vec(&vec![i_size, o_size], &w_data)
-            .expect("Ensured data can be arranged into a matrix of the given size.");
+        Self::with_initializers(
+            i_size,
+            o_size,
+            Initializer::HeNormal,
+            Initializer::HeNormal,
+            seed,
+        )
+    }
+
+    /// The weights drawn from `w_init`, then the bias from `b_init`, with fan-in `i_size` and
+    /// fan-out `o_size`, from a single RNG seeded with `seed`.
+    pub fn with_initializers(
+        i_size: usize,
+        o_size: usize,
+        w_init: Initializer,
+        b_init: Initializer,
+        seed: u64,
+    ) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let sample = |init: Initializer, shape: Vec<usize>, rng: &mut ChaCha8Rng| {
+            let data: Vec<E> = init
+                .sample(&shape, i_size, o_size, rng)
+                .into_iter()
+                .map(E::from)
+                .collect();
+            T::from_vec(&shape, &data)
+                .expect("Ensured data can be arranged into a matrix of the given size.")
+        };
+        let weights = sample(w_init, vec![i_size, o_size], &mut rng);
         // let bias = T::from_vec(&vec![1_usize, 1_usize, o_size], &b_data)
-        let bias = T::from_vec(&vec![1_usize, o_size], &b_data)
-            .expect("Ensured data can be arranged into a matrix of the given size.");
+        let bias = sample(b_init, vec![1_usize, o_size], &mut rng);
 
         LinLayer {
             w: weights,
@@ -95,6 +119,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use elements::symbolic::Expr;
     use tensors::TensorImpl;
 
     #[test]
@@ -102,6 +127,16 @@ mod tests {
         let layer: LinLayer<TensorImpl<f64>, f64> = LinLayer::new(2, 1, 0);
     }
 
+    #[test]
+    fn construct_with_initializers() {
+        let layer: LinLayer<TensorImpl<f64>, f64> =
+            LinLayer::with_initializers(4, 2, Initializer::XavierUniform, Initializer::Zeros, 0);
+        let limit = f64::sqrt(6.0 / 6.0);
+        assert!(layer.w.get_data().iter().all(|w| w.abs() < limit));
+        assert_eq!(layer.b.get_data(), &vec![0.0; 2]);
+        assert_eq!(layer.b.shape(), vec![1, 2]);
+    }
+
     #[test]
     fn three_dim_forward() {
         // Test that the forward method works when the input tensor is 3D
@@ -123,4 +158,23 @@ mod tests {
         println!("{:?}", out);
         assert_eq!(out.shape(), vec![2, 3]);
     }
+
+    #[test]
+    fn symbolic_forward() {
+        // Print the formula of each output
+        let mut layer: LinLayer<TensorImpl<Expr>, Expr> = LinLayer::new(2, 2, 0);
+        layer.w = TensorImpl::from_vec(&vec![2, 2], &Expr::vars("w", &[2, 2])).unwrap();
+        layer.b = TensorImpl::from_vec(&vec![1, 2], &Expr::vars("b", &[1, 2])).unwrap();
+        let x = TensorImpl::from_vec(&vec![1, 1, 2], &Expr::vars("x", &[2])).unwrap();
+        let out = layer.forward(&x).unwrap();
+        assert_eq!(out.shape(), vec![1, 1, 2]);
+        let formulas: Vec<String> = out.get_data().iter().map(|y| y.to_string()).collect();
+        assert_eq!(
+            formulas,
+            [
+                "x_0 * w_0_0 + x_1 * w_1_0 + b_0_0",
+                "x_0 * w_0_1 + x_1 * w_1_1 + b_0_1"
+            ]
+        );
+    }
 }
@@ -0,0 +1,622 @@
+use std::{f64::consts::PI, fmt::Display, str::FromStr};
+
+use anyhow::Error;
+use interfaces::tensors::AsStdError;
+
+use crate::optim::Optimizer;
+
+/// Learning rate schedule, advanced once per optimizer step (or per epoch, as suits the
+/// schedule). A training iteration calls `apply()` before `Optimizer::step()`, then `step()`:
+///
+/// ```ignore
+/// scheduler.apply(&mut optim);
+/// optim.step();
+/// scheduler.step(None);
+/// ```
+///
+/// The hyperparameters of a schedule are set in code; only its progress (`state()`) is saved with a
+/// training checkpoint, like `Optimizer::state()`.
+pub trait LrScheduler {
+    /// The learning rate of the current step.
+    fn lr(&self) -> f64;
+
+    /// Move on to the next step. `metric` (eg. the validation loss) is only used by the schedules
+    /// reacting to it, such as `ReduceOnPlateau`.
+    fn step(&mut self, metric: Option<f64>);
+
+    fn state(&self) -> SchedulerState;
+
+    /// Restore a state returned by `state()`, of a scheduler of the same kind.
+    fn load_state(&mut self, state: SchedulerState) -> Result<(), AsStdError>;
+
+    /// Set the learning rate of `optimizer` to the current one.
+    fn apply(&self, optimizer: &mut dyn Optimizer) {
+        optimizer.set_lr(self.lr());
+    }
+}
+
+/// Progress of an `LrScheduler`, as named values. Written as one `name=value` line per value by
+/// `Display`, and read back by `FromStr`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchedulerState(pub Vec<(String, f64)>);
+
+impl SchedulerState {
+    /// The values, checking that they are those named `names`, in order.
+    fn values(&self, names: &[&str]) -> Result<Vec<f64>, AsStdError> {
+        let found: Vec<&str> = self.0.iter().map(|(name, _)| name.as_str()).collect();
+        if found != names {
+            return Err(Error::msg(format!(
+                "Expected the scheduler state {:?}, got {:?}.",
+                names, found
+            ))
+            .into());
+        }
+        Ok(self.0.iter().map(|(_, value)| *value).collect())
+    }
+
+    fn from_step(step: usize) -> Self {
+        SchedulerState(vec![("step".to_string(), step as f64)])
+    }
+
+    fn to_step(&self) -> Result<usize, AsStdError> {
+        let step = self.values(&["step"])?[0];
+        if step < 0. || step.fract() != 0. {
+            return Err(Error::msg(format!("Invalid step count {}.", step)).into());
+        }
+        Ok(step as usize)
+    }
+}
+
+impl Display for SchedulerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // `f64` is displayed with enough digits to be parsed back exactly.
+        for (name, value) in self.0.iter() {
+            writeln!(f, "{}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SchedulerState {
+    type Err = AsStdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (name, value) = line
+                    .split_once('=')
+                    .ok_or_else(|| Error::msg(format!("Expected name=value, got {}.", line)))?;
+                let value = value.trim().parse::<f64>().map_err(|_| {
+                    Error::msg(format!("Invalid value {} for {}.", value, name.trim()))
+                })?;
+                Ok((name.trim().to_string(), value))
+            })
+            .collect::<Result<_, AsStdError>>()
+            .map(SchedulerState)
+    }
+}
+
+/// From `from` (at `t = 0`) to `to` (at `t = 1`) along half a cosine.
+fn cosine_anneal(from: f64, to: f64, t: f64) -> f64 {
+    to + (from - to) * (1. + (PI * t).cos()) / 2.
+}
+
+/// Linear warmup from `lr / warmup_steps` to `lr` over the first `warmup_steps` steps, then
+/// constant.
+pub struct LinearWarmup {
+    pub lr: f64,
+    pub warmup_steps: usize,
+    step: usize,
+}
+
+impl LinearWarmup {
+    pub fn new(lr: f64, warmup_steps: usize) -> Self {
+        Self {
+            lr,
+            warmup_steps,
+            step: 0,
+        }
+    }
+}
+
+impl LrScheduler for LinearWarmup {
+    fn lr(&self) -> f64 {
+        let warmup_steps = self.warmup_steps.max(1);
+        self.lr * (self.step + 1).min(warmup_steps) as f64 / warmup_steps as f64
+    }
+
+    fn step(&mut self, _metric: Option<f64>) {
+        self.step += 1;
+    }
+
+    fn state(&self) -> SchedulerState {
+        SchedulerState::from_step(self.step)
+    }
+
+    fn load_state(&mut self, state: SchedulerState) -> Result<(), AsStdError> {
+        self.step = state.to_step()?;
+        Ok(())
+    }
+}
+
+/// Cosine decay from `max_lr` to `min_lr` over `decay_steps` steps, then constant.
+pub struct CosineDecay {
+    pub max_lr: f64,
+    pub min_lr: f64,
+    pub decay_steps: usize,
+    step: usize,
+}
+
+impl CosineDecay {
+    pub fn new(max_lr: f64, min_lr: f64, decay_steps: usize) -> Self {
+        Self {
+            max_lr,
+            min_lr,
+            decay_steps,
+            step: 0,
+        }
+    }
+}
+
+impl LrScheduler for CosineDecay {
+    fn lr(&self) -> f64 {
+        let t = self.step.min(self.decay_steps) as f64 / self.decay_steps.max(1) as f64;
+        cosine_anneal(self.max_lr, self.min_lr, t)
+    }
+
+    fn step(&mut self, _metric: Option<f64>) {
+        self.step += 1;
+    }
+
+    fn state(&self) -> SchedulerState {
+        SchedulerState::from_step(self.step)
+    }
+
+    fn load_state(&mut self, state: SchedulerState) -> Result<(), AsStdError> {
+        self.step = state.to_step()?;
+        Ok(())
+    }
+}
+
+/// `lr`, multiplied by `gamma` every `step_size` steps.
+pub struct StepDecay {
+    pub lr: f64,
+    pub step_size: usize,
+    pub gamma: f64,
+    step: usize,
+}
+
+impl StepDecay {
+    pub fn new(lr: f64, step_size: usize, gamma: f64) -> Self {
+        Self {
+            lr,
+            step_size,
+            gamma,
+            step: 0,
+        }
+    }
+}
+
+impl LrScheduler for StepDecay {
+    fn lr(&self) -> f64 {
+        self.lr * self.gamma.powf((self.step / self.step_size.max(1)) as f64)
+    }
+
+    fn step(&mut self, _metric: Option<f64>) {
+        self.step += 1;
+    }
+
+    fn state(&self) -> SchedulerState {
+        SchedulerState::from_step(self.step)
+    }
+
+    fn load_state(&mut self, state: SchedulerState) -> Result<(), AsStdError> {
+        self.step = state.to_step()?;
+        Ok(())
+    }
+}
+
+/// `lr`, multiplied by `gamma` every step.
+pub struct ExponentialDecay {
+    pub lr: f64,
+    pub gamma: f64,
+    step: usize,
+}
+
+impl ExponentialDecay {
+    pub fn new(lr: f64, gamma: f64) -> Self {
+        Self { lr, gamma, step: 0 }
+    }
+}
+
+impl LrScheduler for ExponentialDecay {
+    fn lr(&self) -> f64 {
+        self.lr * self.gamma.powf(self.step as f64)
+    }
+
+    fn step(&mut self, _metric: Option<f64>) {
+        self.step += 1;
+    }
+
+    fn state(&self) -> SchedulerState {
+        SchedulerState::from_step(self.step)
+    }
+
+    fn load_state(&mut self, state: SchedulerState) -> Result<(), AsStdError> {
+        self.step = state.to_step()?;
+        Ok(())
+    }
+}
+
+/// The 1cycle policy ([Smith & Topin](https://arxiv.org/abs/1708.07120)): cosine annealing from
+/// `max_lr / div_factor` up to `max_lr` over the first `pct_start` of the `total_steps`, then down
+/// to `max_lr / (div_factor * final_div_factor)` at the last step.
+pub struct OneCycle {
+    pub max_lr: f64,
+    pub total_steps: usize,
+    pub pct_start: f64,
+    pub div_factor: f64,
+    pub final_div_factor: f64,
+    step: usize,
+}
+
+impl OneCycle {
+    /// With the usual defaults: 30% of the steps going up, from `max_lr / 25` down to
+    /// `max_lr / 25e4`.
+    pub fn new(max_lr: f64, total_steps: usize) -> Self {
+        Self {
+            max_lr,
+            total_steps,
+            pct_start: 0.3,
+            div_factor: 25.,
+            final_div_factor: 1e4,
+            step: 0,
+        }
+    }
+}
+
+impl LrScheduler for OneCycle {
+    fn lr(&self) -> f64 {
+        let initial_lr = self.max_lr / self.div_factor;
+        let min_lr = initial_lr / self.final_div_factor;
+        let up_steps = (self.pct_start * self.total_steps as f64).round().max(1.);
+        let down_steps = (self.total_steps as f64 - 1. - up_steps).max(1.);
+        let step = self.step as f64;
+        if step <= up_steps {
+            cosine_anneal(initial_lr, self.max_lr, step / up_steps)
+        } else {
+            cosine_anneal(
+                self.max_lr,
+                min_lr,
+                ((step - up_steps) / down_steps).min(1.),
+            )
+        }
+    }
+
+    fn step(&mut self, _metric: Option<f64>) {
+        self.step += 1;
+    }
+
+    fn state(&self) -> SchedulerState {
+        SchedulerState::from_step(self.step)
+    }
+
+    fn load_state(&mut self, state: SchedulerState) -> Result<(), AsStdError> {
+        self.step = state.to_step()?;
+        Ok(())
+    }
+}
+
+/// Multiply the learning rate by `factor` (down to `min_lr`) when the metric passed to `step()`
+/// has not decreased by a relative `threshold` for more than `patience` steps. Steps without a
+/// metric are ignored.
+pub struct ReduceOnPlateau {
+    pub factor: f64,
+    pub patience: usize,
+    pub threshold: f64,
+    pub min_lr: f64,
+    lr: f64,
+    best: f64,
+    num_bad_steps: usize,
+}
+
+impl ReduceOnPlateau {
+    /// With a relative `threshold` of 1e-4 and no minimum learning rate.
+    pub fn new(lr: f64, factor: f64, patience: usize) -> Self {
+        Self {
+            factor,
+            patience,
+            threshold: 1e-4,
+            min_lr: 0.,
+            lr,
+            best: f64::INFINITY,
+            num_bad_steps: 0,
+        }
+    }
+}
+
+impl LrScheduler for ReduceOnPlateau {
+    fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    fn step(&mut self, metric: Option<f64>) {
+        let Some(metric) = metric else {
+            return;
+        };
+        if metric < self.best * (1. - self.threshold) {
+            self.best = metric;
+            self.num_bad_steps = 0;
+        } else {
+            self.num_bad_steps += 1;
+        }
+        if self.num_bad_steps > self.patience {
+            self.lr = (self.lr * self.factor).max(self.min_lr);
+            self.num_bad_steps = 0;
+        }
+    }
+
+    fn state(&self) -> SchedulerState {
+        SchedulerState(vec![
+            ("lr".to_string(), self.lr),
+            ("best".to_string(), self.best),
+            ("num_bad_steps".to_string(), self.num_bad_steps as f64),
+        ])
+    }
+
+    fn load_state(&mut self, state: SchedulerState) -> Result<(), AsStdError> {
+        let values = state.values(&["lr", "best", "num_bad_steps"])?;
+        self.lr = values[0];
+        self.best = values[1];
+        self.num_bad_steps = values[2] as usize;
+        Ok(())
+    }
+}
+
+/// Schedules run one after the other: `schedulers[i + 1]` takes over (from its own first step)
+/// at step `milestones[i]`. Eg. warmup then cosine decay:
+///
+/// ```ignore
+/// Sequential::new(
+///     vec![
+///         Box::new(LinearWarmup::new(1e-3, 100)),
+///         Box::new(CosineDecay::new(1e-3, 1e-5, 900)),
+///     ],
+///     vec![100],
+/// )
+/// ```
+pub struct Sequential {
+    schedulers: Vec<Box<dyn LrScheduler>>,
+    milestones: Vec<usize>,
+    step: usize,
+}
+
+impl Sequential {
+    /// Errors unless there is one milestone less than schedulers, in increasing order.
+    pub fn new(
+        schedulers: Vec<Box<dyn LrScheduler>>,
+        milestones: Vec<usize>,
+    ) -> Result<Self, AsStdError> {
+        if schedulers.is_empty() || milestones.len() != schedulers.len() - 1 {
+            return Err(Error::msg(format!(
+                "Expected one milestone less than the {} schedulers, got {}.",
+                schedulers.len(),
+                milestones.len()
+            ))
+            .into());
+        }
+        if milestones.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(Error::msg("The milestones must be increasing.").into());
+        }
+        Ok(Self {
+            schedulers,
+            milestones,
+            step: 0,
+        })
+    }
+
+    fn active(&self) -> usize {
+        self.milestones.iter().filter(|m| self.step >= **m).count()
+    }
+}
+
+impl LrScheduler for Sequential {
+    fn lr(&self) -> f64 {
+        self.schedulers[self.active()].lr()
+    }
+
+    fn step(&mut self, metric: Option<f64>) {
+        let active = self.active();
+        self.step += 1;
+        // The next scheduler starts from its first step.
+        if self.active() == active {
+            self.schedulers[active].step(metric);
+        }
+    }
+
+    /// The step count, then the states of the schedulers with their names prefixed by their
+    /// index (eg. `1.step`).
+    fn state(&self) -> SchedulerState {
+        let mut state = SchedulerState::from_step(self.step);
+        for (idx, scheduler) in self.schedulers.iter().enumerate() {
+            state.0.extend(
+                scheduler
+                    .state()
+                    .0
+                    .into_iter()
+                    .map(|(name, value)| (format!("{}.{}", idx, name), value)),
+            );
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: SchedulerState) -> Result<(), AsStdError> {
+        let (step, rest) = state.0.split_first().ok_or_else(|| {
+            Error::msg("Expected the step count of the Sequential scheduler, got nothing.")
+        })?;
+        let step = SchedulerState(vec![step.clone()]).to_step()?;
+        let mut states = vec![SchedulerState(vec![]); self.schedulers.len()];
+        for (name, value) in rest.iter() {
+            let (idx, name) = name
+                .split_once('.')
+                .and_then(|(idx, name)| Some((idx.parse::<usize>().ok()?, name)))
+                .filter(|(idx, _)| *idx < states.len())
+                .ok_or_else(|| Error::msg(format!("Unexpected scheduler state {}.", name)))?;
+            states[idx].0.push((name.to_string(), *value));
+        }
+        for (scheduler, state) in self.schedulers.iter_mut().zip(states) {
+            scheduler.load_state(state)?;
+        }
+        self.step = step;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use autodiff::node::Node;
+    use interfaces::utils::Pow;
+
+    use super::*;
+    use crate::optim::Adam;
+
+    fn lrs(scheduler: &mut impl LrScheduler, steps: usize) -> Vec<f64> {
+        (0..steps)
+            .map(|_| {
+                let lr = scheduler.lr();
+                scheduler.step(None);
+                lr
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-12, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_closed_form_schedules() {
+        assert_close(
+            &lrs(&mut LinearWarmup::new(1., 4), 6),
+            &[0.25, 0.5, 0.75, 1., 1., 1.],
+        );
+        assert_close(
+            &lrs(&mut CosineDecay::new(1., 0., 2), 4),
+            &[1., 0.5, 0., 0.],
+        );
+        assert_close(
+            &lrs(&mut StepDecay::new(1., 2, 0.1), 5),
+            &[1., 1., 0.1, 0.1, 0.01],
+        );
+        assert_close(
+            &lrs(&mut ExponentialDecay::new(1., 0.5), 3),
+            &[1., 0.5, 0.25],
+        );
+    }
+
+    #[test]
+    fn test_one_cycle() {
+        let lrs = lrs(&mut OneCycle::new(1., 11), 12);
+        // Up over 3 steps, down over the 7 others.
+        assert!((lrs[0] - 1. / 25.).abs() < 1e-12);
+        assert!(lrs[..3].windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(lrs[3], 1.);
+        assert!(lrs[3..].windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!((lrs[10] - 1. / 25e4).abs() < 1e-12);
+        assert_eq!(lrs[11], lrs[10]);
+    }
+
+    #[test]
+    fn test_reduce_on_plateau() {
+        let mut scheduler = ReduceOnPlateau::new(1., 0.5, 1);
+        let mut lrs = vec![];
+        for metric in [3., 2., 2., 2., 1., 1., 1., 1.] {
+            scheduler.step(Some(metric));
+            lrs.push(scheduler.lr());
+        }
+        assert_eq!(lrs, vec![1., 1., 1., 0.5, 0.5, 0.5, 0.25, 0.25]);
+        // Without a metric, nothing changes.
+        scheduler.step(None);
+        scheduler.step(None);
+        assert_eq!(scheduler.lr(), 0.25);
+    }
+
+    fn warmup_cosine() -> Sequential {
+        Sequential::new(
+            vec![
+                Box::new(LinearWarmup::new(1., 2)),
+                Box::new(CosineDecay::new(1., 0., 2)),
+                Box::new(ReduceOnPlateau::new(0.1, 0.5, 0)),
+            ],
+            vec![2, 4],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sequential() {
+        assert_close(&lrs(&mut warmup_cosine(), 6), &[0.5, 1., 1., 0.5, 0.1, 0.1]);
+        assert!(Sequential::new(vec![Box::new(LinearWarmup::new(1., 2))], vec![2]).is_err());
+        assert!(Sequential::new(
+            vec![
+                Box::new(LinearWarmup::new(1., 2)),
+                Box::new(LinearWarmup::new(1., 2)),
+                Box::new(LinearWarmup::new(1., 2)),
+            ],
+            vec![3, 3]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut scheduler = warmup_cosine();
+        lrs(&mut scheduler, 4);
+        for metric in [1., 2., 3.] {
+            scheduler.step(Some(metric));
+        }
+        let saved = scheduler.state().to_string();
+
+        let mut restored = warmup_cosine();
+        restored
+            .load_state(saved.parse::<SchedulerState>().unwrap())
+            .unwrap();
+        assert_eq!(restored.state(), scheduler.state());
+        assert_eq!(lrs(&mut restored, 3), lrs(&mut scheduler, 3));
+
+        assert!(restored
+            .load_state(LinearWarmup::new(1., 2).state())
+            .is_err());
+        assert!("step".parse::<SchedulerState>().is_err());
+        assert!("step=x".parse::<SchedulerState>().is_err());
+    }
+
+    #[test]
+    fn test_schedule_optimizer() {
+        // Minimise (p - 3)^2 with Adam, warming up then decaying the learning rate.
+        let p = Node::new(0., None);
+        let mut adam = Adam::new(0., vec![p.clone()]);
+        let mut scheduler = Sequential::new(
+            vec![
+                Box::new(LinearWarmup::new(0.5, 10)),
+                Box::new(CosineDecay::new(0.5, 1e-3, 289)),
+            ],
+            vec![10],
+        )
+        .unwrap();
+        for _ in 0..300 {
+            adam.zero_grad();
+            let mut loss = (p.clone() - Node::from(3.)).pow(Node::from(2.));
+            loss.backward(1.);
+            scheduler.apply(&mut adam);
+            adam.step();
+            scheduler.step(None);
+        }
+        assert!((adam.lr() - 1e-3).abs() < 1e-6);
+        assert!((p.val() - 3.).abs() < 1e-3);
+    }
+}
@@ -1,10 +1,159 @@
-use autodiff::node::Node;
-use interfaces::tensors::{RealElement, RealTensor};
+use anyhow::Error;
+use autodiff::graph_ptr::GraphPtr;
+use autodiff::node::GenericNode;
+use autodiff::tape::Var;
+use interfaces::tensors::{AsStdError, RealElement, RealTensor};
 
+/// A trainable scalar, as returned by `DLModule::params()`: a value and, after a backward pass, a
+/// gradient.
+pub trait Parameter {
+    fn val(&self) -> f64;
+    fn grad(&self) -> Option<f64>;
+    fn set_val(&mut self, new_val: f64);
+    fn set_grad(&mut self, new_grad: f64);
+
+    /// The gradient, counting a missing one (of a parameter the loss does not depend on) as 0.
+    fn grad_or_zero(&self) -> f64 {
+        self.grad().unwrap_or(0.)
+    }
+}
+
+impl<P: GraphPtr> Parameter for GenericNode<f64, P> {
+    fn val(&self) -> f64 {
+        GenericNode::val(self)
+    }
+    fn grad(&self) -> Option<f64> {
+        GenericNode::grad(self)
+    }
+    fn set_val(&mut self, new_val: f64) {
+        GenericNode::set_val(self, new_val)
+    }
+    fn set_grad(&mut self, new_grad: f64) {
+        GenericNode::set_grad(self, new_grad)
+    }
+}
+
+impl Parameter for Var {
+    fn val(&self) -> f64 {
+        Var::val(self)
+    }
+    fn grad(&self) -> Option<f64> {
+        Var::grad(self)
+    }
+    fn set_val(&mut self, new_val: f64) {
+        Var::set_val(self, new_val)
+    }
+    fn set_grad(&mut self, new_grad: f64) {
+        Var::set_grad(self, new_grad)
+    }
+}
+
+/// Internal state of an optimizer, eg. to save with a training checkpoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizerState {
+    /// The number of steps taken.
+    pub step: usize,
+    /// Named per-parameter buffers (eg. Adam's moment estimates), each aligned with the
+    /// parameters.
+    pub buffers: Vec<(String, Vec<f64>)>,
+}
+
+impl OptimizerState {
+    /// Take the buffers out of `self`, checking that they are those named `names`, in order, and
+    /// that each holds one value per parameter.
+    fn into_buffers(self, names: &[&str], num_params: usize) -> Result<Vec<Vec<f64>>, AsStdError> {
+        let found: Vec<&str> = self.buffers.iter().map(|(name, _)| name.as_str()).collect();
+        if found != names {
+            return Err(Error::msg(format!(
+                "Expected the optimizer buffers {:?}, got {:?}.",
+                names, found
+            ))
+            .into());
+        }
+        if let Some((name, buffer)) = self.buffers.iter().find(|(_, b)| b.len() != num_params) {
+            return Err(Error::msg(format!(
+                "The buffer {} has {} values for {} parameters.",
+                name,
+                buffer.len(),
+                num_params
+            ))
+            .into());
+        }
+        Ok(self.buffers.into_iter().map(|(_, buffer)| buffer).collect())
+    }
+}
+
+/// Gradient based optimizer of the parameters of a model (as returned by `DLModule::params()`).
+///
+/// A training iteration calls `zero_grad()`, runs the forward and backward passes, then calls
+/// `step()`.
+pub trait Optimizer {
+    /// Update the parameters from their gradients.
+    fn step(&mut self);
+
+    /// Reset the gradients of the parameters to 0.
+    fn zero_grad(&mut self);
+
+    /// The learning rate of the next `step()`.
+    fn lr(&self) -> f64;
+
+    /// Change the learning rate, eg. following an `LrScheduler`.
+    fn set_lr(&mut self, lr: f64);
+
+    fn state(&self) -> OptimizerState;
+
+    /// Restore a state returned by `state()`, of an optimizer of the same kind over the same
+    /// number of parameters.
+    fn load_state(&mut self, state: OptimizerState) -> Result<(), AsStdError>;
+}
+
+fn zero_grad<P: Parameter>(params: &mut [P]) {
+    for p in params.iter_mut() {
+        p.set_grad(0.0)
+    }
+}
+
+/// Weight decay applied by an optimizer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WeightDecay {
+    None,
+    /// L2 regularisation: `coefficient * p` is added to the gradient of `p`, so it is scaled by
+    /// the adaptive learning rate of `p` (if any).
+    L2(f64),
+    /// Decoupled weight decay (as in AdamW): `p` is shrunk by `lr * coefficient * p`, independently
+    /// of its gradient.
+    Decoupled(f64),
+}
+
+impl WeightDecay {
+    /// The gradient of `p`, with the L2 term.
+    fn grad<P: Parameter>(&self, p: &P) -> f64 {
+        let grad = p.grad_or_zero();
+        match self {
+            WeightDecay::L2(coefficient) => grad + coefficient * p.val(),
+            _ => grad,
+        }
+    }
+
+    /// The value of `p` after the decoupled decay.
+    fn decay<P: Parameter>(&self, p: &P, lr: f64) -> f64 {
+        match self {
+            WeightDecay::Decoupled(coefficient) => p.val() * (1. - lr * coefficient),
+            _ => p.val(),
+        }
+    }
+}
+
+/// Stochastic gradient descent.
+///
+/// `update(itr)` divides the learning rate by 10 for the last quarter of the `max_itr`
+/// iterations; `Optimizer::step()` uses the learning rate as is, leaving any decay to an
+/// `LrScheduler`.
 pub struct OptimSGD<T> {
     l_rate: f64,
     max_itr: usize,
     params: Vec<T>,
+    itr: usize,
 }
 
 impl<T> OptimSGD<T> {
@@ -13,15 +162,14 @@ impl<T> OptimSGD<T> {
             l_rate,
             max_itr,
             params,
+            itr: 0,
         }
     }
 }
 
-impl OptimSGD<Node<f64>> {
+impl<T: Parameter> OptimSGD<T> {
     pub fn zero_grad(&mut self) {
-        for p in self.params.iter_mut() {
-            p.set_grad(0.0)
-        }
+        zero_grad(&mut self.params)
     }
 
     pub fn update(&mut self, itr: usize) {
@@ -29,11 +177,350 @@ impl OptimSGD<Node<f64>> {
         if itr > self.max_itr.saturating_mul(3).saturating_div(4) {
             l_rate *= 0.1;
         }
+        self.apply(l_rate);
+    }
+
+    fn apply(&mut self, l_rate: f64) {
         for p in self.params.iter_mut() {
             // println!("{:?}", p.grad());
-            p.set_val(p.val() + (-l_rate * p.grad().unwrap()))
+            p.set_val(p.val() + (-l_rate * p.grad_or_zero()))
+        }
+    }
+}
+
+impl<T: Parameter> Optimizer for OptimSGD<T> {
+    fn step(&mut self) {
+        self.apply(self.l_rate);
+        self.itr += 1;
+    }
+
+    fn zero_grad(&mut self) {
+        OptimSGD::zero_grad(self)
+    }
+
+    fn lr(&self) -> f64 {
+        self.l_rate
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.l_rate = lr;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState {
+            step: self.itr,
+            buffers: vec![],
+        }
+    }
+
+    fn load_state(&mut self, state: OptimizerState) -> Result<(), AsStdError> {
+        let step = state.step;
+        state.into_buffers(&[], self.params.len())?;
+        self.itr = step;
+        Ok(())
+    }
+}
+
+/// Adam: gradient descent with per-parameter learning rates, from bias-corrected running
+/// estimates of the first and second moments of the gradients
+/// ([Kingma & Ba](https://arxiv.org/abs/1412.6980)). With `WeightDecay::Decoupled`, this is AdamW
+/// ([Loshchilov & Hutter](https://arxiv.org/abs/1711.05101)).
+pub struct Adam<T> {
+    pub lr: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub eps: f64,
+    pub weight_decay: WeightDecay,
+    params: Vec<T>,
+    /// Running averages of the gradients and squared gradients.
+    exp_avg: Vec<f64>,
+    exp_avg_sq: Vec<f64>,
+    step: usize,
+}
+
+impl<T> Adam<T> {
+    /// Adam with the usual defaults: betas (0.9, 0.999), eps 1e-8 and no weight decay.
+    pub fn new(lr: f64, params: Vec<T>) -> Self {
+        let num_params = params.len();
+        Self {
+            lr,
+            beta1: 0.9,
+            beta2: 0.999,
+            eps: 1e-8,
+            weight_decay: WeightDecay::None,
+            params,
+            exp_avg: vec![0.; num_params],
+            exp_avg_sq: vec![0.; num_params],
+            step: 0,
+        }
+    }
+
+    /// AdamW: Adam with decoupled weight decay.
+    pub fn adamw(lr: f64, weight_decay: f64, params: Vec<T>) -> Self {
+        Self {
+            weight_decay: WeightDecay::Decoupled(weight_decay),
+            ..Self::new(lr, params)
+        }
+    }
+}
+
+impl<T: Parameter> Optimizer for Adam<T> {
+    fn step(&mut self) {
+        self.step += 1;
+        let bias_correction1 = 1. - self.beta1.powi(self.step as i32);
+        let bias_correction2 = 1. - self.beta2.powi(self.step as i32);
+        for (i, p) in self.params.iter_mut().enumerate() {
+            let grad = self.weight_decay.grad(p);
+            self.exp_avg[i] = self.beta1 * self.exp_avg[i] + (1. - self.beta1) * grad;
+            self.exp_avg_sq[i] = self.beta2 * self.exp_avg_sq[i] + (1. - self.beta2) * grad * grad;
+            let m_hat = self.exp_avg[i] / bias_correction1;
+            let v_hat = self.exp_avg_sq[i] / bias_correction2;
+            let val = self.weight_decay.decay(p, self.lr);
+            p.set_val(val - self.lr * m_hat / (v_hat.sqrt() + self.eps));
+        }
+    }
+
+    fn zero_grad(&mut self) {
+        zero_grad(&mut self.params)
+    }
+
+    fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState {
+            step: self.step,
+            buffers: vec![
+                ("exp_avg".to_string(), self.exp_avg.clone()),
+                ("exp_avg_sq".to_string(), self.exp_avg_sq.clone()),
+            ],
+        }
+    }
+
+    fn load_state(&mut self, state: OptimizerState) -> Result<(), AsStdError> {
+        let step = state.step;
+        let mut buffers = state.into_buffers(&["exp_avg", "exp_avg_sq"], self.params.len())?;
+        self.exp_avg_sq = buffers.pop().unwrap();
+        self.exp_avg = buffers.pop().unwrap();
+        self.step = step;
+        Ok(())
+    }
+}
+
+/// Stochastic gradient descent with momentum: the parameters move along a running sum of their
+/// gradients, decayed by `momentum` each step. With `nesterov`, the step looks ahead along the
+/// momentum ([Sutskever et al.](https://proceedings.mlr.press/v28/sutskever13.html)).
+pub struct MomentumSGD<T> {
+    pub lr: f64,
+    pub momentum: f64,
+    pub nesterov: bool,
+    pub weight_decay: WeightDecay,
+    params: Vec<T>,
+    velocity: Vec<f64>,
+    step: usize,
+}
+
+impl<T> MomentumSGD<T> {
+    pub fn new(lr: f64, momentum: f64, params: Vec<T>) -> Self {
+        let num_params = params.len();
+        Self {
+            lr,
+            momentum,
+            nesterov: false,
+            weight_decay: WeightDecay::None,
+            params,
+            velocity: vec![0.; num_params],
+            step: 0,
+        }
+    }
+
+    pub fn nesterov(lr: f64, momentum: f64, params: Vec<T>) -> Self {
+        Self {
+            nesterov: true,
+            ..Self::new(lr, momentum, params)
+        }
+    }
+}
+
+impl<T: Parameter> Optimizer for MomentumSGD<T> {
+    fn step(&mut self) {
+        self.step += 1;
+        for (i, p) in self.params.iter_mut().enumerate() {
+            let grad = self.weight_decay.grad(p);
+            self.velocity[i] = self.momentum * self.velocity[i] + grad;
+            let direction = if self.nesterov {
+                grad + self.momentum * self.velocity[i]
+            } else {
+                self.velocity[i]
+            };
+            let val = self.weight_decay.decay(p, self.lr);
+            p.set_val(val - self.lr * direction);
+        }
+    }
+
+    fn zero_grad(&mut self) {
+        zero_grad(&mut self.params)
+    }
+
+    fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState {
+            step: self.step,
+            buffers: vec![("velocity".to_string(), self.velocity.clone())],
+        }
+    }
+
+    fn load_state(&mut self, state: OptimizerState) -> Result<(), AsStdError> {
+        let step = state.step;
+        let mut buffers = state.into_buffers(&["velocity"], self.params.len())?;
+        self.velocity = buffers.pop().unwrap();
+        self.step = step;
+        Ok(())
+    }
+}
+
+/// RMSProp: gradient descent with the gradients of each parameter divided by the root of a
+/// running average (decayed by `alpha`) of their squares.
+pub struct RMSProp<T> {
+    pub lr: f64,
+    pub alpha: f64,
+    pub eps: f64,
+    pub weight_decay: WeightDecay,
+    params: Vec<T>,
+    square_avg: Vec<f64>,
+    step: usize,
+}
+
+impl<T> RMSProp<T> {
+    /// With the usual defaults: `alpha` 0.99, `eps` 1e-8 and no weight decay.
+    pub fn new(lr: f64, params: Vec<T>) -> Self {
+        let num_params = params.len();
+        Self {
+            lr,
+            alpha: 0.99,
+            eps: 1e-8,
+            weight_decay: WeightDecay::None,
+            params,
+            square_avg: vec![0.; num_params],
+            step: 0,
+        }
+    }
+}
+
+impl<T: Parameter> Optimizer for RMSProp<T> {
+    fn step(&mut self) {
+        self.step += 1;
+        for (i, p) in self.params.iter_mut().enumerate() {
+            let grad = self.weight_decay.grad(p);
+            self.square_avg[i] = self.alpha * self.square_avg[i] + (1. - self.alpha) * grad * grad;
+            let val = self.weight_decay.decay(p, self.lr);
+            p.set_val(val - self.lr * grad / (self.square_avg[i].sqrt() + self.eps));
+        }
+    }
+
+    fn zero_grad(&mut self) {
+        zero_grad(&mut self.params)
+    }
+
+    fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState {
+            step: self.step,
+            buffers: vec![("square_avg".to_string(), self.square_avg.clone())],
+        }
+    }
+
+    fn load_state(&mut self, state: OptimizerState) -> Result<(), AsStdError> {
+        let step = state.step;
+        let mut buffers = state.into_buffers(&["square_avg"], self.params.len())?;
+        self.square_avg = buffers.pop().unwrap();
+        self.step = step;
+        Ok(())
+    }
+}
+
+/// Adagrad: gradient descent with the gradients of each parameter divided by the root of the sum
+/// of all their past squares, so that rarely updated parameters take larger steps.
+pub struct Adagrad<T> {
+    pub lr: f64,
+    pub eps: f64,
+    pub weight_decay: WeightDecay,
+    params: Vec<T>,
+    sum: Vec<f64>,
+    step: usize,
+}
+
+impl<T> Adagrad<T> {
+    /// With `eps` 1e-10 and no weight decay.
+    pub fn new(lr: f64, params: Vec<T>) -> Self {
+        let num_params = params.len();
+        Self {
+            lr,
+            eps: 1e-10,
+            weight_decay: WeightDecay::None,
+            params,
+            sum: vec![0.; num_params],
+            step: 0,
+        }
+    }
+}
+
+impl<T: Parameter> Optimizer for Adagrad<T> {
+    fn step(&mut self) {
+        self.step += 1;
+        for (i, p) in self.params.iter_mut().enumerate() {
+            let grad = self.weight_decay.grad(p);
+            self.sum[i] += grad * grad;
+            let val = self.weight_decay.decay(p, self.lr);
+            p.set_val(val - self.lr * grad / (self.sum[i].sqrt() + self.eps));
+        }
+    }
+
+    fn zero_grad(&mut self) {
+        zero_grad(&mut self.params)
+    }
+
+    fn lr(&self) -> f64 {
+        self.lr
+    }
+
+    fn set_lr(&mut self, lr: f64) {
+        self.lr = lr;
+    }
+
+    fn state(&self) -> OptimizerState {
+        OptimizerState {
+            step: self.step,
+            buffers: vec![("sum".to_string(), self.sum.clone())],
         }
     }
+
+    fn load_state(&mut self, state: OptimizerState) -> Result<(), AsStdError> {
+        let step = state.step;
+        let mut buffers = state.into_buffers(&["sum"], self.params.len())?;
+        self.sum = buffers.pop().unwrap();
+        self.step = step;
+        Ok(())
+    }
 }
 
 // fn bce<E>(y: E, y_pred: E) -> E
@@ -75,11 +562,165 @@ where
 
 #[cfg(test)]
 mod tests {
+    use autodiff::node::Node;
     use interfaces::tensors::Tensor;
+    use interfaces::utils::Pow;
     use tensors::TensorImpl;
 
     use super::*;
 
+    /// Minimise `(p - 3)^2` from `p = 0`, returning `p`.
+    fn minimise_quadratic(optim: &mut impl Optimizer, p: &Node<f64>, itrs: usize) -> f64 {
+        for _ in 0..itrs {
+            optim.zero_grad();
+            let mut loss = (p.clone() - Node::from(3.)).pow(Node::from(2.));
+            loss.backward(1.);
+            optim.step();
+        }
+        p.val()
+    }
+
+    #[test]
+    fn test_adam_first_step() {
+        // With bias correction, the first step has size `lr` whatever the gradient.
+        for grad in [1e-3, 5., -20.] {
+            let mut p = Node::new(1., None);
+            p.set_grad(grad);
+            let mut adam = Adam::new(0.1, vec![p.clone()]);
+            adam.step();
+            assert!((p.val() - (1. - 0.1 * grad.signum())).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_adam_converges() {
+        let p = Node::new(0., None);
+        let mut adam = Adam::new(0.1, vec![p.clone()]);
+        assert!((minimise_quadratic(&mut adam, &p, 500) - 3.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_weight_decay() {
+        // Without gradient, decoupled weight decay shrinks the parameters geometrically.
+        let p = Node::new(2., None);
+        let mut adamw = Adam::adamw(0.1, 0.5, vec![p.clone()]);
+        adamw.zero_grad();
+        adamw.step();
+        adamw.step();
+        assert!((p.val() - 2. * 0.95 * 0.95).abs() < 1e-12);
+
+        // L2 goes through the moment estimates instead: the first step has size `lr`.
+        let p = Node::new(2., None);
+        let mut adam = Adam::new(0.1, vec![p.clone()]);
+        adam.weight_decay = WeightDecay::L2(0.5);
+        adam.zero_grad();
+        adam.step();
+        assert!((p.val() - 1.9).abs() < 1e-6);
+
+        // The minimum is pulled towards 0.
+        let p = Node::new(0., None);
+        let mut adamw = Adam::adamw(0.05, 0.1, vec![p.clone()]);
+        let p = minimise_quadratic(&mut adamw, &p, 2000);
+        assert!(p < 2.99 && p > 2.5);
+    }
+
+    /// The values of a parameter starting at 1 with a constant gradient of 1, after each of 3 steps.
+    fn constant_grad_steps<O: Optimizer>(make: impl FnOnce(Vec<Node<f64>>) -> O) -> Vec<f64> {
+        let p = Node::new(1., None);
+        let mut optim = make(vec![p.clone()]);
+        (0..3)
+            .map(|_| {
+                p.clone().set_grad(1.);
+                optim.step();
+                p.val()
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_momentum() {
+        // Velocities 1, 1.5, 1.75
+        assert_close(
+            &constant_grad_steps(|params| MomentumSGD::new(0.1, 0.5, params)),
+            &[0.9, 0.75, 0.575],
+        );
+        // Nesterov steps 1 + 0.5 . 1, 1 + 0.5 . 1.5, 1 + 0.5 . 1.75
+        assert_close(
+            &constant_grad_steps(|params| MomentumSGD::nesterov(0.1, 0.5, params)),
+            &[0.85, 0.675, 0.4875],
+        );
+        // Without momentum, L2 adds `coefficient * p` to the gradient.
+        assert_close(
+            &constant_grad_steps(|params| {
+                let mut sgd = MomentumSGD::new(0.1, 0., params);
+                sgd.weight_decay = WeightDecay::L2(1.);
+                sgd
+            }),
+            &[0.8, 0.62, 0.458],
+        );
+    }
+
+    #[test]
+    fn test_rmsprop() {
+        // Square averages 0.5, 0.75, 0.875
+        let steps = constant_grad_steps(|params| {
+            let mut rmsprop = RMSProp::new(0.1, params);
+            rmsprop.alpha = 0.5;
+            rmsprop
+        });
+        let expected = [
+            1. - 0.1 / 0.5_f64.sqrt(),
+            1. - 0.1 / 0.5_f64.sqrt() - 0.1 / 0.75_f64.sqrt(),
+            1. - 0.1 / 0.5_f64.sqrt() - 0.1 / 0.75_f64.sqrt() - 0.1 / 0.875_f64.sqrt(),
+        ];
+        assert_close(&steps, &expected);
+    }
+
+    #[test]
+    fn test_adagrad() {
+        // Sums 1, 2, 3
+        let expected = [
+            0.9,
+            0.9 - 0.1 / 2_f64.sqrt(),
+            0.9 - 0.1 / 2_f64.sqrt() - 0.1 / 3_f64.sqrt(),
+        ];
+        assert_close(
+            &constant_grad_steps(|params| Adagrad::new(0.1, params)),
+            &expected,
+        );
+        let p = Node::new(0., None);
+        let mut adagrad = Adagrad::new(1., vec![p.clone()]);
+        assert!((minimise_quadratic(&mut adagrad, &p, 500) - 3.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_load_state() {
+        let (p1, p2) = (Node::new(0., None), Node::new(0., None));
+        let mut adam1 = Adam::new(0.1, vec![p1.clone()]);
+        minimise_quadratic(&mut adam1, &p1, 10);
+
+        // Resume from a checkpoint of `adam1` and continue both.
+        let mut adam2 = Adam::new(0.1, vec![p2.clone()]);
+        p2.clone().set_val(p1.val());
+        adam2.load_state(adam1.state()).unwrap();
+        assert_eq!(
+            minimise_quadratic(&mut adam1, &p1, 10),
+            minimise_quadratic(&mut adam2, &p2, 10)
+        );
+
+        let mut sgd = OptimSGD::new(0.1, 10, vec![p1.clone()]);
+        assert!(sgd.load_state(adam1.state()).is_err());
+        let mut state = adam1.state();
+        state.buffers[1].1.push(0.);
+        assert!(adam2.load_state(state).is_err());
+    }
+
     #[test]
     fn test_cce() {
         let y_pred = (0..(2 * 2 * 2))
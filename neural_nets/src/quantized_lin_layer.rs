@@ -0,0 +1,309 @@
+use std::sync::Mutex;
+
+use anyhow::Error;
+use elements::qint8::{QInt8, QParams};
+use interfaces::deep_learning::{element_paths, DLModule, LinearLayer};
+use interfaces::tensors::{AsStdError, Tensor};
+use tensors::TensorImpl;
+
+use crate::lin_layer::LinLayer;
+
+/// Linear layer with 8-bit weights and inputs, for inference only. Built from a trained
+/// `LinLayer` with `calibrate()` (or `from_observed()`), which choose how the inputs are quantized
+/// from the range of the inputs seen on sample batches.
+///
+/// `forward()` takes and returns `f64` tensors: the inputs are quantized (saturating outside the
+/// calibrated range), multiplied with the weights accumulating in `i32`, and the result is
+/// dequantized.
+pub struct QuantizedLinLayer {
+    /// Shape (i_size, o_size), quantized symmetrically per output channel (column).
+    pub w: TensorImpl<QInt8>,
+    /// Quantized with a zero point of 0 and the scale of the products of its channel.
+    pub b: Vec<i32>,
+    pub x_params: QParams,
+}
+
+impl QuantizedLinLayer {
+    /// Quantize `layer` for inputs in `[min, max]`.
+    pub fn from_range(layer: &LinLayer<TensorImpl<f64>, f64>, min: f64, max: f64) -> Self {
+        let x_params = QParams::from_range(min, max);
+        let (i_size, o_size) = (layer.w.shape()[0], layer.w.shape()[1]);
+        let w_data = layer.w.get_data();
+        let w_params: Vec<QParams> = (0..o_size)
+            .map(|j| {
+                let max_abs =
+                    (0..i_size).fold(0_f64, |acc, k| acc.max(w_data[k * o_size + j].abs()));
+                QParams::symmetric(max_abs)
+            })
+            .collect();
+        let w: Vec<QInt8> = w_data
+            .iter()
+            .enumerate()
+            .map(|(idx, w)| w_params[idx % o_size].quantize(*w))
+            .collect();
+        let b = layer
+            .b
+            .get_data()
+            .iter()
+            .zip(w_params.iter())
+            .map(|(b, w_params)| (b / (x_params.scale * w_params.scale)).round() as i32)
+            .collect();
+        Self {
+            w: TensorImpl::from_vec(&vec![i_size, o_size], &w)
+                .expect("Same shape as the weights of `layer`."),
+            b,
+            x_params,
+        }
+    }
+
+    /// Quantize `layer` for the range of its inputs over the calibration `batches`.
+    pub fn calibrate(
+        layer: &LinLayer<TensorImpl<f64>, f64>,
+        batches: &[TensorImpl<f64>],
+    ) -> Result<Self, AsStdError> {
+        let observed = Observed::new(layer.clone());
+        for batch in batches.iter() {
+            observed.forward(batch)?;
+        }
+        Self::from_observed(&observed)
+    }
+
+    /// Quantize the layer of `observed` for the range of the inputs it has seen.
+    pub fn from_observed(
+        observed: &Observed<LinLayer<TensorImpl<f64>, f64>>,
+    ) -> Result<Self, AsStdError> {
+        let (min, max) = observed.range().ok_or(Error::msg(
+            "The layer cannot be calibrated: it has not seen any input.",
+        ))?;
+        Ok(Self::from_range(&observed.layer, min, max))
+    }
+}
+
+impl DLModule<TensorImpl<f64>, f64> for QuantizedLinLayer {
+    type DLModuleError = AsStdError;
+
+    fn forward(&self, x: &TensorImpl<f64>) -> Result<TensorImpl<f64>, Self::DLModuleError> {
+        let (i_size, o_size) = (self.w.shape()[0], self.w.shape()[1]);
+        let mut shape = x.shape();
+        if shape.last() != Some(&i_size) {
+            return Err(Error::msg(format!(
+                "The last dimension of the input {:?} must be the input size {}.",
+                shape, i_size
+            ))
+            .into());
+        }
+        let zero_point = self.x_params.zero_point as i32;
+        let w = self.w.get_data();
+        let mut out = Vec::with_capacity(x.get_data().len() / i_size * o_size);
+        for row in x.get_data().chunks(i_size) {
+            let q_row: Vec<i32> = row
+                .iter()
+                .map(|x| self.x_params.quantize(*x).value as i32 - zero_point)
+                .collect();
+            for j in 0..o_size {
+                let acc = q_row.iter().enumerate().fold(self.b[j], |acc, (k, q_x)| {
+                    acc + q_x * w[k * o_size + j].value as i32
+                });
+                out.push(acc as f64 * self.x_params.scale * w[j].params.scale);
+            }
+        }
+        *shape.last_mut().unwrap() = o_size;
+        TensorImpl::from_vec(&shape, &out)
+    }
+
+    /// The dequantized weights then biases, as for `LinLayer`.
+    fn params(&self) -> Vec<f64> {
+        let w = self.w.get_data();
+        w.iter()
+            .map(QInt8::dequantize)
+            .chain(
+                self.b
+                    .iter()
+                    .enumerate()
+                    .map(|(j, b)| *b as f64 * self.x_params.scale * w[j].params.scale),
+            )
+            .collect()
+    }
+
+    fn param_paths(&self) -> Vec<String> {
+        let mut paths = element_paths("w", &self.w.shape());
+        paths.extend(element_paths("b", &[1, self.b.len()]));
+        paths
+    }
+
+    /// The dequantized weights and biases. They cannot be loaded: quantize a `LinLayer` instead.
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, TensorImpl<f64>)> {
+        let (w_shape, b_shape) = (self.w.shape(), vec![1, self.b.len()]);
+        let mut params = self.params();
+        let b = params.split_off(w_shape.iter().product());
+        vec![
+            (
+                "w".to_string(),
+                w_shape.clone(),
+                TensorImpl::from_vec(&w_shape, &params).expect("Same shape as the weights."),
+            ),
+            (
+                "b".to_string(),
+                b_shape.clone(),
+                TensorImpl::from_vec(&b_shape, &b).expect("Same shape as the biases."),
+            ),
+        ]
+    }
+}
+
+impl LinearLayer<TensorImpl<f64>, f64> for QuantizedLinLayer {}
+
+/// Wraps a linear layer to record the range of its inputs, for calibrating a quantized version
+/// of it (see `QuantizedLinLayer::from_observed()`). Swap it in for the layers of a model, run
+/// calibration batches through the model, then swap in the quantized layers.
+pub struct Observed<L> {
+    pub layer: L,
+    range: Mutex<Option<(f64, f64)>>,
+}
+
+impl<L> Observed<L> {
+    pub fn new(layer: L) -> Self {
+        Self {
+            layer,
+            range: Mutex::new(None),
+        }
+    }
+
+    /// The smallest and largest input seen, if any.
+    pub fn range(&self) -> Option<(f64, f64)> {
+        *self.range.lock().unwrap()
+    }
+}
+
+impl<L> DLModule<TensorImpl<f64>, f64> for Observed<L>
+where
+    L: DLModule<TensorImpl<f64>, f64, DLModuleError = AsStdError>,
+{
+    type DLModuleError = AsStdError;
+
+    fn forward(&self, x: &TensorImpl<f64>) -> Result<TensorImpl<f64>, Self::DLModuleError> {
+        let mut range = self.range.lock().unwrap();
+        for x in x.get_data().iter() {
+            *range = Some(match *range {
+                Some((min, max)) => (min.min(*x), max.max(*x)),
+                None => (*x, *x),
+            });
+        }
+        drop(range);
+        self.layer.forward(x)
+    }
+
+    fn params(&self) -> Vec<f64> {
+        self.layer.params()
+    }
+
+    fn param_paths(&self) -> Vec<String> {
+        self.layer.param_paths()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, TensorImpl<f64>)> {
+        self.layer.named_parameters()
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut TensorImpl<f64>>, AsStdError> {
+        self.layer.parameters_mut()
+    }
+
+    fn train(&mut self, training: bool) {
+        self.layer.train(training);
+    }
+
+    fn is_training(&self) -> bool {
+        self.layer.is_training()
+    }
+}
+
+impl<L> LinearLayer<TensorImpl<f64>, f64> for Observed<L> where
+    L: LinearLayer<TensorImpl<f64>, f64, DLModuleError = AsStdError>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_batches() -> Vec<TensorImpl<f64>> {
+        (0..4)
+            .map(|batch| {
+                let data = (0..12)
+                    .map(|i| ((batch * 12 + i) as f64 * 0.37).sin() * 2.)
+                    .collect();
+                TensorImpl::from_vec(&vec![2, 3, 2], &data).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn forward_close_to_float() {
+        let layer: LinLayer<TensorImpl<f64>, f64> = LinLayer::new(2, 4, 0);
+        let batches = sample_batches();
+        let quantized = QuantizedLinLayer::calibrate(&layer, &batches).unwrap();
+        for batch in batches.iter() {
+            let expected = layer.forward(batch).unwrap();
+            let actual = quantized.forward(batch).unwrap();
+            assert_eq!(actual.shape(), vec![2, 3, 4]);
+            for (a, e) in actual.get_data().iter().zip(expected.get_data()) {
+                assert!((a - e).abs() < 0.05, "{} != {}", a, e);
+            }
+        }
+    }
+
+    #[test]
+    fn inputs_saturate_outside_calibrated_range() {
+        let layer: LinLayer<TensorImpl<f64>, f64> = LinLayer::new(1, 1, 0);
+        let quantized = QuantizedLinLayer::from_range(&layer, -1., 1.);
+        let x = TensorImpl::from_vec(&vec![2, 1], &vec![1., 10.]).unwrap();
+        let out = quantized.forward(&x).unwrap().get_data().clone();
+        assert!((out[0] - out[1]).abs() < 1e-12);
+    }
+
+    #[test]
+    fn params_match_layer() {
+        let layer: LinLayer<TensorImpl<f64>, f64> = LinLayer::new(3, 2, 0);
+        let quantized = QuantizedLinLayer::from_range(&layer, -1., 1.);
+        let params = quantized.params();
+        assert_eq!(params.len(), layer.params().len());
+        for (q, p) in params.iter().zip(layer.params()) {
+            assert!((q - p).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn state_dict_is_read_only() {
+        let layer: LinLayer<TensorImpl<f64>, f64> = LinLayer::new(3, 2, 0);
+        let mut quantized = QuantizedLinLayer::from_range(&layer, -1., 1.);
+        let state = quantized.state_dict();
+        let paths: Vec<(&str, &[usize])> = state
+            .0
+            .iter()
+            .map(|(path, shape, _)| (path.as_str(), shape.as_slice()))
+            .collect();
+        assert_eq!(paths, vec![("w", &[3, 2][..]), ("b", &[1, 2][..])]);
+        let values: Vec<f64> = state
+            .0
+            .iter()
+            .flat_map(|(_, _, values)| values.clone())
+            .collect();
+        assert_eq!(values, quantized.params());
+        assert!(quantized.load_state_dict(&state).is_err());
+    }
+
+    #[test]
+    fn calibrate_without_batches() {
+        let layer: LinLayer<TensorImpl<f64>, f64> = LinLayer::new(2, 2, 0);
+        assert!(QuantizedLinLayer::calibrate(&layer, &[]).is_err());
+    }
+
+    #[test]
+    fn wrong_input_size() {
+        let layer: LinLayer<TensorImpl<f64>, f64> = LinLayer::new(2, 2, 0);
+        let quantized = QuantizedLinLayer::from_range(&layer, -1., 1.);
+        let x = TensorImpl::from_vec(&vec![1, 3], &vec![0.; 3]).unwrap();
+        assert!(quantized.forward(&x).is_err());
+    }
+}
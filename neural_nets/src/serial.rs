@@ -1,6 +1,6 @@
 use interfaces::{
-    deep_learning::DLModule,
-    tensors::{Element, Tensor},
+    deep_learning::{prefix_named_parameters, prefix_paths, DLModule},
+    tensors::{AsStdError, Element, Tensor},
 };
 
 pub struct Serial<T, E>
@@ -9,6 +9,7 @@ where
     E: Element,
 {
     modules: Vec<Box<dyn DLModule<T, E, DLModuleError = <T as Tensor<E>>::TensorError>>>,
+    training: bool,
 }
 
 impl<T, E> DLModule<T, E> for Serial<T, E>
@@ -32,6 +33,43 @@ where
             acc
         })
     }
+
+    fn param_paths(&self) -> Vec<String> {
+        self.modules
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, module)| prefix_paths(&idx.to_string(), module.param_paths()))
+            .collect()
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, T)> {
+        self.modules
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, module)| {
+                prefix_named_parameters(&idx.to_string(), module.named_parameters())
+            })
+            .collect()
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut T>, AsStdError> {
+        let mut params = Vec::new();
+        for module in self.modules.iter_mut() {
+            params.extend(module.parameters_mut()?);
+        }
+        Ok(params)
+    }
+
+    fn train(&mut self, training: bool) {
+        self.training = training;
+        for module in self.modules.iter_mut() {
+            module.train(training);
+        }
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
+    }
 }
 
 impl<T, E> Serial<T, E>
@@ -39,16 +77,21 @@ where
     T: Tensor<E>,
     E: Element,
 {
+    /// Starts in training mode: call `train(false)` to also switch the `modules` to evaluation
+    /// mode.
     pub fn new(
         modules: Vec<Box<dyn DLModule<T, E, DLModuleError = <T as Tensor<E>>::TensorError>>>,
     ) -> Self {
-        Serial { modules }
+        Serial {
+            modules,
+            training: true,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::lin_layer::LinLayer;
+    use crate::{dropout::Dropout, lin_layer::LinLayer};
 
     use super::*;
     use rand::seq;
@@ -85,4 +128,48 @@ mod tests {
         // w1 + b1 + w2 + b2
         assert_eq!(serial.params().len(), 3 + 3 + 3 + 1);
     }
+
+    #[test]
+    fn state_dict_serial() {
+        let mut serial: Serial<TensorImpl<f64>, f64> = Serial::new(vec![
+            Box::new(LinLayer::new(1, 3, 0)),
+            Box::new(Dropout::new(0.5, 0)),
+            Box::new(LinLayer::new(3, 1, 0)),
+        ]);
+        let named = serial.named_parameters();
+        let paths: Vec<&str> = named.iter().map(|(path, _, _)| path.as_str()).collect();
+        assert_eq!(paths, vec!["0.w", "0.b", "2.w", "2.b"]);
+        assert_eq!(named[2].1, vec![3, 1]);
+
+        let other: Serial<TensorImpl<f64>, f64> = Serial::new(vec![
+            Box::new(LinLayer::new(1, 3, 1)),
+            Box::new(LinLayer::new(3, 1, 1)),
+        ]);
+        let mut state = serial.state_dict();
+        // The layers of `other` are not at the same paths.
+        assert!(serial.load_state_dict(&other.state_dict()).is_err());
+        state.0[0].2[1] = 10.;
+        serial.load_state_dict(&state).unwrap();
+        assert_eq!(serial.params()[1], 10.);
+        assert_eq!(serial.state_dict(), state);
+
+        state.0.pop();
+        assert!(serial.load_state_dict(&state).is_err());
+    }
+
+    #[test]
+    fn train_serial() {
+        let mut serial: Serial<TensorImpl<f64>, f64> = Serial::new(vec![
+            Box::new(LinLayer::new(4, 3, 0)),
+            Box::new(Dropout::new(0.5, 0)),
+        ]);
+        assert!(serial.is_training());
+        let x = TensorImpl::from_vec(&vec![3, 2, 4], &vec![4.0; 24]).unwrap();
+        assert_ne!(serial.forward(&x).unwrap(), serial.forward(&x).unwrap());
+
+        // Evaluation mode reaches the dropout.
+        serial.train(false);
+        assert!(!serial.is_training());
+        assert_eq!(serial.forward(&x).unwrap(), serial.forward(&x).unwrap());
+    }
 }
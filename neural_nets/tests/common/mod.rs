@@ -0,0 +1,24 @@
+// Fixtures shared by the integration tests.
+use interfaces::deep_learning::DLModule;
+use interfaces::tensors::{Piecewise, RealElement, Tensor};
+use neural_nets::{act_layer::ActLayer, lin_layer::LinLayer, serial::Serial};
+use tensors::TensorImpl;
+
+/// The input and output sizes of the linear layers of `xor_model()`.
+pub const XOR_SIZES: [(usize, usize); 4] = [(2, 5), (5, 10), (10, 10), (10, 2)];
+
+type Module<E> =
+    Box<dyn DLModule<TensorImpl<E>, E, DLModuleError = <TensorImpl<E> as Tensor<E>>::TensorError>>;
+
+/// The model of the XOR tests: linear layers of `XOR_SIZES` with a ReLU between each, with the
+/// seeded (untrained) initial weights.
+pub fn xor_model<E: RealElement + Piecewise + 'static>(seed: u64) -> Serial<TensorImpl<E>, E> {
+    let mut modules: Vec<Module<E>> = Vec::new();
+    for (idx, (i_size, o_size)) in XOR_SIZES.into_iter().enumerate() {
+        if idx > 0 {
+            modules.push(Box::new(ActLayer::new()));
+        }
+        modules.push(Box::new(LinLayer::new(i_size, o_size, seed)));
+    }
+    Serial::new(modules)
+}
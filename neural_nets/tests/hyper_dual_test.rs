@@ -0,0 +1,118 @@
+use autodiff::node::Node;
+use elements::hyper_dual::HyperDual;
+use interfaces::deep_learning::DLModule;
+use interfaces::tensors::{Piecewise, RealElement, Tensor};
+use neural_nets::{act_layer::ActLayer, lin_layer::LinLayer};
+use tensors::TensorImpl;
+
+/// A two layer regression model, small enough to seed each of its parameters in turn.
+struct Model<E: RealElement + Piecewise> {
+    l1: LinLayer<TensorImpl<E>, E>,
+    act: ActLayer<TensorImpl<E>, E>,
+    l2: LinLayer<TensorImpl<E>, E>,
+}
+
+impl<E: RealElement + Piecewise> Model<E> {
+    fn new(seed: u64) -> Self {
+        Model {
+            l1: LinLayer::new(2, 3, seed),
+            act: ActLayer::new(),
+            l2: LinLayer::new(3, 1, seed + 1),
+        }
+    }
+
+    /// In `DLModule::params()` order.
+    fn params(&self) -> Vec<E> {
+        let mut params = self.l1.params();
+        params.extend(self.l2.params());
+        params
+    }
+
+    /// Squared error summed over a fixed batch of (1, 2, 2) inputs.
+    fn loss(&self) -> E {
+        let x = TensorImpl::from_vec(
+            &vec![1, 2, 2],
+            &[0.5, -1.0, 1.5, 0.25].map(E::from).to_vec(),
+        )
+        .unwrap();
+        let target = [1.0, -0.5].map(E::from);
+        let h = self.act.forward(&self.l1.forward(&x).unwrap()).unwrap();
+        let y = self.l2.forward(&h).unwrap();
+        y.get_data()
+            .iter()
+            .zip(target)
+            .fold(E::zero(), |acc, (y_i, t_i)| {
+                acc + (y_i.clone() - t_i).pow(E::from(2.0))
+            })
+    }
+}
+
+/// Replace element `idx` of `t` with `HyperDual::variable()`.
+fn seed(t: &TensorImpl<HyperDual>, idx: usize) -> TensorImpl<HyperDual> {
+    let mut data = t.get_data().clone();
+    data[idx] = HyperDual::variable(data[idx].real);
+    TensorImpl::from_vec(&t.shape(), &data).unwrap()
+}
+
+/// Diagonal of the Hessian of the loss with respect to the parameters, in `params()` order, with
+/// one forward pass per parameter.
+fn hessian_diagonal(seed_value: u64) -> Vec<f64> {
+    let model = Model::<HyperDual>::new(seed_value);
+    let sizes = [
+        model.l1.w.get_data().len(),
+        model.l1.b.get_data().len(),
+        model.l2.w.get_data().len(),
+        model.l2.b.get_data().len(),
+    ];
+    let mut diagonal = Vec::new();
+    for (tensor_idx, size) in sizes.into_iter().enumerate() {
+        for idx in 0..size {
+            let mut model = Model::<HyperDual>::new(seed_value);
+            match tensor_idx {
+                0 => model.l1.w = seed(&model.l1.w, idx),
+                1 => model.l1.b = seed(&model.l1.b, idx),
+                2 => model.l2.w = seed(&model.l2.w, idx),
+                _ => model.l2.b = seed(&model.l2.b, idx),
+            }
+            diagonal.push(model.loss().e1e2);
+        }
+    }
+    diagonal
+}
+
+#[test]
+fn hessian_diagonal_matches_reverse_over_reverse() {
+    let seed_value = 3;
+    let diagonal = hessian_diagonal(seed_value);
+
+    let model = Model::<Node<f64>>::new(seed_value);
+    let params = model.params();
+    let loss = model.loss();
+    let grads = loss.grad_wrt(&params, true);
+    assert_eq!(diagonal.len(), params.len());
+    for ((param, grad), expected) in params.iter().zip(grads.iter()).zip(diagonal.iter()) {
+        let second = grad.grad_wrt(std::slice::from_ref(param), false)[0].val();
+        assert!(
+            f64::abs(second - expected) < 1e-10,
+            "{} != {}",
+            second,
+            expected
+        );
+    }
+    // The output bias enters each squared error linearly: d2/db2 = 2 . batch size.
+    assert!(f64::abs(diagonal[diagonal.len() - 1] - 4.0) < 1e-12);
+}
+
+#[test]
+fn first_derivative_matches_backward() {
+    let seed_value = 3;
+    let mut model = Model::<HyperDual>::new(seed_value);
+    model.l2.w = seed(&model.l2.w, 1);
+    let dual_grad = model.loss().e1;
+
+    let model = Model::<Node<f64>>::new(seed_value);
+    let mut loss = model.loss();
+    loss.backward(1.0);
+    let node_grad = model.l2.w.get_data()[1].grad().unwrap();
+    assert!(f64::abs(dual_grad - node_grad) < 1e-12);
+}
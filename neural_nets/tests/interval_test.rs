@@ -0,0 +1,66 @@
+mod common;
+
+use elements::interval::Interval;
+use interfaces::deep_learning::DLModule;
+use interfaces::tensors::{RealTensor, Tensor};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use tensors::TensorImpl;
+
+use common::xor_model;
+
+/// The boxes around the 4 XOR inputs, as a (1, 4, 2) batch.
+fn input_boxes(radius: f64) -> Vec<Interval> {
+    [0., 0., 0., 1., 1., 0., 1., 1.]
+        .iter()
+        .map(|x| Interval::new(x - radius, x + radius))
+        .collect()
+}
+
+#[test]
+fn output_bounds_contain_sampled_outputs() {
+    let seed = 2;
+    let radius = 0.1;
+    let boxes = input_boxes(radius);
+    let bounds = xor_model::<Interval>(seed)
+        .forward(&TensorImpl::from_vec(&vec![1, 4, 2], &boxes).unwrap())
+        .unwrap()
+        .softmax(2);
+
+    let model = xor_model::<f64>(seed);
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    for _ in 0..200 {
+        // Sample the corners too, where ReLUs switch the most.
+        let x: Vec<f64> = boxes
+            .iter()
+            .map(|b| match rng.gen_range(0..3) {
+                0 => b.lo(),
+                1 => b.hi(),
+                _ => rng.gen_range(b.lo()..b.hi()),
+            })
+            .collect();
+        let y = model
+            .forward(&TensorImpl::from_vec(&vec![1, 4, 2], &x).unwrap())
+            .unwrap()
+            .softmax(2);
+        for (bound, y_i) in bounds.get_data().iter().zip(y.get_data()) {
+            assert!(bound.contains(*y_i), "{} does not contain {}", bound, y_i);
+        }
+    }
+}
+
+#[test]
+fn point_inputs_give_tight_bounds() {
+    let seed = 2;
+    let x: Vec<f64> = [0., 0., 0., 1., 1., 0., 1., 1.].to_vec();
+    let y = xor_model::<f64>(seed)
+        .forward(&TensorImpl::from_vec(&vec![1, 4, 2], &x).unwrap())
+        .unwrap();
+    let bounds = xor_model::<Interval>(seed)
+        .forward(&TensorImpl::from_vec(&vec![1, 4, 2], &input_boxes(0.)).unwrap())
+        .unwrap();
+    for (bound, y_i) in bounds.get_data().iter().zip(y.get_data()) {
+        assert!(bound.contains(*y_i));
+        assert!(bound.width() < 1e-12);
+    }
+}
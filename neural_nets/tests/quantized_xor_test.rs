@@ -0,0 +1,110 @@
+mod common;
+
+use autodiff::node::Node;
+use interfaces::deep_learning::DLModule;
+use interfaces::tensors::{AsStdError, RealTensor, Tensor};
+use neural_nets::optim::{bce, OptimSGD};
+use neural_nets::quantized_lin_layer::{Observed, QuantizedLinLayer};
+use neural_nets::{act_layer::ActLayer, lin_layer::LinLayer, xor_generator::XorGenerator};
+use tensors::TensorImpl;
+
+use common::{xor_model, XOR_SIZES};
+
+/// Train the model of `xor_test` and return its linear layers with `f64` weights.
+fn train_xor(seed: u64) -> Vec<LinLayer<TensorImpl<f64>, f64>> {
+    let max_itr = 300;
+    let batch_size = 5;
+    let model = xor_model::<Node<f64>>(seed);
+    let mut xor_gen = XorGenerator::new(batch_size, seed);
+    let mut optim = OptimSGD::new(0.01, max_itr, model.params());
+    let class_0 =
+        TensorImpl::from_vec(&vec![2, 1], &vec![Node::from(1.0), Node::from(0.0)]).unwrap();
+    for itr in 0..max_itr {
+        let (x, y) = xor_gen.next().unwrap();
+        let y_tensor = TensorImpl::from_vec(&vec![1, batch_size, 1], &y).unwrap();
+        let pred = model.forward(&x).unwrap().softmax(2);
+        let loss = bce(y_tensor, pred.matmul(&class_0).unwrap()).dim_sum(vec![1]);
+        optim.zero_grad();
+        loss.at(vec![0, 0, 0]).unwrap().clone().backward(1.0);
+        optim.update(itr);
+    }
+
+    // `params()` lists the weights then the biases of each layer in turn.
+    let params: Vec<f64> = model.params().iter().map(|p| p.val()).collect();
+    let mut offset = 0;
+    XOR_SIZES
+        .into_iter()
+        .map(|(i_size, o_size)| {
+            let mut layer = LinLayer::new(i_size, o_size, seed);
+            let (w, rest) = params[offset..].split_at(i_size * o_size);
+            layer.w = TensorImpl::from_vec(&vec![i_size, o_size], &w.to_vec()).unwrap();
+            layer.b = TensorImpl::from_vec(&vec![1, o_size], &rest[..o_size].to_vec()).unwrap();
+            offset += (i_size + 1) * o_size;
+            layer
+        })
+        .collect()
+}
+
+/// Forward through linear layers with a ReLU between each.
+fn forward<L>(layers: &[L], x: &TensorImpl<f64>) -> TensorImpl<f64>
+where
+    L: DLModule<TensorImpl<f64>, f64, DLModuleError = AsStdError>,
+{
+    let act: ActLayer<TensorImpl<f64>, f64> = ActLayer::new();
+    let mut tmp = x.clone();
+    for (idx, layer) in layers.iter().enumerate() {
+        if idx > 0 {
+            tmp = act.forward(&tmp).unwrap();
+        }
+        tmp = layer.forward(&tmp).unwrap();
+    }
+    tmp.softmax(2)
+}
+
+/// Fraction of the inputs for which the probability of class 1 is on the side of 0.5 of `y`.
+fn accuracy(pred: &TensorImpl<f64>, y: &[f64]) -> f64 {
+    let correct = y
+        .iter()
+        .enumerate()
+        .filter(|(idx, y)| (pred.get_data()[idx * 2] > 0.5) == (**y == 1.0))
+        .count();
+    correct as f64 / y.len() as f64
+}
+
+#[test]
+fn quantized_xor_accuracy() {
+    let seed = 2;
+    let layers = train_xor(seed);
+
+    // Calibrate on a few batches, then evaluate on unseen ones.
+    let mut xor_gen = XorGenerator::<f64>::new(20, seed + 1);
+    let observed: Vec<Observed<_>> = layers.iter().cloned().map(Observed::new).collect();
+    for _ in 0..4 {
+        forward(&observed, &xor_gen.next().unwrap().0);
+    }
+    let quantized: Vec<QuantizedLinLayer> = observed
+        .iter()
+        .map(|layer| QuantizedLinLayer::from_observed(layer).unwrap())
+        .collect();
+
+    let (x, y) = xor_gen.next().unwrap();
+    let float_pred = forward(&layers, &x);
+    let quantized_pred = forward(&quantized, &x);
+    let float_accuracy = accuracy(&float_pred, &y);
+    let quantized_accuracy = accuracy(&quantized_pred, &y);
+    let max_prob_delta = float_pred
+        .get_data()
+        .iter()
+        .zip(quantized_pred.get_data())
+        .fold(0_f64, |acc, (f, q)| acc.max((f - q).abs()));
+    println!(
+        "XOR accuracy: float {}, quantized {} (delta {}), max probability delta {}",
+        float_accuracy,
+        quantized_accuracy,
+        quantized_accuracy - float_accuracy,
+        max_prob_delta
+    );
+    assert!(float_accuracy > 0.9);
+    assert_eq!(quantized_accuracy, float_accuracy);
+    assert!(max_prob_delta < 0.05);
+}
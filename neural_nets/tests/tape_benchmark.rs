@@ -0,0 +1,122 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use autodiff::node::Node;
+use autodiff::tape::{Tape, Var};
+use interfaces::deep_learning::DLModule;
+use interfaces::tensors::{Piecewise, RealElement, RealTensor, Tensor};
+use neural_nets::optim::bce;
+use neural_nets::xor_generator::XorGenerator;
+use tensors::TensorImpl;
+
+use common::xor_model;
+
+/// The operations of the training loop that differ between the two engines.
+trait Trainable: RealElement + Into<f64> + Piecewise + 'static {
+    fn backward(&mut self);
+
+    fn sgd_step(&mut self, l_rate: f64);
+
+    /// Taken once the model is built, and passed to `end_step()` to free the graph of a step.
+    fn step_mark() -> usize;
+
+    fn end_step(mark: usize);
+}
+
+impl Trainable for Node<f64> {
+    fn backward(&mut self) {
+        Node::backward(self, 1.0);
+    }
+
+    fn sgd_step(&mut self, l_rate: f64) {
+        self.set_val(self.val() - l_rate * self.grad().unwrap_or(0.0));
+        self.set_grad(0.0);
+    }
+
+    fn step_mark() -> usize {
+        0
+    }
+
+    // The graph is freed when the loss is dropped.
+    fn end_step(_mark: usize) {}
+}
+
+impl Trainable for Var {
+    fn backward(&mut self) {
+        Var::backward(self, 1.0);
+    }
+
+    fn sgd_step(&mut self, l_rate: f64) {
+        self.set_val(self.val() - l_rate * self.grad().unwrap_or(0.0));
+        self.set_grad(0.0);
+    }
+
+    fn step_mark() -> usize {
+        Tape::current().len()
+    }
+
+    // Keep the parameters, drop the ops of the step.
+    fn end_step(mark: usize) {
+        Tape::current().truncate(mark);
+    }
+}
+
+/// Train the XOR model of `xor_test` and return the loss of each iteration and the time taken.
+fn train_xor<E: Trainable>(max_itr: usize) -> (Vec<f64>, Duration) {
+    let seed = 2;
+    let batch_size = 5;
+    let model = xor_model::<E>(seed);
+    let mut params = model.params();
+    let mut xor_gen = XorGenerator::new(batch_size, seed);
+    let class_0 = TensorImpl::from_vec(&vec![2, 1], &vec![E::from(1.0), E::from(0.0)]).unwrap();
+    let mark = E::step_mark();
+
+    let start = Instant::now();
+    let mut losses = Vec::new();
+    for _ in 0..max_itr {
+        let (x, y) = xor_gen.next().unwrap();
+        let y_tensor = TensorImpl::from_vec(&vec![1, batch_size, 1], &y).unwrap();
+        let pred = model.forward(&x).unwrap().softmax(2);
+        let loss = bce(y_tensor, pred.matmul(&class_0).unwrap()).dim_sum(vec![1]);
+
+        let mut root = loss.at(vec![0, 0, 0]).unwrap().clone();
+        losses.push(root.clone().into());
+        root.backward();
+        for p in params.iter_mut() {
+            p.sgd_step(0.01);
+        }
+        E::end_step(mark);
+    }
+    (losses, start.elapsed())
+}
+
+#[test]
+fn tape_trains_xor_like_node() {
+    let (node_losses, _) = train_xor::<Node<f64>>(20);
+    let (var_losses, _) = train_xor::<Var>(20);
+    for (node_loss, var_loss) in node_losses.iter().zip(var_losses.iter()) {
+        assert!(f64::abs(node_loss - var_loss) < 1e-9);
+    }
+}
+
+/// Run with `cargo test --release -p neural_nets --test tape_benchmark -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_xor_node_vs_tape() {
+    let max_itr = 300;
+    let (node_losses, node_time) = train_xor::<Node<f64>>(max_itr);
+    let (var_losses, var_time) = train_xor::<Var>(max_itr);
+
+    println!("XOR, {} iterations:", max_itr);
+    println!(
+        "  Node: {:?}, final loss {}",
+        node_time,
+        node_losses[max_itr - 1]
+    );
+    println!(
+        "  Var:  {:?}, final loss {}",
+        var_time,
+        var_losses[max_itr - 1]
+    );
+}
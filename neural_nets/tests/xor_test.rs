@@ -1,30 +1,25 @@
+mod common;
+
 use std::iter::zip;
 
+use autodiff::no_grad::no_grad;
 use autodiff::node::Node;
+use autodiff::profile::{profile_backward, GraphStats};
 use interfaces::deep_learning::DLModule;
 use interfaces::tensors::RealTensor;
 use interfaces::tensors::Tensor;
-use neural_nets::optim::bce;
-use neural_nets::{
-    act_layer::ActLayer, lin_layer::LinLayer, optim::OptimSGD, serial::Serial,
-    xor_generator::XorGenerator,
-};
+use neural_nets::optim::{bce, Adagrad, Adam, MomentumSGD, Optimizer, RMSProp, WeightDecay};
+use neural_nets::{optim::OptimSGD, serial::Serial, xor_generator::XorGenerator};
 use tensors::TensorImpl;
 
+use common::xor_model;
+
 #[test]
 fn xor_test() {
     let seed = 2;
     let max_itr = 300;
     let batch_size = 5;
-    let model: Serial<TensorImpl<Node<f64>>, Node<f64>> = Serial::new(vec![
-        Box::new(LinLayer::new(2, 5, seed)),
-        Box::new(ActLayer::new()),
-        Box::new(LinLayer::new(5, 10, seed)),
-        Box::new(ActLayer::new()),
-        Box::new(LinLayer::new(10, 10, seed)),
-        Box::new(ActLayer::new()),
-        Box::new(LinLayer::new(10, 2, seed)),
-    ]);
+    let model: XorModel = xor_model(seed);
 
     let mut xor_gen = XorGenerator::new(batch_size, seed);
 
@@ -54,9 +49,14 @@ fn xor_test() {
         );
 
         optim.zero_grad();
-        loss.at(vec![0, 0, 0]).unwrap().clone().backward(1.0);
+        let mut root = loss.at(vec![0, 0, 0]).unwrap().clone();
+        root.backward(1.0);
+        // Free this iteration's graph now rather than when `loss` goes out of scope.
+        root.release_graph();
         optim.update(itr);
     }
+    // Evaluation only, no need to build a graph.
+    let _guard = no_grad();
     let (x, y) = xor_gen.next().unwrap();
     let y_tensor = TensorImpl::from_vec(&vec![1, batch_size, 1], &y).unwrap();
     // shape (1,B,1)
@@ -88,3 +88,129 @@ fn xor_test() {
     let loss = loss_tensor.dim_sum(vec![1]);
     assert!(loss.at(vec![0, 0, 0]).unwrap().clone().val() < 0.1_f64)
 }
+
+type XorModel = Serial<TensorImpl<Node<f64>>, Node<f64>>;
+
+/// The BCE loss of the predicted probability of class 0, summed over the batch.
+fn xor_loss(
+    model: &XorModel,
+    xor_gen: &mut XorGenerator<Node<f64>>,
+    batch_size: usize,
+) -> Node<f64> {
+    let (x, y) = xor_gen.next().unwrap();
+    let y_tensor = TensorImpl::from_vec(&vec![1, batch_size, 1], &y).unwrap();
+    let class_0 = model
+        .forward(&x)
+        .unwrap()
+        .softmax(2)
+        .matmul(
+            &TensorImpl::from_vec(&vec![2, 1], &vec![Node::from(1.0), Node::from(0.0)]).unwrap(),
+        )
+        .unwrap();
+    let loss = bce(y_tensor, class_0).dim_sum(vec![1]);
+    loss.at(vec![0, 0, 0]).unwrap().clone()
+}
+
+#[test]
+fn xor_profile_test() {
+    let seed = 2;
+    let batch_size = 5;
+    let model: XorModel = xor_model(seed);
+    let num_params = model.params().len();
+    let mut xor_gen = XorGenerator::new(batch_size, seed);
+    let mut optim = OptimSGD::new(0.01, 3, model.params());
+
+    for itr in 0..3 {
+        optim.zero_grad();
+        let mut loss = xor_loss(&model, &mut xor_gen, batch_size);
+        let stats = profile_backward(&mut loss, 1.0);
+        println!("{:#}", stats);
+        assert!(stats.backward_time.is_some());
+        let op_count: usize = stats.per_op.values().map(|op| op.count).sum();
+        assert_eq!(op_count, stats.total.count);
+        assert!(stats.total.count > num_params);
+        // The softmax and the BCE loss, one per element of the batch.
+        assert_eq!(stats.per_op["Exp"].count, 2 * batch_size);
+        assert_eq!(stats.per_op["Ln"].count, 2 * batch_size);
+        // The deepest nodes are leaves, below at least one node per layer.
+        assert_eq!(stats.per_op["Leaf"].max_depth, stats.total.max_depth);
+        assert!(stats.total.max_depth > 7);
+
+        loss.release_graph();
+        assert_eq!(GraphStats::of(&loss).total.count, 1);
+        optim.update(itr);
+    }
+}
+
+/// Train the XOR model with the optimizer built by `make_optim`, returning the losses on a
+/// training batch every 50 iterations, and finally on an evaluation batch.
+fn train_xor<O: Optimizer>(make_optim: impl FnOnce(Vec<Node<f64>>) -> O) -> Vec<f64> {
+    let seed = 2;
+    let batch_size = 5;
+    let model: XorModel = xor_model(seed);
+    let mut xor_gen = XorGenerator::new(batch_size, seed);
+    let mut optim = make_optim(model.params());
+    let mut losses = vec![];
+    for itr in 0..300 {
+        optim.zero_grad();
+        let mut loss = xor_loss(&model, &mut xor_gen, batch_size);
+        if itr % 50 == 0 {
+            losses.push(loss.val());
+        }
+        loss.backward(1.0);
+        loss.release_graph();
+        optim.step();
+    }
+    let _guard = no_grad();
+    losses.push(xor_loss(&model, &mut xor_gen, batch_size).val());
+    losses
+}
+
+/// The evaluation loss must be small, and well below the loss of the untrained model: a model
+/// that starts out near the optimum would pass the first check without learning anything.
+fn assert_converges(losses: &[f64]) {
+    let last = *losses.last().unwrap();
+    assert!(last < 0.1_f64);
+    assert!(last < 0.1 * losses[0]);
+}
+
+#[test]
+fn momentum_xor_test() {
+    assert_converges(&train_xor(|params| MomentumSGD::new(0.01, 0.9, params)));
+}
+
+#[test]
+fn nesterov_xor_test() {
+    assert_converges(&train_xor(|params| {
+        MomentumSGD::nesterov(0.01, 0.9, params)
+    }));
+}
+
+#[test]
+fn momentum_l2_xor_test() {
+    assert_converges(&train_xor(|params| {
+        let mut sgd = MomentumSGD::new(0.01, 0.9, params);
+        sgd.weight_decay = WeightDecay::L2(1e-4);
+        sgd
+    }));
+}
+
+#[test]
+fn rmsprop_xor_test() {
+    assert_converges(&train_xor(|params| RMSProp::new(0.005, params)));
+}
+
+#[test]
+fn adagrad_xor_test() {
+    assert_converges(&train_xor(|params| Adagrad::new(0.05, params)));
+}
+
+#[test]
+fn adam_xor_test() {
+    assert_converges(&train_xor(|params| Adam::new(0.005, params)));
+}
+
+#[test]
+fn adamw_xor_test() {
+    assert_converges(&train_xor(|params| Adam::adamw(0.005, 1e-2, params)));
+}
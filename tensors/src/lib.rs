@@ -1,6 +1,6 @@
 use anyhow::Error;
 use interfaces::tensors::{AsStdError, Element, RealElement, RealTensor, Tensor};
-use interfaces::utils::{Exp, Ln, Pow};
+use interfaces::utils::{Conj, Exp, Ln, Pow};
 use std::{
     fmt::Debug,
     ops::{Add, Div, Mul, Sub},
@@ -571,6 +571,16 @@ where
     }
 }
 
+impl<E: Element + Conj> TensorImpl<E> {
+    /// The conjugate transpose (Hermitian adjoint): `transpose()` of the last two dimensions,
+    /// conjugating each element.
+    pub fn conj_transpose(&self) -> Self {
+        let mut transposed = self.transpose();
+        transposed.data = transposed.data.into_iter().map(Conj::conj).collect();
+        transposed
+    }
+}
+
 impl<E: RealElement> Exp for TensorImpl<E> {
     fn exp(self) -> Self {
         let new_data = self.data.iter().map(|x| x.clone().exp()).collect();
@@ -625,6 +635,7 @@ impl<E: RealElement> RealTensor<E> for TensorImpl<E> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use elements::complex64::Complex64;
     use rand::Rng;
 
     fn make_random_f64_tensor(
@@ -1233,4 +1244,92 @@ mod tests {
             assert_eq!(result2, expected_result);
         }
     }
+
+    fn complex_tensor(shape: Vec<usize>, data: &[(f64, f64)]) -> TensorImpl<Complex64> {
+        let data = data
+            .iter()
+            .map(|(re, im)| Complex64::new(*re, *im))
+            .collect();
+        TensorImpl::from_vec(&shape, &data).unwrap()
+    }
+
+    #[test]
+    fn test_complex_matmul() {
+        let a = complex_tensor(vec![2, 2], &[(1., 1.), (0., 2.), (3., 0.), (1., -1.)]);
+        let b = complex_tensor(vec![2, 1], &[(2., 0.), (0., 1.)]);
+        // [(1+i)2 + (2i)(i), 3 . 2 + (1-i)i]
+        let expected = complex_tensor(vec![2, 1], &[(0., 2.), (7., 1.)]);
+        assert_eq!(a.matmul(&b).unwrap(), expected);
+
+        let batched = complex_tensor(
+            vec![2, 2, 2],
+            &[
+                (1., 1.),
+                (0., 2.),
+                (3., 0.),
+                (1., -1.),
+                (0., 0.),
+                (1., 0.),
+                (0., 1.),
+                (0., 0.),
+            ],
+        );
+        let expected = complex_tensor(vec![2, 2, 1], &[(0., 2.), (7., 1.), (0., 1.), (0., 2.)]);
+        assert_eq!(batched.matmul(&b).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_complex_transpose() {
+        let a = complex_tensor(
+            vec![2, 3],
+            &[(1., 1.), (2., 0.), (0., 3.), (4., -4.), (5., 0.), (0., -6.)],
+        );
+        let transposed = complex_tensor(
+            vec![3, 2],
+            &[(1., 1.), (4., -4.), (2., 0.), (5., 0.), (0., 3.), (0., -6.)],
+        );
+        assert_eq!(a.transpose(), transposed);
+        let adjoint = complex_tensor(
+            vec![3, 2],
+            &[
+                (1., -1.),
+                (4., 4.),
+                (2., -0.),
+                (5., -0.),
+                (0., -3.),
+                (0., 6.),
+            ],
+        );
+        assert_eq!(a.conj_transpose(), adjoint);
+
+        // A A^H is Hermitian, with the squared norms of the rows on its (real) diagonal.
+        let gram = a.matmul(&a.conj_transpose()).unwrap();
+        assert_eq!(gram.conj_transpose(), gram);
+        assert_eq!(gram.at(vec![0, 0]), Some(&Complex64::new(15., 0.)));
+        assert_eq!(gram.at(vec![1, 1]), Some(&Complex64::new(93., 0.)));
+    }
+
+    #[test]
+    fn test_complex_dim_sum() {
+        let a = complex_tensor(vec![2, 2], &[(1., 1.), (2., -1.), (3., 0.5), (0., 2.)]);
+        assert_eq!(
+            a.dim_sum(vec![0]),
+            complex_tensor(vec![1, 2], &[(4., 1.5), (2., 1.)])
+        );
+        assert_eq!(
+            a.dim_sum(vec![1]),
+            complex_tensor(vec![2, 1], &[(3., 0.), (3., 2.5)])
+        );
+        assert_eq!(
+            a.dim_sum(vec![0, 1]),
+            complex_tensor(vec![1, 1], &[(6., 2.5)])
+        );
+    }
+
+    #[test]
+    fn test_real_conj_transpose() {
+        let mut rng = rand::thread_rng();
+        let a = make_random_f64_tensor(&mut rng, vec![3, 4, 2]);
+        assert_eq!(a.conj_transpose(), a.transpose());
+    }
 }
@@ -0,0 +1,134 @@
+//! Algebraic properties of the tensor operations, checked for exact equality with `Rational`
+//! elements.
+
+use elements::rational::Rational;
+use interfaces::tensors::Tensor;
+use proptest::prelude::*;
+use tensors::TensorImpl;
+
+fn rational() -> impl Strategy<Value = Rational> {
+    (-20_i128..=20, 1_i128..=12).prop_map(|(numer, denom)| Rational::new(numer, denom).unwrap())
+}
+
+fn tensor(shape: Vec<usize>) -> impl Strategy<Value = TensorImpl<Rational>> {
+    let len: usize = shape.iter().product();
+    prop::collection::vec(rational(), len)
+        .prop_map(move |data| TensorImpl::from_vec(&shape, &data).unwrap())
+}
+
+/// Tensors of shapes (b, n, k), (k, l) and (l, m), for chained matmuls.
+fn matmul_chain() -> impl Strategy<
+    Value = (
+        TensorImpl<Rational>,
+        TensorImpl<Rational>,
+        TensorImpl<Rational>,
+    ),
+> {
+    (1_usize..3, 1_usize..4, 1_usize..4, 1_usize..4, 1_usize..4).prop_flat_map(|(b, n, k, l, m)| {
+        (
+            tensor(vec![b, n, k]),
+            tensor(vec![k, l]),
+            tensor(vec![l, m]),
+        )
+    })
+}
+
+/// A tensor of shape (n, k, m) and two tensors its shape broadcasts with: one without its first
+/// and one without its last dimension (both kept as size 1).
+fn broadcast_triple() -> impl Strategy<
+    Value = (
+        TensorImpl<Rational>,
+        TensorImpl<Rational>,
+        TensorImpl<Rational>,
+    ),
+> {
+    (1_usize..4, 1_usize..4, 1_usize..4).prop_flat_map(|(n, k, m)| {
+        (
+            tensor(vec![n, k, m]),
+            tensor(vec![1, k, m]),
+            tensor(vec![n, k, 1]),
+        )
+    })
+}
+
+proptest! {
+    #[test]
+    fn matmul_is_associative((a, b, c) in matmul_chain()) {
+        let lhs = a.matmul(&b).unwrap().matmul(&c).unwrap();
+        let rhs = a.matmul(&b.matmul(&c).unwrap()).unwrap();
+        prop_assert_eq!(lhs, rhs);
+    }
+
+    #[test]
+    fn matmul_distributes_over_addition(
+        (a, b, c) in matmul_chain()
+            .prop_flat_map(|(a, b, _)| {
+                let shape = b.shape();
+                (Just(a), Just(b), tensor(shape))
+            })
+    ) {
+        let lhs = a.matmul(&(b.clone() + c.clone())).unwrap();
+        let rhs = a.matmul(&b).unwrap() + a.matmul(&c).unwrap();
+        prop_assert_eq!(lhs, rhs);
+    }
+
+    #[test]
+    fn transpose_of_product((_, b, c) in matmul_chain()) {
+        let lhs = b.matmul(&c).unwrap().transpose();
+        let rhs = c.transpose().matmul(&b.transpose()).unwrap();
+        prop_assert_eq!(lhs, rhs);
+    }
+
+    #[test]
+    fn broadcasting_distributes((a, b, c) in broadcast_triple()) {
+        let lhs = (a.clone() + b.clone()) * c.clone();
+        let rhs = a * c.clone() + b * c;
+        prop_assert_eq!(lhs, rhs);
+    }
+
+    #[test]
+    fn broadcasting_commutes((a, b, c) in broadcast_triple()) {
+        prop_assert_eq!(a.clone() + b.clone(), b.clone() + a.clone());
+        prop_assert_eq!(a.clone() * c.clone(), c * a);
+    }
+
+    #[test]
+    fn dim_sum_order_is_irrelevant((a, _, _) in broadcast_triple()) {
+        let all = a.dim_sum(vec![0, 1, 2]);
+        prop_assert_eq!(a.dim_sum(vec![2, 0, 1]), all.clone());
+        let total = a.get_data().iter().fold(Rational::from(0), |acc, x| acc + *x);
+        prop_assert_eq!(all.get_data(), &vec![total]);
+    }
+
+    #[test]
+    fn dim_sum_is_linear((a, b, _) in broadcast_triple(), dim in 1_usize..3) {
+        // Not across dimension 0, which `b` is broadcast along.
+        let sum = a.clone() + b.clone();
+        prop_assert_eq!(sum.dim_sum(vec![dim]), a.dim_sum(vec![dim]) + b.dim_sum(vec![dim]));
+    }
+
+    #[test]
+    fn dim_sum_of_matmul((a, b, _) in matmul_chain()) {
+        // Summing the rows of a product is the product of the summed rows.
+        let lhs = a.matmul(&b).unwrap().dim_sum(vec![1]);
+        let rhs = a.dim_sum(vec![1]).matmul(&b).unwrap();
+        prop_assert_eq!(lhs, rhs);
+    }
+
+    #[test]
+    fn concat_then_dim_sum((a, b, _) in broadcast_triple(), dim in 1_usize..3) {
+        // Summing across the concatenated dimension sums the parts.
+        let concatenated = a.concat(&b, 0).unwrap();
+        prop_assert_eq!(
+            concatenated.dim_sum(vec![0]),
+            a.dim_sum(vec![0]) + b.dim_sum(vec![0])
+        );
+        // Summing across another dimension commutes with the concatenation.
+        let concatenated = a.concat(&a, dim).unwrap();
+        let other = if dim == 1 { 2 } else { 1 };
+        prop_assert_eq!(
+            concatenated.dim_sum(vec![other]),
+            a.dim_sum(vec![other]).concat(&a.dim_sum(vec![other]), dim).unwrap()
+        );
+    }
+}
@@ -1,13 +1,18 @@
-use attention::attention::{El, La, Mal, Te};
 use attention::attention::{MultiHeadAttention, SelfAttention};
 use config::Config;
 use interfaces::{
-    deep_learning::{ActivationLayer, DLModule, LinearLayer},
-    tensors::{RealElement, Tensor},
+    deep_learning::{
+        prefix_named_parameters, prefix_paths, ActivationLayer, DLModule, LinearLayer,
+    },
+    tensors::{AsStdError, Piecewise, RealElement, RealTensor, Tensor},
 };
 
-use neural_nets::{act_layer::ActLayer, lin_layer::LinLayer};
+use neural_nets::{
+    act_layer::ActLayer, dropout::Dropout, feed_forward::FeedForward, initializer::derive_seed,
+    lin_layer::LinLayer,
+};
 use std::marker::PhantomData;
+use tensors::TensorImpl;
 
 // keras_nlp.layers.TransformerEncoder(
 //     intermediate_dim,
@@ -35,22 +40,23 @@ where
     Al: ActivationLayer<T, E>,
 {
     pub self_attention: A,
-    pub linear_layer1: L, // i: C, o: 4C
-    pub activation_layer: Al,
-    pub linear_layer2: L, // i: 4C, o: C
+    pub feed_forward: FeedForward<L, Al, T, E>, // i: C, o: C
+    pub attention_dropout: Dropout<T, E>,
+    pub feed_forward_dropout: Dropout<T, E>,
     pub intermediate_dim: usize,
     pub num_head: usize,
+    training: bool,
     pub _marker_t: PhantomData<T>,
     _marker_e: PhantomData<E>,
 }
 
 impl<T, E, L, A, Al> DLModule<T, E> for Block<L, A, T, E, Al>
 where
-    L: LinearLayer<T, E>,
+    L: LinearLayer<T, E, DLModuleError = <T as Tensor<E>>::TensorError>,
     A: SelfAttention<T, E>,
     T: Tensor<E>,
     E: RealElement,
-    Al: ActivationLayer<T, E>,
+    Al: ActivationLayer<T, E, DLModuleError = <T as Tensor<E>>::TensorError>,
 {
     type DLModuleError = <T as Tensor<E>>::TensorError;
 
@@ -58,68 +64,171 @@ where
         // A block consists of a self-attention layer followed by a feed-forward neural network.
         // It also implements residual connections after each sub-layer.
         // The residual connection adds the original embedding matrix x to the output of the sub-layer.
-        // The feed forward neural network consists of two linear layers with a ReLU activation in between.
-        // The first linear layer expands to 4 times the embedding dimension,
-        // and the second linear layer projects back to the original embedding dimension.
+        // The classic feed forward neural network consists of two linear layers with an activation
+        // in between. The first linear layer expands to the intermediate dimension (eg. 4 times
+        // the embedding dimension), and the second linear layer projects back to the original
+        // embedding dimension. The gated variant (see `GatedFeedForward`) multiplies the expanded
+        // input by an activated gate before projecting back.
+        // In training mode, dropout is applied to the outputs of both sub-layers before the
+        // residual connections.
 
         // TODO: implement residual connections
         println!("{}", "-".repeat(10));
         let att: T = self.self_attention.forward(x).unwrap(); // in: (B x T x C), out: (B x T x C)
+        let att: T = self.attention_dropout.forward(&att)?; // Only in training mode
         let residual1: T = att.clone() + x.clone(); // in: (B x T x C), out: (B x T x C)
         println!("{}", "*".repeat(10));
-        let lin: T = self.linear_layer1.forward(&residual1).unwrap(); // in: (B x T x C), out: (B x T x 4C)
-        let act: T = self.activation_layer.forward(&lin).unwrap(); // in: (B x T x 4C), out: (B x T x 4C)
-        let lin2: T = self.linear_layer2.forward(&act).unwrap(); // in: (B x T x 4C), out: (B x T x C)
+        let ff: T = self.feed_forward.forward(&residual1)?; // in: (B x T x C), out: (B x T x C)
+        let ff: T = self.feed_forward_dropout.forward(&ff)?; // Only in training mode
 
-        let residual2: T = lin2.clone() + residual1.clone(); // in: (B x T x C), out: (B x T x C)
+        let residual2: T = ff.clone() + residual1.clone(); // in: (B x T x C), out: (B x T x C)
         println!("{}", "-".repeat(10));
         Ok(residual2) // (B x T x C)
     }
 
     fn params(&self) -> Vec<E> {
         // pub self_attention: A,
-        // pub linear_layer1: L, // i: C, o: 4C
-        // pub activation_layer: Al,
-        // pub linear_layer2: L, // i: 4C, o: C
+        // pub feed_forward: FeedForward<L, Al, T, E>,
         self.self_attention
             .params()
             .into_iter()
-            .chain(self.linear_layer1.params().into_iter())
-            .chain(self.activation_layer.params().into_iter())
-            .chain(self.linear_layer2.params().into_iter())
+            .chain(self.feed_forward.params())
             .collect()
     }
+
+    fn param_paths(&self) -> Vec<String> {
+        let mut paths = prefix_paths("attn", self.self_attention.param_paths());
+        paths.extend(prefix_paths("ff", self.feed_forward.param_paths()));
+        paths
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, T)> {
+        let mut params = prefix_named_parameters("attn", self.self_attention.named_parameters());
+        params.extend(prefix_named_parameters(
+            "ff",
+            self.feed_forward.named_parameters(),
+        ));
+        params
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut T>, AsStdError> {
+        let mut params = self.self_attention.parameters_mut()?;
+        params.extend(self.feed_forward.parameters_mut()?);
+        Ok(params)
+    }
+
+    fn train(&mut self, training: bool) {
+        self.training = training;
+        self.self_attention.train(training);
+        self.feed_forward.train(training);
+        self.attention_dropout.train(training);
+        self.feed_forward_dropout.train(training);
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
+    }
 }
 
 // TODO: once activation is concrete
 // Block<L, A, T, E, Al>
-impl Block<La, Mal, Te, El, ActLayer<Te, El>> {
+impl<E: RealElement + Piecewise>
+    Block<
+        LinLayer<TensorImpl<E>, E>,
+        MultiHeadAttention<TensorImpl<E>, E, LinLayer<TensorImpl<E>, E>>,
+        TensorImpl<E>,
+        E,
+        ActLayer<TensorImpl<E>, E>,
+    >
+{
+    /// The feed-forward network is gated if `config.gated_feed_forward` (eg. SwiGLU with the
+    /// `"silu"` activation), classic otherwise.
+    ///
+    /// Panics if `config.activation` is not a valid `Activation` (eg. `"relu"` or `"gelu"`).
     pub fn new(config: &Config, is_masked: bool) -> Self {
-        let self_attention = MultiHeadAttention::new(config, is_masked);
+        // The seeds of the sub-modules are derived from `config.seed` and their names.
+        let attention_config = Config {
+            seed: derive_seed(config.seed, "attn"),
+            ..config.clone()
+        };
+        let self_attention = MultiHeadAttention::new(&attention_config, is_masked);
         // Residual connection: add embedding matrix X to the output of the sub-layer element-wise
-        let linear_layer1 = LinLayer::new(config.embed_dim, 4 * config.embed_dim, config.seed);
-        let activation_layer = ActLayer::new();
-        let linear_layer2 = LinLayer::new(4 * config.embed_dim, config.embed_dim, config.seed);
+        let activation = config
+            .activation
+            .parse()
+            .expect("The config must name a valid activation.");
+        let make_feed_forward = if config.gated_feed_forward {
+            FeedForward::gated
+        } else {
+            FeedForward::classic
+        };
+        let feed_forward = make_feed_forward(
+            config.embed_dim,
+            config.intermediate_dim,
+            activation,
+            config.seed,
+        );
+        let attention_dropout =
+            Dropout::new(config.dropout, derive_seed(config.seed, "attn_dropout"));
+        let feed_forward_dropout =
+            Dropout::new(config.dropout, derive_seed(config.seed, "ff_dropout"));
         // Residual connection: add embedding matrix X to the output of the sub-layer element-wise
         Self {
             self_attention,
-            linear_layer1,
-            activation_layer,
-            linear_layer2,
-            intermediate_dim: config.embed_dim * 4,
+            feed_forward,
+            attention_dropout,
+            feed_forward_dropout,
+            intermediate_dim: config.intermediate_dim,
             num_head: config.num_head,
+            training: true,
             _marker_t: PhantomData,
             _marker_e: PhantomData,
         }
     }
 }
 
+impl<L, T, E, Al> Block<L, MultiHeadAttention<T, E, L>, T, E, Al>
+where
+    L: LinearLayer<T, E>,
+    T: RealTensor<E>,
+    E: RealElement,
+    Al: ActivationLayer<T, E>,
+{
+    /// Replace each linear layer `l` (of the attention, then of the feed-forward network) with
+    /// `f(l)`, eg. to quantize the block.
+    #[allow(clippy::type_complexity)]
+    pub fn try_map_linear_layers<L2, Er>(
+        self,
+        f: &mut impl FnMut(L) -> Result<L2, Er>,
+    ) -> Result<Block<L2, MultiHeadAttention<T, E, L2>, T, E, Al>, Er>
+    where
+        L2: LinearLayer<T, E>,
+    {
+        Ok(Block {
+            self_attention: self.self_attention.try_map_linear_layers(f)?,
+            feed_forward: self.feed_forward.try_map_linear_layers(f)?,
+            attention_dropout: self.attention_dropout,
+            feed_forward_dropout: self.feed_forward_dropout,
+            intermediate_dim: self.intermediate_dim,
+            num_head: self.num_head,
+            training: self.training,
+            _marker_t: PhantomData,
+            _marker_e: PhantomData,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use autodiff::node::Node;
+    #[cfg(not(feature = "tape"))]
+    use neural_nets::checkpoint::Checkpoint;
     use num_traits::Zero;
 
     use super::*;
+    use attention::attention::{El, La, Mal, Te};
+    use neural_nets::act_layer::Activation;
+
+    type TestBlock = Block<La, Mal, Te, El, ActLayer<Te, El>>;
 
     fn get_config() -> Config {
         Config {
@@ -130,6 +239,10 @@ mod tests {
             num_head: 4,
             seed: 0,
             num_blocks: 1,
+            dropout: 0.0,
+            activation: "relu".to_string(),
+            intermediate_dim: 80,
+            gated_feed_forward: false,
         }
     }
 
@@ -138,17 +251,17 @@ mod tests {
         let config = get_config();
         // query + values + keys + lin layer 1 + lin layer 2
         // 7 * 7
-        let block = Block::new(&config, true);
+        let block = TestBlock::new(&config, true);
         println!("{}", block.params().len());
     }
 
     #[test]
     fn test_forward() {
         let config = get_config();
-        let block = Block::new(&config, true);
+        let block = TestBlock::new(&config, true);
         let x = Te::from_vec(
             &vec![config.batch_size, config.seq_len, config.embed_dim],
-            &vec![Node::<f64>::zero(); config.batch_size * config.seq_len * config.embed_dim],
+            &vec![El::zero(); config.batch_size * config.seq_len * config.embed_dim],
         )
         .unwrap();
         let out = block.forward(&x).unwrap();
@@ -156,4 +269,145 @@ mod tests {
         let actual_shape = out.shape();
         assert_eq!(actual_shape, expected_shape);
     }
+
+    #[test]
+    fn test_activation_from_config() {
+        let config = Config {
+            activation: "gelu_tanh".to_string(),
+            ..get_config()
+        };
+        let block = TestBlock::new(&config, true);
+        match block.feed_forward {
+            FeedForward::Classic {
+                activation_layer, ..
+            } => assert_eq!(activation_layer.activation, Activation::GELUTanh),
+            FeedForward::Gated(_) => panic!("Expected the classic feed-forward network."),
+        }
+    }
+
+    #[test]
+    fn test_gated_feed_forward() {
+        let config = Config {
+            activation: "silu".to_string(),
+            intermediate_dim: 32,
+            gated_feed_forward: true,
+            ..get_config()
+        };
+        let block = TestBlock::new(&config, true);
+        let attention_params = block.self_attention.params().len();
+        assert_eq!(
+            block.params().len(),
+            attention_params + 2 * (20 * 32 + 32) + (32 * 20 + 20)
+        );
+        let paths = block.param_paths();
+        assert_eq!(paths[attention_params], "ff.gate.w[0, 0]");
+        assert_eq!(paths.last().unwrap(), "ff.down.b[0, 19]");
+
+        let x = Te::from_vec(
+            &vec![config.batch_size, config.seq_len, config.embed_dim],
+            &vec![El::zero(); config.batch_size * config.seq_len * config.embed_dim],
+        )
+        .unwrap();
+        assert_eq!(block.forward(&x).unwrap().shape(), vec![2, 7, 20]);
+
+        // The query, key and value of each head, then gate, up and down.
+        let mut num_layers = 0;
+        block
+            .try_map_linear_layers(&mut |layer| {
+                num_layers += 1;
+                Ok::<_, ()>(layer)
+            })
+            .unwrap();
+        assert_eq!(num_layers, 3 * config.num_head + 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "The config must name a valid activation.")]
+    fn test_invalid_activation() {
+        let config = Config {
+            activation: "mish".to_string(),
+            ..get_config()
+        };
+        TestBlock::new(&config, true);
+    }
+
+    #[test]
+    fn test_dropout_only_in_training() {
+        let config = Config {
+            dropout: 0.2,
+            ..get_config()
+        };
+        let data: Vec<f64> = (0..config.batch_size * config.seq_len * config.embed_dim)
+            .map(|x| ((x % 11) as f64 - 5.0) / 10.0)
+            .collect();
+        let x = TensorImpl::from_vec(
+            &vec![config.batch_size, config.seq_len, config.embed_dim],
+            &data,
+        )
+        .unwrap();
+        let mut block = Block::new(&config, false);
+        let without_dropout = Block::new(&get_config(), false);
+        assert!(block.is_training());
+        assert_ne!(
+            block.forward(&x).unwrap(),
+            without_dropout.forward(&x).unwrap()
+        );
+
+        block.train(false);
+        assert!(!block.is_training());
+        assert!(!block.feed_forward.is_training());
+        assert!(!block.feed_forward_dropout.is_training());
+        assert_eq!(
+            block.forward(&x).unwrap(),
+            without_dropout.forward(&x).unwrap()
+        );
+    }
+
+    // `Checkpoint` is implemented for `Node` and `SyncNode`.
+    #[cfg(not(feature = "tape"))]
+    #[test]
+    fn test_checkpointed_blocks_match_plain_blocks() {
+        let config = get_config();
+        let x_data: Vec<f64> = (0..config.batch_size * config.seq_len * config.embed_dim)
+            .map(|x| ((x % 11) as f64 - 5.0) / 10.0)
+            .collect();
+        let input = || {
+            let data: Vec<El> = x_data.iter().map(|x| El::new(*x, None)).collect();
+            Te::from_vec(
+                &vec![config.batch_size, config.seq_len, config.embed_dim],
+                &data,
+            )
+            .unwrap()
+        };
+        let loss = |out: Te| {
+            out.into_iter()
+                .fold(El::zero(), |acc, y| acc + y.clone() * y)
+        };
+
+        let plain = [
+            TestBlock::new(&config, true),
+            TestBlock::new(&config, true),
+        ];
+        let out = plain[1]
+            .forward(&plain[0].forward(&input()).unwrap())
+            .unwrap();
+        loss(out).backward(1.0);
+
+        let checkpointed = [
+            Checkpoint::new(TestBlock::new(&config, true)),
+            Checkpoint::new(TestBlock::new(&config, true)),
+        ];
+        let out = checkpointed[1]
+            .forward(&checkpointed[0].forward(&input()).unwrap())
+            .unwrap();
+        loss(out).backward(1.0);
+
+        for (block, checkpointed) in plain.iter().zip(checkpointed.iter()) {
+            for (p, p_checkpointed) in block.params().iter().zip(checkpointed.params().iter()) {
+                let grad = p.grad().unwrap_or(0.0);
+                let grad_checkpointed = p_checkpointed.grad().unwrap_or(0.0);
+                assert!(f64::abs(grad - grad_checkpointed) < 1e-9);
+            }
+        }
+    }
 }
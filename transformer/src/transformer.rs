@@ -1,16 +1,22 @@
 use crate::block::Block;
-use attention::attention::SelfAttention;
-use attention::attention::{El, La, Mal, Te};
+use anyhow::Error;
+use attention::attention::{MultiHeadAttention, SelfAttention};
 use config::Config;
 use embeddings::pos_encoding::PELayer;
-use interfaces::deep_learning::{ActivationLayer, DLModule};
-use interfaces::deep_learning::{EmbeddingLayer, LinearLayer};
-use interfaces::tensors::{RealElement, RealTensor, Tensor};
+use interfaces::deep_learning::LinearLayer;
+use interfaces::deep_learning::{prefix_named_parameters, prefix_paths, ActivationLayer, DLModule};
+use interfaces::tensors::{AsStdError, Element, Piecewise, RealElement, RealTensor, Tensor};
 use neural_nets::embedding_table::EmbeddingTable;
-use neural_nets::{act_layer::ActLayer, lin_layer::LinLayer, serial::Serial};
-use std::default::Default;
-use std::marker::PhantomData;
+use neural_nets::initializer::derive_seed;
+use neural_nets::quantized_lin_layer::{Observed, QuantizedLinLayer};
+use neural_nets::{act_layer::ActLayer, lin_layer::LinLayer};
+use tensors::TensorImpl;
 
+/// Decoder-only transformer: token embedding, positional encoding, a stack of `Block`s and a
+/// linear projection back to the vocabulary, followed by a softmax over the vocabulary.
+///
+/// The sub-modules are held as concrete fields (rather than in a `Serial`) so that the model is
+/// `Send + Sync` whenever its element type is (see the `sync` feature).
 pub struct Transformer<L, A, T, E, Al>
 where
     L: LinearLayer<T, E>,
@@ -20,68 +26,231 @@ where
     E: RealElement,
     Al: ActivationLayer<T, E>,
 {
-    model: Serial<T, E>,
-    _marker_l: std::marker::PhantomData<L>,
-    _marker_a: std::marker::PhantomData<A>,
-    _marker_al: std::marker::PhantomData<Al>,
+    embedding: EmbeddingTable<T, E>,
+    pos_encoding: PELayer<T, E>,
+    blocks: Vec<Block<L, A, T, E, Al>>,
+    lm_head: L,
+    training: bool,
 }
 
-impl Transformer<La, Mal, Te, El, ActLayer<Te, El>> {
+impl<E: RealElement + Into<f64> + Piecewise>
+    Transformer<
+        LinLayer<TensorImpl<E>, E>,
+        MultiHeadAttention<TensorImpl<E>, E, LinLayer<TensorImpl<E>, E>>,
+        TensorImpl<E>,
+        E,
+        ActLayer<TensorImpl<E>, E>,
+    >
+{
     pub fn new(config: &Config) -> Self {
-        let mut modules: Vec<
-            Box<dyn DLModule<Te, El, DLModuleError = <Te as Tensor<El>>::TensorError>>,
-        > = vec![];
-        modules.push(Box::new(EmbeddingTable::new(
-            config.embed_dim,
-            config.vocab_size,
-            config.seed,
-        )));
-        modules.push(Box::new(PELayer::<Te, El>::new()));
-
-        for i in 0..config.num_blocks {
-            modules.push(Box::new(Block::new(config, i == 0)));
-        }
-        modules.push(Box::new(LinLayer::new(
-            config.embed_dim,
-            config.vocab_size,
-            config.seed,
-        )));
-        let model = Serial::new(modules);
+        // The seeds of the sub-modules are derived from `config.seed` and their names.
+        let seed = |name: &str| derive_seed(config.seed, name);
+        let embedding = EmbeddingTable::new(config.embed_dim, config.vocab_size, seed("embedding"));
+        let pos_encoding = PELayer::new();
+        let blocks = (0..config.num_blocks)
+            .map(|i| {
+                let block_config = Config {
+                    seed: seed(&format!("blocks.{}", i)),
+                    ..config.clone()
+                };
+                Block::new(&block_config, i == 0)
+            })
+            .collect();
+        let lm_head = LinLayer::new(config.embed_dim, config.vocab_size, seed("lm_head"));
         Self {
-            model,
-            _marker_a: PhantomData,
-            _marker_al: PhantomData,
-            _marker_l: PhantomData,
+            embedding,
+            pos_encoding,
+            blocks,
+            lm_head,
+            training: true,
         }
     }
 }
 
 impl<T, E, L, A, Al> DLModule<T, E> for Transformer<L, A, T, E, Al>
 where
-    L: LinearLayer<T, E>,
+    L: LinearLayer<T, E, DLModuleError = <T as Tensor<E>>::TensorError>,
     A: SelfAttention<T, E>,
     T: RealTensor<E>,
-    E: RealElement,
-    Al: ActivationLayer<T, E>,
+    E: RealElement + Into<f64>,
+    Al: ActivationLayer<T, E, DLModuleError = <T as Tensor<E>>::TensorError>,
 {
     type DLModuleError = <T as Tensor<E>>::TensorError;
 
     fn forward(&self, x: &T) -> Result<T, Self::DLModuleError> {
-        Ok(self.model.forward(x)?.softmax(2))
+        let mut tmp = self.embedding.forward(x)?;
+        tmp = self.pos_encoding.forward(&tmp)?;
+        for block in self.blocks.iter() {
+            tmp = block.forward(&tmp)?;
+        }
+        Ok(self.lm_head.forward(&tmp)?.softmax(2))
     }
 
     fn params(&self) -> Vec<E> {
-        self.model.params()
+        let mut params = self.embedding.params();
+        params.extend(self.pos_encoding.params());
+        for block in self.blocks.iter() {
+            params.extend(block.params());
+        }
+        params.extend(self.lm_head.params());
+        params
+    }
+
+    fn param_paths(&self) -> Vec<String> {
+        let mut paths = prefix_paths("embedding", self.embedding.param_paths());
+        paths.extend(prefix_paths(
+            "pos_encoding",
+            self.pos_encoding.param_paths(),
+        ));
+        for (idx, block) in self.blocks.iter().enumerate() {
+            paths.extend(prefix_paths(
+                &format!("blocks.{}", idx),
+                block.param_paths(),
+            ));
+        }
+        paths.extend(prefix_paths("lm_head", self.lm_head.param_paths()));
+        paths
+    }
+
+    fn named_parameters(&self) -> Vec<(String, Vec<usize>, T)> {
+        let mut params = prefix_named_parameters("embedding", self.embedding.named_parameters());
+        params.extend(prefix_named_parameters(
+            "pos_encoding",
+            self.pos_encoding.named_parameters(),
+        ));
+        for (idx, block) in self.blocks.iter().enumerate() {
+            params.extend(prefix_named_parameters(
+                &format!("blocks.{}", idx),
+                block.named_parameters(),
+            ));
+        }
+        params.extend(prefix_named_parameters(
+            "lm_head",
+            self.lm_head.named_parameters(),
+        ));
+        params
+    }
+
+    fn parameters_mut(&mut self) -> Result<Vec<&mut T>, AsStdError> {
+        let mut params = self.embedding.parameters_mut()?;
+        params.extend(self.pos_encoding.parameters_mut()?);
+        for block in self.blocks.iter_mut() {
+            params.extend(block.parameters_mut()?);
+        }
+        params.extend(self.lm_head.parameters_mut()?);
+        Ok(params)
+    }
+
+    fn train(&mut self, training: bool) {
+        self.training = training;
+        self.embedding.train(training);
+        self.pos_encoding.train(training);
+        for block in self.blocks.iter_mut() {
+            block.train(training);
+        }
+        self.lm_head.train(training);
+    }
+
+    fn is_training(&self) -> bool {
+        self.training
     }
 }
 
+impl<L, T, E, Al> Transformer<L, MultiHeadAttention<T, E, L>, T, E, Al>
+where
+    L: LinearLayer<T, E>,
+    T: RealTensor<E>,
+    E: RealElement,
+    Al: ActivationLayer<T, E>,
+{
+    /// Replace each linear layer `l` (of the blocks, then the head) with `f(l)`, eg. to quantize
+    /// the model.
+    #[allow(clippy::type_complexity)]
+    pub fn try_map_linear_layers<L2, Er>(
+        self,
+        f: &mut impl FnMut(L) -> Result<L2, Er>,
+    ) -> Result<Transformer<L2, MultiHeadAttention<T, E, L2>, T, E, Al>, Er>
+    where
+        L2: LinearLayer<T, E>,
+    {
+        Ok(Transformer {
+            embedding: self.embedding,
+            pos_encoding: self.pos_encoding,
+            blocks: self
+                .blocks
+                .into_iter()
+                .map(|block| block.try_map_linear_layers(f))
+                .collect::<Result<_, _>>()?,
+            lm_head: f(self.lm_head)?,
+            training: self.training,
+        })
+    }
+}
+
+/// Transformer with `f64` elements, for inference.
+pub type F64Transformer<L> = Transformer<
+    L,
+    MultiHeadAttention<TensorImpl<f64>, f64, L>,
+    TensorImpl<f64>,
+    f64,
+    ActLayer<TensorImpl<f64>, f64>,
+>;
+
+impl F64Transformer<LinLayer<TensorImpl<f64>, f64>> {
+    /// Replace every linear layer with a `QuantizedLinLayer`, calibrated on the range of its
+    /// inputs over the `calibration` batches of token ids, of shape (B, T, 1). The calibration,
+    /// and so the quantized model, is in evaluation mode.
+    pub fn quantize(
+        mut self,
+        calibration: &[TensorImpl<f64>],
+    ) -> Result<F64Transformer<QuantizedLinLayer>, AsStdError> {
+        self.train(false);
+        let observed =
+            self.try_map_linear_layers(&mut |layer| Ok::<_, AsStdError>(Observed::new(layer)))?;
+        for batch in calibration.iter() {
+            observed.forward(batch)?;
+        }
+        observed.try_map_linear_layers(&mut |layer| QuantizedLinLayer::from_observed(&layer))
+    }
+}
+
+/// Perplexity of the `targets` (token ids, in (B, T) order) under the predicted distributions
+/// `probs` of shape (B, T, vocab_size), ie. the exponential of the mean negative log-likelihood.
+pub fn perplexity<T, E>(probs: &T, targets: &[usize]) -> Result<f64, AsStdError>
+where
+    T: Tensor<E>,
+    E: Element + Into<f64>,
+{
+    let shape = probs.shape();
+    if shape.len() != 3 || shape[0] * shape[1] != targets.len() {
+        return Err(Error::msg(format!(
+            "Expected probabilities of shape (B, T, vocab_size) for {} targets, got {:?}.",
+            targets.len(),
+            shape
+        ))
+        .into());
+    }
+    let probs: Vec<E> = probs.clone().into();
+    let vocab_size = shape[2];
+    let nll = targets
+        .iter()
+        .enumerate()
+        .map(|(idx, target)| -probs[idx * vocab_size + target].clone().into().ln())
+        .sum::<f64>();
+    Ok((nll / targets.len() as f64).exp())
+}
+
 #[cfg(test)]
 mod tests {
-    use autodiff::node::Node;
+    use attention::attention::{El, La, Mal, Te};
+    use interfaces::utils::Ln;
+    use neural_nets::optim::{OptimSGD, Optimizer};
     use num_traits::Zero;
 
     use super::*;
 
+    type TestTransformer = Transformer<La, Mal, Te, El, ActLayer<Te, El>>;
+
     fn get_config() -> Config {
         Config {
             batch_size: 2,
@@ -91,23 +260,117 @@ mod tests {
             num_head: 4,
             num_blocks: 4,
             seed: 0,
+            dropout: 0.0,
+            activation: "relu".to_string(),
+            intermediate_dim: 80,
+            gated_feed_forward: false,
         }
     }
 
     #[test]
     fn test_construct() {
         let config = get_config();
-        let model = Transformer::new(&config);
+        let model = TestTransformer::new(&config);
         println!("{}", model.params().len());
     }
 
+    #[test]
+    fn test_param_paths() {
+        let config = get_config();
+        let model = TestTransformer::new(&config);
+        let paths = model.param_paths();
+        assert_eq!(paths.len(), model.params().len());
+        assert_eq!(paths[0], "embedding.table[0, 0]");
+        let block_start = config.vocab_size * config.embed_dim;
+        assert_eq!(paths[block_start], "blocks.0.attn.query.0.w[0, 0]");
+        assert!(paths.contains(&"blocks.3.ff.linear2.b[0, 19]".to_string()));
+        assert_eq!(paths.last().unwrap(), "lm_head.b[0, 11]");
+    }
+
+    #[test]
+    fn test_named_parameters() {
+        let config = get_config();
+        let model = TestTransformer::new(&config);
+        let named = model.named_parameters();
+        let params: Vec<El> = named
+            .iter()
+            .flat_map(|(_, _, tensor)| tensor.get_data().clone())
+            .collect();
+        assert_eq!(params, model.params());
+        let (path, shape, _) = &named[1];
+        assert_eq!(path, "blocks.0.attn.query.0.w");
+        assert_eq!(shape, &vec![20, 5]);
+        assert_eq!(named[0].1, vec![config.vocab_size, config.embed_dim]);
+        assert!(named
+            .iter()
+            .any(|(path, _, _)| path == "blocks.3.ff.linear1.w"));
+        assert_eq!(named.last().unwrap().0, "lm_head.b");
+    }
+
+    #[test]
+    fn test_load_state_dict() {
+        let config = get_config();
+        let model = TestTransformer::new(&config);
+        let mut other = TestTransformer::new(&Config {
+            seed: 1,
+            ..get_config()
+        });
+        assert_ne!(model.state_dict(), other.state_dict());
+        other.load_state_dict(&model.state_dict()).unwrap();
+        assert_eq!(model.state_dict(), other.state_dict());
+        // A copy: the parameters of the models are independent.
+        let mut params = other.params();
+        params[0].set_val(100.);
+        assert_ne!(model.params()[0].val(), 100.);
+
+        let mut wider = TestTransformer::new(&Config {
+            intermediate_dim: 40,
+            ..get_config()
+        });
+        let before = wider.state_dict();
+        let err = wider.load_state_dict(&model.state_dict()).unwrap_err();
+        assert!(err.to_string().contains("blocks.0.ff.linear1.w"));
+        assert_eq!(wider.state_dict(), before);
+    }
+
+    #[test]
+    fn test_load_state_dict_before_optimizer_step() {
+        let config = get_config();
+        let model = TestTransformer::new(&config);
+        let mut other = TestTransformer::new(&Config {
+            seed: 1,
+            ..get_config()
+        });
+        // Collected before the load, the parameters of the optimizer are those of the model.
+        let mut optimizer = OptimSGD::new(0.1, 1, other.params());
+        other.load_state_dict(&model.state_dict()).unwrap();
+        for param in other.params().iter_mut() {
+            param.set_grad(1.);
+        }
+        optimizer.step();
+        let expected: Vec<f64> = model.params().iter().map(|p| p.val() - 0.1).collect();
+        let actual: Vec<f64> = other.params().iter().map(|p| p.val()).collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_train() {
+        let mut model = TestTransformer::new(&get_config());
+        assert!(model.is_training());
+        model.train(false);
+        assert!(!model.is_training());
+        assert!(model.blocks.iter().all(|block| !block.is_training()));
+        model.train(true);
+        assert!(model.blocks[3].attention_dropout.is_training());
+    }
+
     #[test]
     fn test_forward() {
         let config = get_config();
-        let model = Transformer::new(&config);
+        let model = TestTransformer::new(&config);
         let x = Te::from_vec(
             &vec![config.batch_size, config.seq_len, 1],
-            &vec![Node::<f64>::zero(); config.batch_size * config.seq_len * 1],
+            &vec![El::zero(); config.batch_size * config.seq_len * 1],
         )
         .unwrap();
         let out = model.forward(&x).unwrap();
@@ -115,4 +378,135 @@ mod tests {
         let actual_shape = out.shape();
         assert_eq!(actual_shape, expected_shape);
     }
+
+    #[cfg(all(feature = "sync", not(feature = "tape")))]
+    #[test]
+    fn test_forward_on_another_thread() {
+        fn assert_send_sync<S: Send + Sync>() {}
+        assert_send_sync::<TestTransformer>();
+
+        let config = get_config();
+        let model = std::sync::Arc::new(TestTransformer::new(&config));
+        let x = Te::from_vec(
+            &vec![config.batch_size, config.seq_len, 1],
+            &vec![El::zero(); config.batch_size * config.seq_len],
+        )
+        .unwrap();
+        let handle = {
+            let model = model.clone();
+            std::thread::spawn(move || model.forward(&x).unwrap().shape())
+        };
+        assert_eq!(handle.join().unwrap(), vec![2, 7, 12]);
+    }
+
+    /// Token ids (B, T, 1) of `batch_size` sequences of a repeating pattern, and the next token
+    /// of each position.
+    fn token_batch(config: &Config, offset: usize) -> (TensorImpl<f64>, Vec<usize>) {
+        let tokens: Vec<usize> = (0..config.batch_size * (config.seq_len + 1))
+            .map(|idx| (idx * 7 + offset) % config.vocab_size)
+            .collect();
+        let mut x = Vec::new();
+        let mut targets = Vec::new();
+        for sequence in tokens.chunks(config.seq_len + 1) {
+            x.extend(sequence[..config.seq_len].iter().map(|t| *t as f64));
+            targets.extend_from_slice(&sequence[1..]);
+        }
+        let x = TensorImpl::from_vec(&vec![config.batch_size, config.seq_len, 1], &x).unwrap();
+        (x, targets)
+    }
+
+    #[test]
+    fn test_quantized_perplexity() {
+        let config = get_config();
+        let model = F64Transformer::<LinLayer<TensorImpl<f64>, f64>>::new(&config);
+        // Untrained weights make the activations grow from block to block until the predictions
+        // are one-hot (or the softmax overflows): shrink them for a perplexity of the order of the
+        // vocabulary size.
+        let model = model
+            .try_map_linear_layers(&mut |mut layer| {
+                layer.w = layer.w * 0.1;
+                Ok::<_, AsStdError>(layer)
+            })
+            .unwrap();
+        let (x, targets) = token_batch(&config, 0);
+        let float_perplexity = perplexity(&model.forward(&x).unwrap(), &targets).unwrap();
+
+        let calibration: Vec<TensorImpl<f64>> = (1..4)
+            .map(|offset| token_batch(&config, offset).0)
+            .collect();
+        let quantized = model.quantize(&calibration).unwrap();
+        assert_eq!(
+            quantized.params().len(),
+            TestTransformer::new(&config).params().len()
+        );
+        let quantized_perplexity = perplexity(&quantized.forward(&x).unwrap(), &targets).unwrap();
+
+        let delta = (quantized_perplexity - float_perplexity) / float_perplexity;
+        println!(
+            "Perplexity: float {}, quantized {} (relative delta {})",
+            float_perplexity, quantized_perplexity, delta
+        );
+        assert!(delta.abs() < 0.01);
+    }
+
+    #[test]
+    fn test_quantize_in_evaluation_mode() {
+        let config = Config {
+            dropout: 0.5,
+            ..get_config()
+        };
+        let calibration: Vec<TensorImpl<f64>> = (1..4)
+            .map(|offset| token_batch(&config, offset).0)
+            .collect();
+        let quantized = F64Transformer::<LinLayer<TensorImpl<f64>, f64>>::new(&config)
+            .quantize(&calibration)
+            .unwrap();
+        assert!(!quantized.is_training());
+        // Same weights, without dropout: calibrated on the same ranges.
+        let without_dropout = F64Transformer::<LinLayer<TensorImpl<f64>, f64>>::new(&get_config())
+            .quantize(&calibration)
+            .unwrap();
+        let (x, _) = token_batch(&config, 0);
+        assert_eq!(
+            quantized.forward(&x).unwrap(),
+            without_dropout.forward(&x).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_perplexity() {
+        // Uniform predictions over 4 tokens
+        let probs = TensorImpl::from_vec(&vec![1, 2, 4], &vec![0.25; 8]).unwrap();
+        assert!((perplexity(&probs, &[0, 3]).unwrap() - 4.).abs() < 1e-12);
+        assert!(perplexity(&probs, &[0]).is_err());
+    }
+
+    /// Time a training step, with the element type selected by the features. Compare `Node` and
+    /// the tape-based `Var` with
+    /// `cargo test --release -p transformer [--features tape] -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_forward_backward() {
+        let config = get_config();
+        let model = TestTransformer::new(&config);
+        let data: Vec<El> = (0..config.batch_size * config.seq_len)
+            .map(|x| El::from((x % config.vocab_size) as f64))
+            .collect();
+        let x = Te::from_vec(&vec![config.batch_size, config.seq_len, 1], &data).unwrap();
+
+        let start = std::time::Instant::now();
+        let out = model.forward(&x).unwrap();
+        let forward_time = start.elapsed();
+        let mut loss = El::from(-1.0) * out.at(vec![0, 0, 0]).unwrap().clone().ln();
+        let start = std::time::Instant::now();
+        loss.backward(1.0);
+        let backward_time = start.elapsed();
+
+        println!(
+            "{}: forward {:?}, backward {:?}",
+            std::any::type_name::<El>(),
+            forward_time,
+            backward_time
+        );
+    }
 }